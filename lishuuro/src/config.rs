@@ -0,0 +1,452 @@
+use std::{collections::HashMap, env, fmt, fs, net::SocketAddr, str::FromStr};
+
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+use url::Url;
+
+/// Used when `LISHUURO_CONFIG` is not set.
+const CONFIG_FILE: &str = "lishuuro.toml";
+
+/// Server configuration loaded from TOML file.
+/// Every value can be changed with `LISHUURO_` environment variable.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: String,
+    /// Separate address for `/metrics`. Without it metrics are served
+    /// on `bind` address, only to admins.
+    pub metrics_bind: Option<String>,
+    pub storage: Storage,
+    pub mongo_uri: String,
+    pub mongo_database: String,
+    pub redis_uri: String,
+    pub lichess: LichessConfig,
+    pub urls: UrlsConfig,
+    /// Capacity for broadcast channels.
+    pub broadcast_capacity: usize,
+    pub chat: ChatConfig,
+    pub log: LogConfig,
+    pub rate_limit: RateLimitConfig,
+}
+
+/// Where players, games, articles and sessions are saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    /// MongoDB and Redis.
+    Mongo,
+    /// Process memory, used for tests and local development.
+    Memory,
+}
+
+impl FromStr for Storage {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mongo" => Ok(Storage::Mongo),
+            "memory" => Ok(Storage::Memory),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LichessConfig {
+    pub oauth_url: String,
+    pub token_url: String,
+    pub account_url: String,
+    pub client_id: String,
+}
+
+/// Server and frontend origins.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Origins {
+    pub server: String,
+    pub frontend: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UrlsConfig {
+    pub prod: Origins,
+    pub dev: Origins,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatConfig {
+    /// Longest message, in bytes.
+    pub max_length: usize,
+    /// Messages that can be sent at once.
+    pub burst: f64,
+    /// Seconds until player can send one more message.
+    pub refill_secs: f64,
+    /// Same message can't be repeated in this period.
+    pub repeat_secs: u64,
+    /// Accounts younger than this can't post links.
+    pub new_account_days: i64,
+    /// File with bad words, one per line.
+    pub words_file: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Filter like `info` or `info,lishuuro=debug`.
+    pub level: String,
+    /// One JSON object per line, otherwise plain text.
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Take client IP from `X-Forwarded-For`. Enable only behind proxy.
+    pub trust_proxy: bool,
+    /// Websocket messages per session, by message type.
+    pub messages: HashMap<String, Limit>,
+    /// Websocket messages per session for other types.
+    pub default: Limit,
+    /// All websocket messages from one IP.
+    pub ip: Limit,
+    /// HTTP requests from one IP.
+    pub http: Limit,
+    /// New anonymous accounts from one IP.
+    pub accounts: Limit,
+    /// Rejected messages before socket is closed.
+    pub strikes: u32,
+    /// Strikes are forgotten after this many seconds without new one.
+    pub strikes_reset_secs: u64,
+}
+
+/// Token bucket settings.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    /// Requests that can be sent at once.
+    pub burst: f64,
+    /// Seconds until one more request can be sent.
+    pub refill_secs: f64,
+}
+
+impl Limit {
+    pub const fn new(burst: f64, refill_secs: f64) -> Self {
+        Self { burst, refill_secs }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: String::from("127.0.0.1:8080"),
+            metrics_bind: None,
+            storage: Storage::Mongo,
+            mongo_uri: String::from("mongodb://127.0.0.1:27017"),
+            mongo_database: String::from("lishuuro"),
+            redis_uri: String::from("redis://127.0.0.1/"),
+            lichess: LichessConfig::default(),
+            urls: UrlsConfig::default(),
+            broadcast_capacity: 100,
+            chat: ChatConfig::default(),
+            log: LogConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+        }
+    }
+}
+
+impl Default for LichessConfig {
+    fn default() -> Self {
+        Self {
+            oauth_url: String::from("https://lichess.org/oauth"),
+            token_url: String::from("https://lichess.org/api/token"),
+            account_url: String::from("https://lichess.org/api/account"),
+            client_id: String::from("lishuuro"),
+        }
+    }
+}
+
+impl Default for UrlsConfig {
+    fn default() -> Self {
+        Self {
+            prod: Origins {
+                server: String::from("https://lishuuro.org/w"),
+                frontend: String::from("https://lishuuro.org"),
+            },
+            dev: Origins {
+                server: String::from("http://localhost:8080"),
+                frontend: String::from("http://localhost:3000"),
+            },
+        }
+    }
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            max_length: 50,
+            burst: 5.0,
+            refill_secs: 6.0,
+            repeat_secs: 60,
+            new_account_days: 7,
+            words_file: None,
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let messages = [
+            ("home_lobby_add", Limit::new(3.0, 5.0)),
+            ("home_lobby_accept", Limit::new(3.0, 5.0)),
+            ("live_chat_message", Limit::new(5.0, 2.0)),
+            ("live_game_play", Limit::new(10.0, 0.2)),
+            ("live_game_place", Limit::new(10.0, 0.2)),
+            ("live_game_buy", Limit::new(20.0, 0.1)),
+            ("inbox_send", Limit::new(5.0, 5.0)),
+        ];
+        Self {
+            trust_proxy: false,
+            messages: messages
+                .into_iter()
+                .map(|(t, limit)| (String::from(t), limit))
+                .collect(),
+            default: Limit::new(20.0, 0.1),
+            ip: Limit::new(100.0, 0.01),
+            http: Limit::new(60.0, 0.1),
+            accounts: Limit::new(10.0, 60.0),
+            strikes: 10,
+            strikes_reset_secs: 60,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: String::from("info"),
+            json: true,
+        }
+    }
+}
+
+/// Error found while loading config.
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    Env(&'static str, String),
+    Invalid(&'static str, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "can't read {path}: {e}"),
+            ConfigError::Parse(path, e) => write!(f, "bad TOML in {path}: {e}"),
+            ConfigError::Env(name, value) => {
+                write!(f, "bad value for {name}: {value:?}")
+            }
+            ConfigError::Invalid(field, reason) => {
+                write!(f, "invalid `{field}`: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Load config file, apply environment variables and validate.
+    /// Missing default file is not an error.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut config = match env::var("LISHUURO_CONFIG") {
+            Ok(path) => Self::from_file(&path)?,
+            Err(_) if fs::metadata(CONFIG_FILE).is_ok() => {
+                Self::from_file(CONFIG_FILE)?
+            }
+            Err(_) => Self::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| ConfigError::Read(String::from(path), e))?;
+        toml::from_str(&text)
+            .map_err(|e| ConfigError::Parse(String::from(path), e))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        set_env("LISHUURO_BIND", &mut self.bind)?;
+        set_env_opt("LISHUURO_METRICS_BIND", &mut self.metrics_bind)?;
+        set_env("LISHUURO_STORAGE", &mut self.storage)?;
+        set_env("LISHUURO_MONGO_URI", &mut self.mongo_uri)?;
+        set_env("LISHUURO_MONGO_DATABASE", &mut self.mongo_database)?;
+        set_env("LISHUURO_REDIS_URI", &mut self.redis_uri)?;
+        set_env("LISHUURO_LICHESS_OAUTH_URL", &mut self.lichess.oauth_url)?;
+        set_env("LISHUURO_LICHESS_TOKEN_URL", &mut self.lichess.token_url)?;
+        set_env(
+            "LISHUURO_LICHESS_ACCOUNT_URL",
+            &mut self.lichess.account_url,
+        )?;
+        set_env("LISHUURO_LICHESS_CLIENT_ID", &mut self.lichess.client_id)?;
+        set_env("LISHUURO_PROD_SERVER", &mut self.urls.prod.server)?;
+        set_env("LISHUURO_PROD_FRONTEND", &mut self.urls.prod.frontend)?;
+        set_env("LISHUURO_DEV_SERVER", &mut self.urls.dev.server)?;
+        set_env("LISHUURO_DEV_FRONTEND", &mut self.urls.dev.frontend)?;
+        set_env("LISHUURO_BROADCAST_CAPACITY", &mut self.broadcast_capacity)?;
+        set_env("LISHUURO_CHAT_MAX_LENGTH", &mut self.chat.max_length)?;
+        set_env("LISHUURO_CHAT_BURST", &mut self.chat.burst)?;
+        set_env("LISHUURO_CHAT_REFILL_SECS", &mut self.chat.refill_secs)?;
+        set_env("LISHUURO_CHAT_REPEAT_SECS", &mut self.chat.repeat_secs)?;
+        set_env(
+            "LISHUURO_CHAT_NEW_ACCOUNT_DAYS",
+            &mut self.chat.new_account_days,
+        )?;
+        set_env_opt("LISHUURO_CHAT_WORDS_FILE", &mut self.chat.words_file)?;
+        set_env("LISHUURO_LOG_LEVEL", &mut self.log.level)?;
+        set_env("LISHUURO_LOG_JSON", &mut self.log.json)?;
+        let limits = &mut self.rate_limit;
+        set_env("LISHUURO_RATE_LIMIT_TRUST_PROXY", &mut limits.trust_proxy)?;
+        set_env("LISHUURO_RATE_LIMIT_STRIKES", &mut limits.strikes)?;
+        set_env(
+            "LISHUURO_RATE_LIMIT_STRIKES_RESET_SECS",
+            &mut limits.strikes_reset_secs,
+        )?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.bind.parse::<SocketAddr>().is_err() {
+            return Err(invalid(
+                "bind",
+                "expected address like 127.0.0.1:8080",
+            ));
+        }
+        if let Some(addr) = &self.metrics_bind {
+            if addr.parse::<SocketAddr>().is_err() {
+                return Err(invalid(
+                    "metrics_bind",
+                    "expected address like 127.0.0.1:9090",
+                ));
+            }
+        }
+        if !self.mongo_uri.starts_with("mongodb://")
+            && !self.mongo_uri.starts_with("mongodb+srv://")
+        {
+            return Err(invalid("mongo_uri", "expected mongodb:// URI"));
+        }
+        if self.mongo_database.is_empty() {
+            return Err(invalid("mongo_database", "can't be empty"));
+        }
+        if !self.redis_uri.starts_with("redis://")
+            && !self.redis_uri.starts_with("rediss://")
+        {
+            return Err(invalid("redis_uri", "expected redis:// URI"));
+        }
+        let urls = [
+            ("lichess.oauth_url", &self.lichess.oauth_url),
+            ("lichess.token_url", &self.lichess.token_url),
+            ("lichess.account_url", &self.lichess.account_url),
+            ("urls.prod.server", &self.urls.prod.server),
+            ("urls.prod.frontend", &self.urls.prod.frontend),
+            ("urls.dev.server", &self.urls.dev.server),
+            ("urls.dev.frontend", &self.urls.dev.frontend),
+        ];
+        for (field, url) in urls {
+            if let Err(e) = Url::parse(url) {
+                return Err(invalid(field, &e.to_string()));
+            }
+        }
+        if self.lichess.client_id.is_empty() {
+            return Err(invalid("lichess.client_id", "can't be empty"));
+        }
+        if self.broadcast_capacity == 0 {
+            return Err(invalid("broadcast_capacity", "must be positive"));
+        }
+        if self.chat.max_length == 0 {
+            return Err(invalid("chat.max_length", "must be positive"));
+        }
+        if self.chat.burst < 1.0 {
+            return Err(invalid("chat.burst", "must be at least 1"));
+        }
+        if self.chat.refill_secs <= 0.0 {
+            return Err(invalid("chat.refill_secs", "must be positive"));
+        }
+        if let Err(e) = EnvFilter::try_new(&self.log.level) {
+            return Err(invalid("log.level", &e.to_string()));
+        }
+        let limits = &self.rate_limit;
+        let all = [
+            ("rate_limit.default", &limits.default),
+            ("rate_limit.ip", &limits.ip),
+            ("rate_limit.http", &limits.http),
+            ("rate_limit.accounts", &limits.accounts),
+        ];
+        let messages =
+            limits.messages.values().map(|l| ("rate_limit.messages", l));
+        for (field, limit) in all.into_iter().chain(messages) {
+            if limit.burst < 1.0 {
+                return Err(invalid(field, "burst must be at least 1"));
+            }
+            if limit.refill_secs <= 0.0 {
+                return Err(invalid(field, "refill_secs must be positive"));
+            }
+        }
+        if limits.strikes == 0 {
+            return Err(invalid("rate_limit.strikes", "must be positive"));
+        }
+        Ok(())
+    }
+
+    /// Server URL and frontend origin.
+    pub fn curr_url(&self, prod: bool) -> (&str, &str) {
+        let origins = if prod {
+            &self.urls.prod
+        } else {
+            &self.urls.dev
+        };
+        (&origins.server, &origins.frontend)
+    }
+
+    pub fn bind_addr(&self) -> SocketAddr {
+        self.bind.parse().expect("validated at startup")
+    }
+
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        let addr = self.metrics_bind.as_ref()?;
+        Some(addr.parse().expect("validated at startup"))
+    }
+}
+
+/// Replace value if environment variable is set.
+fn set_env<T: FromStr>(
+    name: &'static str,
+    value: &mut T,
+) -> Result<(), ConfigError> {
+    if let Ok(text) = env::var(name) {
+        *value = text.parse().map_err(|_| ConfigError::Env(name, text))?;
+    }
+    Ok(())
+}
+
+/// Set optional value if environment variable is set.
+fn set_env_opt<T: FromStr>(
+    name: &'static str,
+    value: &mut Option<T>,
+) -> Result<(), ConfigError> {
+    if let Ok(text) = env::var(name) {
+        let parsed = text.parse().map_err(|_| ConfigError::Env(name, text))?;
+        *value = Some(parsed);
+    }
+    Ok(())
+}
+
+fn invalid(field: &'static str, reason: &str) -> ConfigError {
+    ConfigError::Invalid(field, String::from(reason))
+}
@@ -0,0 +1,617 @@
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    sync::Mutex,
+};
+
+use async_session::async_trait;
+use bson::DateTime;
+
+use crate::{
+    lichess::login::{player_ids, public_game_id, random_username},
+    notifications::ALL_USERS,
+    websockets::{leaderboards::LEADERBOARD_SIZE, rooms::ChatMsg, GameGet},
+};
+
+use super::{
+    mongo::{
+        Article, ChatReport, Conversation, DirectMessage, FilteredMsg,
+        Notification, NotificationRead, Player, ProfileGame, ShuuroGame,
+    },
+    ratings::{Rating, PROVISIONAL_DEVIATION},
+    redis::UserSession,
+    repos::{
+        ArticleRepo, ChatRepo, GameRepo, InboxRepo, NotificationRepo,
+        PlayerRepo, SessionStore,
+    },
+    StorageError, ID_RETRIES,
+};
+
+// IN-MEMORY STORAGE
+// Used for running server without Mongo and Redis. Nothing is saved after exit.
+
+pub struct MemoryPlayers {
+    all: Mutex<HashMap<String, Player>>,
+    usernames: fn() -> String,
+}
+
+impl Default for MemoryPlayers {
+    fn default() -> Self {
+        Self::with_usernames(random_username)
+    }
+}
+
+impl MemoryPlayers {
+    /// Storage with custom generator for anonymous usernames.
+    pub fn with_usernames(usernames: fn() -> String) -> Self {
+        Self {
+            all: Mutex::new(HashMap::new()),
+            usernames,
+        }
+    }
+}
+
+#[async_trait]
+impl PlayerRepo for MemoryPlayers {
+    async fn create_player(&self) -> Result<String, StorageError> {
+        let mut all = self.all.lock().unwrap();
+        for _ in 0..ID_RETRIES {
+            let username = (self.usernames)();
+            if all.contains_key(&username) {
+                continue;
+            }
+            let player = Player {
+                _id: String::from(&username),
+                reg: false,
+                created_at: DateTime::now(),
+                muted_until: None,
+                blocked: vec![],
+                following: vec![],
+                ratings: HashMap::new(),
+            };
+            all.insert(String::from(&username), player);
+            return Ok(username);
+        }
+        Err(StorageError::IdsExhausted)
+    }
+
+    async fn player_exist(
+        &self,
+        username: &str,
+        session: &UserSession,
+    ) -> Option<UserSession> {
+        let mut session = session.clone();
+        session.is_new = true;
+        session.new_username(username);
+        session.new_register();
+        let mut all = self.all.lock().unwrap();
+        if !all.contains_key(username) {
+            all.insert(String::from(username), Player::from(&session));
+        }
+        Some(session)
+    }
+
+    async fn get_player(&self, username: &str) -> Option<Player> {
+        self.all.lock().unwrap().get(username).cloned()
+    }
+
+    async fn set_rating(&self, username: &str, key: &str, rating: &Rating) {
+        if let Some(player) = self.all.lock().unwrap().get_mut(username) {
+            player.ratings.insert(String::from(key), *rating);
+        }
+    }
+
+    async fn get_leaderboard(&self, key: &str, since: DateTime) -> Vec<Player> {
+        let all = self.all.lock().unwrap();
+        let mut players: Vec<Player> = all
+            .values()
+            .filter(|p| {
+                p.reg
+                    && p.ratings.get(key).is_some_and(|r| {
+                        r.deviation <= PROVISIONAL_DEVIATION
+                            && r.last_played.is_some_and(|l| l >= since)
+                    })
+            })
+            .cloned()
+            .collect();
+        players.sort_by(|a, b| {
+            b.ratings[key].rating.total_cmp(&a.ratings[key].rating)
+        });
+        players.truncate(LEADERBOARD_SIZE);
+        players
+    }
+
+    async fn set_mute(&self, username: &str, until: Option<DateTime>) -> bool {
+        if let Some(player) = self.all.lock().unwrap().get_mut(username) {
+            player.muted_until = until;
+            return true;
+        }
+        false
+    }
+
+    async fn set_blocked(
+        &self,
+        username: &str,
+        other: &str,
+        block: bool,
+    ) -> bool {
+        if let Some(player) = self.all.lock().unwrap().get_mut(username) {
+            set_contains(&mut player.blocked, other, block);
+            return true;
+        }
+        false
+    }
+
+    async fn set_following(
+        &self,
+        username: &str,
+        other: &str,
+        follow: bool,
+    ) -> bool {
+        if let Some(player) = self.all.lock().unwrap().get_mut(username) {
+            set_contains(&mut player.following, other, follow);
+            return true;
+        }
+        false
+    }
+
+    async fn followers(&self, username: &str) -> Vec<String> {
+        let all = self.all.lock().unwrap();
+        all.values()
+            .filter(|p| p.following.iter().any(|f| f == username))
+            .map(|p| String::from(&p._id))
+            .collect()
+    }
+}
+
+/// Add or remove name from list, same as `$addToSet` and `$pull`.
+fn set_contains(list: &mut Vec<String>, name: &str, add: bool) {
+    if !add {
+        list.retain(|n| n != name);
+    } else if !list.iter().any(|n| n == name) {
+        list.push(String::from(name));
+    }
+}
+
+pub struct MemoryGames {
+    all: Mutex<HashMap<String, ShuuroGame>>,
+    ids: fn() -> String,
+}
+
+impl Default for MemoryGames {
+    fn default() -> Self {
+        Self::with_ids(public_game_id)
+    }
+}
+
+impl MemoryGames {
+    /// Storage with custom generator for public game IDs.
+    pub fn with_ids(ids: fn() -> String) -> Self {
+        Self {
+            all: Mutex::new(HashMap::new()),
+            ids,
+        }
+    }
+}
+
+#[async_trait]
+impl GameRepo for MemoryGames {
+    async fn get_game(&self, id: &str) -> Option<ShuuroGame> {
+        self.all.lock().unwrap().get(id).cloned()
+    }
+
+    async fn add_game(
+        &self,
+        game: &mut ShuuroGame,
+    ) -> Result<(), StorageError> {
+        let mut all = self.all.lock().unwrap();
+        for _ in 0..ID_RETRIES {
+            if let Entry::Vacant(entry) = all.entry((self.ids)()) {
+                game._id = String::from(entry.key());
+                game.player_ids = player_ids();
+                entry.insert(game.clone());
+                return Ok(());
+            }
+        }
+        Err(StorageError::IdsExhausted)
+    }
+
+    async fn player_game_id(&self, id: &str) -> Option<String> {
+        let all = self.all.lock().unwrap();
+        all.values()
+            .find(|g| g.player_ids.iter().any(|p| p == id))
+            .map(|g| String::from(&g._id))
+    }
+
+    async fn update_game(&self, game: &ShuuroGame) {
+        if let Some(old) = self.all.lock().unwrap().get_mut(&game._id) {
+            *old = game.clone();
+        }
+    }
+
+    async fn insert_move(&self, json: &GameGet) {
+        if let Some(game) = self.all.lock().unwrap().get_mut(&json.game_id) {
+            let history = {
+                if json.game_move.contains('@') {
+                    &mut game.history.1
+                } else {
+                    &mut game.history.2
+                }
+            };
+            history.push(String::from(&json.game_move));
+        }
+    }
+
+    async fn player_games(
+        &self,
+        username: &str,
+        page: u64,
+    ) -> Option<Vec<ProfileGame>> {
+        let all = self.all.lock().unwrap();
+        let mut games: Vec<&ShuuroGame> = all
+            .values()
+            .filter(|g| g.players.iter().any(|p| p == username))
+            .collect();
+        games.sort_by_key(|g| Reverse(g.last_clock));
+        let games = games
+            .into_iter()
+            .skip(page as usize * 5)
+            .take(5)
+            .filter_map(|g| bson::from_bson(bson::to_bson(g).ok()?).ok())
+            .collect();
+        Some(games)
+    }
+
+    async fn unfinished(&self) -> HashMap<String, ShuuroGame> {
+        let all = self.all.lock().unwrap();
+        all.iter()
+            .filter(|(_, g)| g.status < 0)
+            .map(|(id, g)| (String::from(id), g.clone()))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryArticles {
+    all: Mutex<HashMap<String, Article>>,
+}
+
+#[async_trait]
+impl ArticleRepo for MemoryArticles {
+    async fn get_article(&self, id: &str) -> Option<Article> {
+        self.all.lock().unwrap().get(id).cloned()
+    }
+
+    async fn get_articles(
+        &self,
+        category: Option<&String>,
+        page: u64,
+        drafts: bool,
+    ) -> Option<Vec<Article>> {
+        let all = self.all.lock().unwrap();
+        let mut articles: Vec<Article> = all
+            .values()
+            .filter(|a| category.is_none_or(|c| &a.category == c))
+            .filter(|a| drafts || !a.draft)
+            .cloned()
+            .collect();
+        articles.sort_by(|a, b| b.date.cmp(&a.date));
+        let articles = articles
+            .into_iter()
+            .skip(page as usize * 10)
+            .take(10)
+            .map(|mut a| {
+                a.text = String::from("");
+                a.html = String::from("");
+                a
+            })
+            .collect();
+        Some(articles)
+    }
+
+    async fn add_article(&self, article: &Article) -> bool {
+        let mut all = self.all.lock().unwrap();
+        if all.contains_key(&article._id) {
+            return false;
+        }
+        all.insert(String::from(&article._id), article.clone());
+        true
+    }
+
+    async fn update_article(&self, article: &Article) -> bool {
+        if let Some(old) = self.all.lock().unwrap().get_mut(&article._id) {
+            old.title = String::from(&article.title);
+            old.category = String::from(&article.category);
+            old.text = String::from(&article.text);
+            old.html = String::from(&article.html);
+            old.headline = String::from(&article.headline);
+            return true;
+        }
+        false
+    }
+
+    async fn set_article_draft(&self, id: &str, draft: bool) -> bool {
+        if let Some(article) = self.all.lock().unwrap().get_mut(id) {
+            article.draft = draft;
+            return true;
+        }
+        false
+    }
+
+    async fn delete_article(&self, id: &str) -> bool {
+        self.all.lock().unwrap().remove(id).is_some()
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryChat {
+    chats: Mutex<HashMap<String, Vec<ChatMsg>>>,
+    filtered: Mutex<Vec<FilteredMsg>>,
+    reports: Mutex<Vec<ChatReport>>,
+}
+
+#[async_trait]
+impl ChatRepo for MemoryChat {
+    async fn add_line(&self, id: &str, m: &ChatMsg) {
+        let mut chats = self.chats.lock().unwrap();
+        chats.entry(String::from(id)).or_default().push(m.clone());
+    }
+
+    async fn delete_line(&self, id: &str, msg_id: u64) {
+        if let Some(lines) = self.chats.lock().unwrap().get_mut(id) {
+            lines.retain(|m| m.msg_id != msg_id);
+        }
+    }
+
+    async fn game_chat(&self, id: &str) -> Option<Vec<ChatMsg>> {
+        self.chats.lock().unwrap().get(id).cloned()
+    }
+
+    async fn unfinished_chats(
+        &self,
+        ids: Vec<&String>,
+    ) -> HashMap<String, Vec<ChatMsg>> {
+        let chats = self.chats.lock().unwrap();
+        ids.into_iter()
+            .filter_map(|id| Some((String::from(id), chats.get(id)?.clone())))
+            .collect()
+    }
+
+    async fn add_filtered(&self, msg: &FilteredMsg) {
+        self.filtered.lock().unwrap().push(msg.clone());
+    }
+
+    async fn get_filtered(&self) -> Vec<FilteredMsg> {
+        let filtered = self.filtered.lock().unwrap();
+        filtered.iter().rev().take(50).cloned().collect()
+    }
+
+    async fn add_report(&self, report: &ChatReport) -> bool {
+        self.reports.lock().unwrap().push(report.clone());
+        true
+    }
+
+    async fn get_reports(&self) -> Vec<ChatReport> {
+        let reports = self.reports.lock().unwrap();
+        reports
+            .iter()
+            .filter(|r| !r.resolved)
+            .take(50)
+            .cloned()
+            .collect()
+    }
+
+    async fn resolve_report(&self, id: &str) -> bool {
+        let mut reports = self.reports.lock().unwrap();
+        if let Some(report) = reports.iter_mut().find(|r| r._id == id) {
+            report.resolved = true;
+            return true;
+        }
+        false
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryInbox {
+    conversations: Mutex<HashMap<String, Conversation>>,
+    messages: Mutex<Vec<DirectMessage>>,
+}
+
+#[async_trait]
+impl InboxRepo for MemoryInbox {
+    async fn add_message(&self, m: &DirectMessage) -> bool {
+        self.messages.lock().unwrap().push(m.clone());
+        let mut players = [String::from(&m.user), String::from(&m.to)];
+        players.sort();
+        let mut conversations = self.conversations.lock().unwrap();
+        let c = conversations
+            .entry(String::from(&m.conversation))
+            .or_insert_with(|| Conversation {
+                _id: String::from(&m.conversation),
+                players,
+                updated_at: m.time,
+                last: String::from(""),
+                unread: HashMap::new(),
+            });
+        c.updated_at = m.time;
+        c.last = String::from(&m.message);
+        *c.unread.entry(String::from(&m.to)).or_default() += 1;
+        true
+    }
+
+    async fn conversations(&self, username: &str) -> Vec<Conversation> {
+        let conversations = self.conversations.lock().unwrap();
+        let mut all: Vec<Conversation> = conversations
+            .values()
+            .filter(|c| c.players.iter().any(|p| p == username))
+            .cloned()
+            .collect();
+        all.sort_by_key(|c| Reverse(c.updated_at));
+        all.truncate(50);
+        all
+    }
+
+    async fn messages(
+        &self,
+        conversation: &str,
+        page: u64,
+    ) -> Vec<DirectMessage> {
+        let messages = self.messages.lock().unwrap();
+        messages
+            .iter()
+            .rev()
+            .filter(|m| m.conversation == conversation)
+            .skip(page as usize * 50)
+            .take(50)
+            .cloned()
+            .collect()
+    }
+
+    async fn read_conversation(&self, conversation: &str, username: &str) {
+        let mut conversations = self.conversations.lock().unwrap();
+        if let Some(c) = conversations.get_mut(conversation) {
+            c.unread.insert(String::from(username), 0);
+        }
+    }
+
+    async fn unread_count(&self, username: &str) -> u32 {
+        let conversations = self.conversations.lock().unwrap();
+        conversations
+            .values()
+            .filter_map(|c| c.unread.get(username))
+            .sum()
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryNotifications {
+    all: Mutex<Vec<Notification>>,
+    /// IDs of `NotificationRead` for notifications sent to everyone.
+    reads: Mutex<HashSet<String>>,
+}
+
+impl MemoryNotifications {
+    fn is_read(&self, n: &Notification, username: &str) -> bool {
+        let id = NotificationRead::new(&n._id, username)._id;
+        n.read || self.reads.lock().unwrap().contains(&id)
+    }
+}
+
+#[async_trait]
+impl NotificationRepo for MemoryNotifications {
+    async fn add_notification(&self, n: &Notification) -> bool {
+        self.all.lock().unwrap().push(n.clone());
+        true
+    }
+
+    async fn upsert_game_notification(
+        &self,
+        n: &Notification,
+    ) -> Option<Notification> {
+        let game_id = n.data.get("game_id")?.as_str()?;
+        let mut all = self.all.lock().unwrap();
+        let old = all.iter_mut().find(|old| {
+            old.user == n.user
+                && old.kind == n.kind
+                && !old.read
+                && old.data["game_id"] == game_id
+        });
+        match old {
+            Some(old) => {
+                old.data = n.data.clone();
+                old.created_at = n.created_at;
+                Some(old.clone())
+            }
+            None => {
+                all.push(n.clone());
+                Some(n.clone())
+            }
+        }
+    }
+
+    async fn get_notifications(
+        &self,
+        username: &str,
+        page: u64,
+    ) -> Vec<(Notification, bool)> {
+        let mut all: Vec<Notification> = self
+            .all
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|n| n.user == username || n.user == ALL_USERS)
+            .cloned()
+            .collect();
+        all.sort_by_key(|n| Reverse(n.created_at));
+        all.into_iter()
+            .skip(page as usize * 20)
+            .take(20)
+            .map(|n| {
+                let is_read = self.is_read(&n, username);
+                (n, is_read)
+            })
+            .collect()
+    }
+
+    async fn read_notifications(
+        &self,
+        username: &str,
+        id: Option<&str>,
+    ) -> bool {
+        let mut all = self.all.lock().unwrap();
+        let mut reads = self.reads.lock().unwrap();
+        let mut changed = false;
+        for n in all.iter_mut().filter(|n| id.is_none_or(|id| n._id == id)) {
+            if n.user == ALL_USERS {
+                reads.insert(NotificationRead::new(&n._id, username)._id);
+                changed = true;
+            } else if n.user == username && !n.read {
+                n.read = true;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    async fn unread_notifications(&self, username: &str) -> u64 {
+        let all = self.all.lock().unwrap();
+        let unread = all
+            .iter()
+            .filter(|n| n.user == username || n.user == ALL_USERS)
+            .filter(|n| !self.is_read(n, username))
+            .count();
+        unread as u64
+    }
+}
+
+/// Sessions are saved as JSON, same as in Redis. They never expire.
+#[derive(Default)]
+pub struct MemorySessions {
+    all: Mutex<HashMap<String, String>>,
+}
+
+#[async_trait]
+impl SessionStore for MemorySessions {
+    async fn get_session(&self, key: &str) -> Option<UserSession> {
+        let value = self.all.lock().unwrap().get(key).cloned()?;
+        let value = serde_json::from_str::<UserSession>(&value).ok()?;
+        Some(self.set_session(key, value, false).await)
+    }
+
+    async fn set_session(
+        &self,
+        key: &str,
+        mut value: UserSession,
+        force_set: bool,
+    ) -> UserSession {
+        if value.is_new || force_set {
+            if !force_set {
+                value.not_new();
+            }
+            if let Ok(json) = serde_json::to_string(&value) {
+                self.all.lock().unwrap().insert(String::from(key), json);
+            }
+        }
+        value
+    }
+}
@@ -1,25 +1,107 @@
-use crate::lichess::MyKey;
+use std::{fmt, sync::Arc};
 
-use self::{mongo::Mongo, redis::RedisCli};
+use tracing::warn;
 
+use crate::{
+    config::{Config, Storage},
+    lichess::MyKey,
+};
+
+use self::{
+    memory::{
+        MemoryArticles, MemoryChat, MemoryGames, MemoryInbox,
+        MemoryNotifications, MemoryPlayers, MemorySessions,
+    },
+    mongo::Mongo,
+    redis::RedisCli,
+    repos::{
+        ArticleRepo, ChatRepo, GameRepo, InboxRepo, NotificationRepo,
+        PlayerRepo, SessionStore,
+    },
+};
+
+pub mod memory;
 pub mod mongo;
 pub mod queries;
+pub mod ratings;
 pub mod redis;
+pub mod repos;
 pub mod serde_helpers;
 
+/// Attempts to find unused random ID before giving up.
+pub const ID_RETRIES: usize = 5;
+
+/// Error when new player or game can't be stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    /// Every random ID was already taken.
+    IdsExhausted,
+    /// Database returned other error.
+    Backend(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IdsExhausted => write!(f, "no unused id after retries"),
+            Self::Backend(e) => write!(f, "database error: {e}"),
+        }
+    }
+}
+
 /// Struct containing all databases.
 pub struct Database {
-    pub redis: RedisCli,
-    pub mongo: Mongo,
+    pub players: Arc<dyn PlayerRepo>,
+    pub games: Arc<dyn GameRepo>,
+    pub articles: Arc<dyn ArticleRepo>,
+    pub sessions: Arc<dyn SessionStore>,
+    pub chat: Arc<dyn ChatRepo>,
+    pub inbox: Arc<dyn InboxRepo>,
+    pub notifications: Arc<dyn NotificationRepo>,
+    /// Collections for puzzles, opening explorer, game search and profile
+    /// stats. Missing for in-memory storage, so these features are disabled.
+    pub mongo: Option<Mongo>,
     pub key: MyKey,
 }
 
 impl Database {
     /// Create databases.
-    pub async fn new() -> Self {
-        let redis = RedisCli::default().await;
-        let mongo = Mongo::new().await;
+    pub async fn new(config: &Config) -> Self {
         let key = MyKey::default();
-        Self { redis, mongo, key }
+        match config.storage {
+            Storage::Mongo => {
+                let redis = RedisCli::new(&config.redis_uri).await;
+                let mongo =
+                    Mongo::new(&config.mongo_uri, &config.mongo_database).await;
+                Self {
+                    players: Arc::new(mongo.players.clone()),
+                    games: Arc::new(mongo.games.clone()),
+                    articles: Arc::new(mongo.articles.clone()),
+                    sessions: Arc::new(redis),
+                    chat: Arc::new(mongo.clone()),
+                    inbox: Arc::new(mongo.clone()),
+                    notifications: Arc::new(mongo.clone()),
+                    mongo: Some(mongo),
+                    key,
+                }
+            }
+            Storage::Memory => {
+                warn!(
+                    "puzzles, explorer, game search and profile stats \
+                     are disabled with in-memory storage"
+                );
+                Self {
+                    players: Arc::new(MemoryPlayers::default()),
+                    games: Arc::new(MemoryGames::default()),
+                    articles: Arc::new(MemoryArticles::default()),
+                    sessions: Arc::new(MemorySessions::default()),
+                    chat: Arc::new(MemoryChat::default()),
+                    inbox: Arc::new(MemoryInbox::default()),
+                    notifications: Arc::new(MemoryNotifications::default()),
+                    mongo: None,
+                    key,
+                }
+            }
+        }
     }
 }
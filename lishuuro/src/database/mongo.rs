@@ -1,11 +1,17 @@
+use std::collections::HashMap;
+
 use async_session::chrono::Duration;
-use bson::DateTime;
-use mongodb::{options::ClientOptions, Client, Collection};
+use bson::{doc, DateTime};
+use mongodb::{options::ClientOptions, Client, Collection, IndexModel};
 use serde::{Deserialize, Serialize};
 use shuuro::SubVariant;
+use tracing::error;
 
-use crate::websockets::{time_control::TimeControl, GameRequest};
+use crate::websockets::{
+    rooms::ChatMsg, time_control::TimeControl, GameRequest,
+};
 
+use super::ratings::Rating;
 use super::serde_helpers::{
     array_i32_duration, duration_i32, duration_i32_array, i32_duration,
 };
@@ -20,26 +26,74 @@ pub struct Mongo {
     pub players: Collection<Player>,
     pub articles: Collection<Article>,
     pub games: Collection<ShuuroGame>,
+    pub reports: Collection<ChatReport>,
+    pub filtered: Collection<FilteredMsg>,
+    pub chat: Collection<GameChat>,
+    pub conversations: Collection<Conversation>,
+    pub messages: Collection<DirectMessage>,
+    pub notifications: Collection<Notification>,
+    pub notification_reads: Collection<NotificationRead>,
+    pub profiles: Collection<ProfileStats>,
+    pub puzzles: Collection<Puzzle>,
+    pub puzzle_attempts: Collection<PuzzleAttempt>,
 }
 
 impl Mongo {
     /// Create mongodb connection for all collections.
-    pub async fn new() -> Self {
-        let mut client_options =
-            ClientOptions::parse("mongodb://127.0.0.1:27017")
-                .await
-                .expect("No client available");
+    pub async fn new(uri: &str, database: &str) -> Self {
+        let mut client_options = ClientOptions::parse(uri)
+            .await
+            .expect("No client available");
         client_options.app_name = Some("lishuuro".to_string());
         let client =
             Client::with_options(client_options).expect("client not found");
-        let db = client.database("lishuuro");
+        let db = client.database(database);
         let players = db.collection::<Player>("users");
         let games = db.collection::<ShuuroGame>("shuuroGames");
         let articles = db.collection::<Article>("news");
+        let reports = db.collection::<ChatReport>("chatReports");
+        let filtered = db.collection::<FilteredMsg>("filteredChat");
+        let chat = db.collection::<GameChat>("chat");
+        let conversations = db.collection::<Conversation>("conversations");
+        let messages = db.collection::<DirectMessage>("messages");
+        let notifications = db.collection::<Notification>("notifications");
+        let notification_reads =
+            db.collection::<NotificationRead>("notificationReads");
+        let profiles = db.collection::<ProfileStats>("profileStats");
+        let puzzles = db.collection::<Puzzle>("puzzles");
+        let puzzle_attempts = db.collection::<PuzzleAttempt>("puzzleAttempts");
+        Self::create_indexes(&games).await;
         Mongo {
             players,
             games,
             articles,
+            reports,
+            filtered,
+            chat,
+            conversations,
+            messages,
+            notifications,
+            notification_reads,
+            profiles,
+            puzzles,
+            puzzle_attempts,
+        }
+    }
+
+    /// Indexes used by game search and private player links.
+    async fn create_indexes(games: &Collection<ShuuroGame>) {
+        let indexes = [
+            doc! {"last_clock": -1, "_id": -1},
+            doc! {"players": 1, "last_clock": -1, "_id": -1},
+            doc! {"variant": 1, "last_clock": -1, "_id": -1},
+            doc! {"status": 1, "last_clock": -1, "_id": -1},
+            doc! {"player_ids": 1},
+        ];
+        let indexes = indexes
+            .into_iter()
+            .map(|keys| IndexModel::builder().keys(keys).build());
+        if let Err(e) = games.create_indexes(indexes, None).await {
+            error!(error = %e, "can't create game indexes");
         }
     }
 }
@@ -52,6 +106,14 @@ pub struct Player {
     pub _id: String,
     pub reg: bool,
     pub created_at: DateTime,
+    #[serde(default)]
+    pub muted_until: Option<DateTime>,
+    #[serde(default)]
+    pub blocked: Vec<String>,
+    #[serde(default)]
+    pub following: Vec<String>,
+    #[serde(default)]
+    pub ratings: HashMap<String, Rating>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -62,8 +124,153 @@ pub struct Article {
     pub user: String,
     pub date: String,
     pub category: String,
+    /// Missing when only list of articles is loaded.
+    #[serde(default)]
     pub text: String,
     pub headline: String,
+    #[serde(default)]
+    pub html: String,
+    #[serde(default)]
+    pub draft: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Chat message reported by player, with messages around it.
+pub struct ChatReport {
+    pub _id: String,
+    pub reporter: String,
+    pub reported: String,
+    pub room: String,
+    pub msg_id: u64,
+    pub reason: String,
+    pub context: Vec<ChatMsg>,
+    pub created_at: DateTime,
+    pub resolved: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Chat message hidden by filter, saved for moderators.
+pub struct FilteredMsg {
+    pub _id: String,
+    pub user: String,
+    pub room: String,
+    pub message: String,
+    pub reason: String,
+    pub created_at: DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// All chat lines for one game.
+pub struct GameChat {
+    pub _id: String,
+    pub lines: Vec<ChatMsg>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Private conversation between two registered players.
+pub struct Conversation {
+    pub _id: String,
+    pub players: [String; 2],
+    pub updated_at: DateTime,
+    pub last: String,
+    #[serde(default)]
+    pub unread: HashMap<String, u32>,
+}
+
+impl Conversation {
+    /// ID is same for both players.
+    pub fn id(first: &str, second: &str) -> String {
+        if first < second {
+            format!("{first}|{second}")
+        } else {
+            format!("{second}|{first}")
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// One message in private conversation.
+pub struct DirectMessage {
+    pub _id: String,
+    pub conversation: String,
+    pub user: String,
+    pub to: String,
+    pub message: String,
+    pub time: DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    Challenge,
+    MoveMade,
+    ArticlePublished,
+    ModWarning,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Notification for one user, or for everyone if user is `ALL_USERS`.
+pub struct Notification {
+    pub _id: String,
+    pub user: String,
+    pub kind: NotificationKind,
+    pub data: serde_json::Value,
+    /// Only used for notification of one user.
+    #[serde(default)]
+    pub read: bool,
+    pub created_at: DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Notification for everyone that was read by one user.
+pub struct NotificationRead {
+    /// Notification ID and username.
+    pub _id: String,
+    pub user: String,
+    pub notification: String,
+}
+
+impl NotificationRead {
+    pub fn new(notification: &str, user: &str) -> Self {
+        Self {
+            _id: format!("{notification}:{user}"),
+            user: String::from(user),
+            notification: String::from(notification),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Cached statistics for player profile, removed after every game.
+pub struct ProfileStats {
+    pub _id: String,
+    pub variants: Vec<serde_json::Value>,
+    pub avg_length: f64,
+    pub purchases: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Position from finished game with only one winning line.
+pub struct Puzzle {
+    pub _id: String,
+    pub game_id: String,
+    pub variant: String,
+    pub sfen: String,
+    /// Moves in same format as fight moves, first move is from player.
+    pub solution: Vec<String>,
+    pub rating: Rating,
+    #[serde(default)]
+    pub plays: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// First attempt of player for one puzzle.
+pub struct PuzzleAttempt {
+    pub _id: String,
+    pub user: String,
+    pub puzzle: String,
+    pub success: bool,
+    pub time: DateTime,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -96,6 +303,17 @@ pub struct ShuuroGame {
     #[serde(serialize_with = "serialize_subvariant")]
     #[serde(deserialize_with = "deserialize_subvariant")]
     pub sub_variant: Option<SubVariant>,
+    #[serde(default)]
+    pub rated: bool,
+    /// Ratings for both players when rated game started.
+    #[serde(default)]
+    pub ratings: Option<[i32; 2]>,
+    /// Custom starting position, these games are never rated.
+    #[serde(default)]
+    pub start_sfen: Option<String>,
+    /// Private IDs for player URLs. Never sent to spectators.
+    #[serde(default)]
+    pub player_ids: [String; 2],
 }
 
 impl From<(&GameRequest, &[String; 2], &str)> for ShuuroGame {
@@ -109,17 +327,21 @@ impl From<(&GameRequest, &[String; 2], &str)> for ShuuroGame {
             side_to_move: 0,
             clocks: [clock, clock],
             last_clock: DateTime::now(),
-            current_stage: 0,
+            current_stage: if f.0.sfen.is_some() { f.0.stage } else { 0 },
             result: String::from(""),
             status: -2,
             variant: String::from(&f.0.variant),
-            credits: [800, 800],
+            credits: f.0.color_credits(f.1),
             hands: [String::from(""), String::from("")],
             sfen: String::from(""),
             history: (vec![], vec![], vec![]),
             tc: TimeControl::new(f.0.time, f.0.incr),
             draws: [false, false],
-            sub_variant: f.0.sub_variant,
+            sub_variant: f.0.sub_variant.filter(|_| f.0.sfen.is_none()),
+            rated: false,
+            ratings: None,
+            start_sfen: f.0.sfen.clone(),
+            player_ids: [String::new(), String::new()],
         }
     }
 }
@@ -145,4 +367,8 @@ pub struct ProfileGame {
     #[serde(serialize_with = "serialize_subvariant")]
     #[serde(deserialize_with = "deserialize_subvariant")]
     pub sub_variant: Option<SubVariant>,
+    #[serde(default)]
+    pub rated: bool,
+    #[serde(default)]
+    pub start_sfen: Option<String>,
 }
@@ -1,36 +1,88 @@
 use std::collections::HashMap;
 
 use futures::TryStreamExt;
-use mongodb::{options::FindOptions, Collection};
+use mongodb::{
+    error::{Error, ErrorKind, WriteError, WriteFailure},
+    options::{
+        FindOneAndUpdateOptions, FindOneOptions, FindOptions,
+        InsertManyOptions, ReplaceOptions, ReturnDocument, UpdateOptions,
+    },
+    Collection,
+};
 use serde_json::Value;
+use tracing::error;
 
 use crate::{
-    lichess::login::{random_game_id, random_username},
-    websockets::{server_messages::live_game_start, GameGet},
+    lichess::login::{player_ids, public_game_id, random_username},
+    notifications::ALL_USERS,
+    websockets::{leaderboards::LEADERBOARD_SIZE, rooms::ChatMsg, GameGet},
 };
 
 use super::{
-    mongo::{Article, Player, ProfileGame, ShuuroGame},
+    mongo::{
+        Article, ChatReport, Conversation, DirectMessage, FilteredMsg,
+        GameChat, Notification, NotificationRead, Player, ProfileGame,
+        ProfileStats, Puzzle, PuzzleAttempt, ShuuroGame,
+    },
+    ratings::{Rating, PROVISIONAL_DEVIATION},
     redis::UserSession,
+    StorageError, ID_RETRIES,
 };
 
-use bson::doc;
+use bson::{doc, Bson, Document};
+
+/// Number of first purchases counted as opening.
+const OPENING_PURCHASES: i32 = 3;
+
+/// Mongo error code for duplicate key.
+const DUPLICATE_KEY: i32 = 11000;
+
+/// Insert failed because `_id` is already used.
+fn is_duplicate(e: &Error) -> bool {
+    matches!(
+        e.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(WriteError {
+            code: DUPLICATE_KEY,
+            ..
+        }))
+    )
+}
+
+/// Every failed insert in batch was duplicate.
+fn is_duplicate_batch(e: &Error) -> bool {
+    match e.kind.as_ref() {
+        ErrorKind::BulkWrite(failure) => {
+            failure.write_concern_error.is_none()
+                && failure.write_errors.as_ref().is_some_and(|errors| {
+                    errors.iter().all(|e| e.code == DUPLICATE_KEY)
+                })
+        }
+        _ => false,
+    }
+}
 
 /// Create new player.
-pub async fn create_player(db: &Collection<Player>) -> String {
-    loop {
+pub async fn create_player(
+    db: &Collection<Player>,
+) -> Result<String, StorageError> {
+    for _ in 0..ID_RETRIES {
         let username = random_username();
         let player = Player {
             _id: String::from(&username),
             reg: false,
             created_at: bson::DateTime::now(),
+            muted_until: None,
+            blocked: vec![],
+            following: vec![],
+            ratings: HashMap::new(),
         };
-        let res = db.insert_one(&player, None).await;
-        // Player is added, therefore it's new.
-        if res.is_ok() {
-            return (username).to_string();
+        match db.insert_one(&player, None).await {
+            Ok(_) => return Ok(username),
+            Err(e) if is_duplicate(&e) => continue,
+            Err(e) => return Err(StorageError::Backend(e.to_string())),
         }
     }
+    Err(StorageError::IdsExhausted)
 }
 
 /// Check if player(with lichess account) exist
@@ -60,21 +112,441 @@ pub async fn player_exist(
     None
 }
 
-/// Check if game ID exist.
-pub async fn game_exist(db: &Collection<ShuuroGame>) -> String {
-    loop {
-        let id = random_game_id();
-        if (get_game_db(db, &id).await).is_some() {
-            continue;
+/// Mute player until selected time. If time is `None` mute is removed.
+pub async fn set_mute(
+    db: &Collection<Player>,
+    username: &str,
+    until: Option<bson::DateTime>,
+) -> bool {
+    let query = doc! {"_id": username};
+    let update = doc! {"$set": {"muted_until": until}};
+    if let Ok(res) = db.update_one(query, update, None).await {
+        return res.matched_count == 1;
+    }
+    false
+}
+
+/// Get player if exist.
+pub async fn get_player(
+    db: &Collection<Player>,
+    username: &str,
+) -> Option<Player> {
+    if let Ok(player) = db.find_one(doc! {"_id": username}, None).await {
+        return player;
+    }
+    None
+}
+
+/// Add new chat report.
+pub async fn add_report(
+    db: &Collection<ChatReport>,
+    report: &ChatReport,
+) -> bool {
+    db.insert_one(report, None).await.is_ok()
+}
+
+/// Get all unresolved reports, oldest first.
+pub async fn get_reports(db: &Collection<ChatReport>) -> Vec<ChatReport> {
+    let options = FindOptions::builder()
+        .sort(doc! {"created_at": 1})
+        .limit(Some(50))
+        .build();
+    let filter = doc! {"resolved": false};
+    if let Ok(res) = db.find(filter, options).await {
+        return res.try_collect().await.unwrap_or_else(|_| vec![]);
+    }
+    vec![]
+}
+
+/// Mark report as resolved.
+pub async fn resolve_report(db: &Collection<ChatReport>, id: &str) -> bool {
+    let query = doc! {"_id": id};
+    let update = doc! {"$set": {"resolved": true}};
+    if let Ok(res) = db.update_one(query, update, None).await {
+        return res.matched_count == 1;
+    }
+    false
+}
+
+/// Save message hidden by chat filter.
+pub async fn add_filtered(db: &Collection<FilteredMsg>, msg: &FilteredMsg) {
+    if let Err(e) = db.insert_one(msg, None).await {
+        error!(user = %msg.user, error = %e, "can't save filtered message");
+    }
+}
+
+/// Get last 50 filtered messages.
+pub async fn get_filtered(db: &Collection<FilteredMsg>) -> Vec<FilteredMsg> {
+    let options = FindOptions::builder()
+        .sort(doc! {"created_at": -1})
+        .limit(Some(50))
+        .build();
+    if let Ok(res) = db.find(doc! {}, options).await {
+        return res.try_collect().await.unwrap_or_else(|_| vec![]);
+    }
+    vec![]
+}
+
+/// Save new chat line for game.
+pub async fn add_chat_line(db: &Collection<GameChat>, id: &str, m: &ChatMsg) {
+    let query = doc! {"_id": id};
+    if let Ok(line) = bson::to_bson(m) {
+        let update = doc! {"$push": {"lines": line}};
+        let options = UpdateOptions::builder().upsert(true).build();
+        if let Err(e) = db.update_one(query, update, options).await {
+            error!(game_id = %id, error = %e, "can't save chat line");
+        }
+    }
+}
+
+/// Remove deleted chat line.
+pub async fn delete_chat_line(
+    db: &Collection<GameChat>,
+    id: &str,
+    msg_id: u64,
+) {
+    let query = doc! {"_id": id};
+    let update = doc! {"$pull": {"lines": {"msg_id": msg_id as i64}}};
+    if let Err(e) = db.update_one(query, update, None).await {
+        error!(game_id = %id, msg_id, error = %e, "can't delete chat line");
+    }
+}
+
+/// Get chat for game.
+pub async fn get_game_chat(
+    db: &Collection<GameChat>,
+    id: &str,
+) -> Option<Vec<ChatMsg>> {
+    if let Ok(Some(chat)) = db.find_one(doc! {"_id": id}, None).await {
+        return Some(chat.lines);
+    }
+    None
+}
+
+/// Get chats for all unfinished games.
+pub async fn unfinished_chats(
+    db: &Collection<GameChat>,
+    ids: Vec<&String>,
+) -> HashMap<String, Vec<ChatMsg>> {
+    let filter = doc! {"_id": {"$in": ids}};
+    let mut hm = HashMap::new();
+    if let Ok(c) = db.find(filter, None).await {
+        let chats: Vec<GameChat> =
+            c.try_collect().await.unwrap_or_else(|_| vec![]);
+        for chat in chats {
+            hm.insert(chat._id, chat.lines);
         }
-        return id;
     }
+    hm
+}
+
+/// Save private message and update conversation for both players.
+pub async fn add_direct_message(
+    conversations: &Collection<Conversation>,
+    messages: &Collection<DirectMessage>,
+    m: &DirectMessage,
+) -> bool {
+    if messages.insert_one(m, None).await.is_err() {
+        return false;
+    }
+    let mut players = [String::from(&m.user), String::from(&m.to)];
+    players.sort();
+    let query = doc! {"_id": &m.conversation};
+    let unread = format!("unread.{}", &m.to);
+    let update = doc! {
+        "$set": {
+            "players": players.to_vec(),
+            "updated_at": m.time,
+            "last": &m.message
+        },
+        "$inc": {unread: 1}
+    };
+    let options = UpdateOptions::builder().upsert(true).build();
+    conversations
+        .update_one(query, update, options)
+        .await
+        .is_ok()
+}
+
+/// Get last 50 conversations for player.
+pub async fn get_conversations(
+    db: &Collection<Conversation>,
+    username: &str,
+) -> Vec<Conversation> {
+    let options = FindOptions::builder()
+        .sort(doc! {"updated_at": -1})
+        .limit(Some(50))
+        .build();
+    let filter = doc! {"players": {"$in": [username]}};
+    if let Ok(res) = db.find(filter, options).await {
+        return res.try_collect().await.unwrap_or_else(|_| vec![]);
+    }
+    vec![]
+}
+
+/// Get 50 messages per page from conversation, newest first.
+pub async fn get_direct_messages(
+    db: &Collection<DirectMessage>,
+    conversation: &str,
+    page: u64,
+) -> Vec<DirectMessage> {
+    let options = FindOptions::builder()
+        .sort(doc! {"time": -1})
+        .skip(Some(page * 50))
+        .limit(Some(50))
+        .build();
+    let filter = doc! {"conversation": conversation};
+    if let Ok(res) = db.find(filter, options).await {
+        return res.try_collect().await.unwrap_or_else(|_| vec![]);
+    }
+    vec![]
+}
+
+/// Player has read all messages in conversation.
+pub async fn read_conversation(
+    db: &Collection<Conversation>,
+    conversation: &str,
+    username: &str,
+) {
+    let query = doc! {"_id": conversation};
+    let unread = format!("unread.{username}");
+    let update = doc! {"$set": {unread: 0}};
+    if let Err(e) = db.update_one(query, update, None).await {
+        error!(
+            conversation = %conversation,
+            user = %username,
+            error = %e,
+            "can't read conversation"
+        );
+    }
+}
+
+/// Count unread messages in all conversations.
+pub async fn unread_count(
+    db: &Collection<Conversation>,
+    username: &str,
+) -> u32 {
+    let unread = format!("unread.{username}");
+    let filter = doc! {unread: {"$gt": 0}};
+    let mut count = 0;
+    if let Ok(res) = db.find(filter, None).await {
+        let all: Vec<Conversation> =
+            res.try_collect().await.unwrap_or_else(|_| vec![]);
+        for c in all {
+            count += c.unread.get(username).unwrap_or(&0);
+        }
+    }
+    count
+}
+
+/// Add or remove player from block list.
+pub async fn set_blocked(
+    db: &Collection<Player>,
+    username: &str,
+    other: &str,
+    block: bool,
+) -> bool {
+    let query = doc! {"_id": username};
+    let update = {
+        if block {
+            doc! {"$addToSet": {"blocked": other}}
+        } else {
+            doc! {"$pull": {"blocked": other}}
+        }
+    };
+    if let Ok(res) = db.update_one(query, update, None).await {
+        return res.matched_count == 1;
+    }
+    false
+}
+
+/// Follow or unfollow player.
+pub async fn set_following(
+    db: &Collection<Player>,
+    username: &str,
+    other: &str,
+    follow: bool,
+) -> bool {
+    let query = doc! {"_id": username};
+    let update = {
+        if follow {
+            doc! {"$addToSet": {"following": other}}
+        } else {
+            doc! {"$pull": {"following": other}}
+        }
+    };
+    if let Ok(res) = db.update_one(query, update, None).await {
+        return res.matched_count == 1;
+    }
+    false
+}
+
+/// Get usernames of all players following this player.
+pub async fn get_followers(
+    db: &Collection<Player>,
+    username: &str,
+) -> Vec<String> {
+    let options = FindOptions::builder().projection(doc! {"_id": 1}).build();
+    let filter = doc! {"following": username};
+    let q = db
+        .clone_with_type::<bson::Document>()
+        .find(filter, options)
+        .await;
+    if let Ok(res) = q {
+        let all: Vec<bson::Document> =
+            res.try_collect().await.unwrap_or_else(|_| vec![]);
+        return all
+            .iter()
+            .filter_map(|p| p.get_str("_id").ok())
+            .map(String::from)
+            .collect();
+    }
+    vec![]
+}
+
+/// Save new notification.
+pub async fn add_notification(
+    db: &Collection<Notification>,
+    n: &Notification,
+) -> bool {
+    db.insert_one(n, None).await.is_ok()
+}
+
+/// Save notification for game, or refresh unread one for the same game.
+pub async fn upsert_game_notification(
+    db: &Collection<Notification>,
+    n: &Notification,
+) -> Option<Notification> {
+    let kind = bson::to_bson(&n.kind).ok()?;
+    let game_id = n.data.get("game_id")?.as_str()?;
+    let filter = doc! {
+        "user": &n.user,
+        "kind": &kind,
+        "read": false,
+        "data.game_id": game_id
+    };
+    let data = bson::to_bson(&n.data).ok()?;
+    let update = doc! {
+        "$set": {"data": data, "created_at": n.created_at},
+        "$setOnInsert": {"_id": &n._id}
+    };
+    let options = FindOneAndUpdateOptions::builder()
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .build();
+    match db.find_one_and_update(filter, update, options).await {
+        Ok(res) => res,
+        Err(e) => {
+            error!(user = %n.user, error = %e, "can't save notification");
+            None
+        }
+    }
+}
+
+/// Get 20 notifications per page for player, newest first.
+/// Each one is returned with read state for player.
+pub async fn get_notifications(
+    db: &Collection<Notification>,
+    reads: &Collection<NotificationRead>,
+    username: &str,
+    page: u64,
+) -> Vec<(Notification, bool)> {
+    let options = FindOptions::builder()
+        .sort(doc! {"created_at": -1})
+        .skip(Some(page * 20))
+        .limit(Some(20))
+        .build();
+    let filter = doc! {"user": {"$in": [username, ALL_USERS]}};
+    let all: Vec<Notification> = match db.find(filter, options).await {
+        Ok(res) => res.try_collect().await.unwrap_or_else(|_| vec![]),
+        Err(_) => return vec![],
+    };
+    let ids: Vec<String> = all
+        .iter()
+        .filter(|n| n.user == ALL_USERS)
+        .map(|n| NotificationRead::new(&n._id, username)._id)
+        .collect();
+    let mut read: Vec<NotificationRead> = vec![];
+    if !ids.is_empty() {
+        if let Ok(res) = reads.find(doc! {"_id": {"$in": ids}}, None).await {
+            read = res.try_collect().await.unwrap_or_else(|_| vec![]);
+        }
+    }
+    all.into_iter()
+        .map(|n| {
+            let is_read =
+                n.read || read.iter().any(|r| r.notification == n._id);
+            (n, is_read)
+        })
+        .collect()
+}
+
+/// Mark one or all notifications as read.
+pub async fn read_notifications(
+    db: &Collection<Notification>,
+    reads: &Collection<NotificationRead>,
+    username: &str,
+    id: Option<&str>,
+) -> bool {
+    let mut query = doc! {"user": username, "read": false};
+    if let Some(id) = id {
+        query.insert("_id", id);
+    }
+    let update = doc! {"$set": {"read": true}};
+    let personal = match db.update_many(query, update, None).await {
+        Ok(res) => res.matched_count,
+        Err(e) => {
+            error!(user = %username, error = %e, "can't read notifications");
+            0
+        }
+    };
+    let mut query = doc! {"user": ALL_USERS};
+    if let Some(id) = id {
+        query.insert("_id", id);
+    }
+    let options = FindOptions::builder().projection(doc! {"_id": 1}).build();
+    let q = db.clone_with_type::<Document>().find(query, options).await;
+    let all: Vec<Document> = match q {
+        Ok(res) => res.try_collect().await.unwrap_or_else(|_| vec![]),
+        Err(_) => vec![],
+    };
+    let read: Vec<NotificationRead> = all
+        .iter()
+        .filter_map(|n| n.get_str("_id").ok())
+        .map(|id| NotificationRead::new(id, username))
+        .collect();
+    if read.is_empty() {
+        return personal > 0;
+    }
+    // Already read ones are rejected as duplicates.
+    let options = InsertManyOptions::builder().ordered(false).build();
+    if let Err(e) = reads.insert_many(&read, options).await {
+        if !is_duplicate_batch(&e) {
+            error!(user = %username, error = %e, "can't read notifications");
+        }
+    }
+    true
+}
+
+/// Count unread notifications for player.
+pub async fn unread_notifications(
+    db: &Collection<Notification>,
+    reads: &Collection<NotificationRead>,
+    username: &str,
+) -> u64 {
+    let personal = doc! {"user": username, "read": false};
+    let personal = db.count_documents(personal, None).await.unwrap_or(0);
+    let all = doc! {"user": ALL_USERS};
+    let all = db.count_documents(all, None).await.unwrap_or(0);
+    let read = doc! {"user": username};
+    let read = reads.count_documents(read, None).await.unwrap_or(0);
+    personal + all.saturating_sub(read)
 }
 
 /// Get game from database if it exist.
 pub async fn get_game_db(
     db: &Collection<ShuuroGame>,
-    id: &String,
+    id: &str,
 ) -> Option<ShuuroGame> {
     let id = String::from(id);
     let filter = doc! {"_id": id};
@@ -89,13 +561,42 @@ pub async fn get_game_db(
     None
 }
 
-/// Add new game to database and format ws message.
+/// Add new game to database with unused public and player IDs.
 pub async fn add_game_to_db(
     db: &Collection<ShuuroGame>,
-    game: &ShuuroGame,
-) -> Value {
-    if let Err(_res) = db.insert_one(game, None).await {}
-    live_game_start(game)
+    game: &mut ShuuroGame,
+) -> Result<(), StorageError> {
+    for _ in 0..ID_RETRIES {
+        game._id = public_game_id();
+        game.player_ids = player_ids();
+        match db.insert_one(&*game, None).await {
+            Ok(_) => return Ok(()),
+            Err(e) if is_duplicate(&e) => continue,
+            Err(e) => return Err(StorageError::Backend(e.to_string())),
+        }
+    }
+    Err(StorageError::IdsExhausted)
+}
+
+/// Public ID of game with this private player ID.
+pub async fn get_player_game_id(
+    db: &Collection<ShuuroGame>,
+    id: &str,
+) -> Option<String> {
+    let options = FindOneOptions::builder()
+        .projection(doc! {"_id": 1})
+        .build();
+    let q = db
+        .clone_with_type::<Document>()
+        .find_one(doc! {"player_ids": id}, options)
+        .await;
+    match q {
+        Ok(game) => Some(String::from(game?.get_str("_id").ok()?)),
+        Err(e) => {
+            error!(error = %e, "can't find game by player id");
+            None
+        }
+    }
 }
 
 /// Update all fields for game.
@@ -105,13 +606,15 @@ pub async fn update_entire_game(
 ) {
     let query = doc! {"_id": &game._id};
     let update = doc! {"$set": bson::to_bson(&game).unwrap()};
-    db.update_one(query, update, None).await.ok();
+    if let Err(e) = db.update_one(query, update, None).await {
+        error!(game_id = %game._id, error = %e, "can't update game");
+    }
 }
 
 /// Get last 5 games for player.
 pub async fn get_player_games(
     db: &Collection<ShuuroGame>,
-    username: &String,
+    username: &str,
     page: u64,
 ) -> Option<Vec<ProfileGame>> {
     let options = FindOptions::builder()
@@ -133,10 +636,381 @@ pub async fn get_player_games(
     None
 }
 
+/// Search games, newest first.
+pub async fn search_games(
+    db: &Collection<ShuuroGame>,
+    filter: Document,
+    limit: i64,
+) -> Vec<ProfileGame> {
+    let options = FindOptions::builder()
+        .projection(doc! {"history": 0, "credits": 0, "hands": 0})
+        .sort(doc! {"last_clock": -1, "_id": -1})
+        .limit(Some(limit))
+        .build();
+    let q = db
+        .clone_with_type::<ProfileGame>()
+        .find(filter, options)
+        .await;
+    if let Ok(res) = q {
+        return res.try_collect().await.unwrap_or_else(|_| vec![]);
+    }
+    vec![]
+}
+
+/// Save one rating for player.
+pub async fn set_rating(
+    db: &Collection<Player>,
+    username: &str,
+    key: &str,
+    rating: &Rating,
+) {
+    if let Ok(rating) = bson::to_bson(rating) {
+        let query = doc! {"_id": username};
+        let update = doc! {"$set": {format!("ratings.{key}"): rating}};
+        if let Err(e) = db.update_one(query, update, None).await {
+            error!(user = %username, key, error = %e, "can't save rating");
+        }
+    }
+}
+
+/// Get top active players with established rating.
+pub async fn get_leaderboard(
+    db: &Collection<Player>,
+    key: &str,
+    since: bson::DateTime,
+) -> Vec<Player> {
+    let rating = format!("ratings.{key}");
+    let options = FindOptions::builder()
+        .sort(doc! {format!("{rating}.rating"): -1})
+        .limit(Some(LEADERBOARD_SIZE as i64))
+        .build();
+    let filter = doc! {
+        "reg": true,
+        format!("{rating}.deviation"): {"$lte": PROVISIONAL_DEVIATION},
+        format!("{rating}.last_played"): {"$gte": since}
+    };
+    if let Ok(res) = db.find(filter, options).await {
+        return res.try_collect().await.unwrap_or_else(|_| vec![]);
+    }
+    vec![]
+}
+
+/// Run aggregation and return all documents as json.
+async fn aggregate_json(
+    db: &Collection<ShuuroGame>,
+    pipeline: Vec<Document>,
+) -> Vec<Value> {
+    if let Ok(res) = db.aggregate(pipeline, None).await {
+        let all: Vec<Document> =
+            res.try_collect().await.unwrap_or_else(|_| vec![]);
+        return all
+            .into_iter()
+            .map(|d| Bson::Document(d).into_relaxed_extjson())
+            .collect();
+    }
+    vec![]
+}
+
+/// Calculate results, game length and favourite purchases for player.
+pub async fn profile_stats(
+    db: &Collection<ShuuroGame>,
+    username: &String,
+) -> ProfileStats {
+    let finished = doc! {
+        "$match": {"players": username, "status": {"$gt": 0}}
+    };
+    let me = doc! {"$indexOfArray": ["$players", username]};
+    let variants = vec![
+        finished.clone(),
+        doc! {"$project": {
+            "variant": 1,
+            "sub_variant": 1,
+            "me": &me,
+            "loser": {"$indexOfArray": [["w", "b"], "$result"]},
+            "decisive": {"$in": ["$status", [1, 7, 8]]},
+            "length": {"$size": {
+                "$ifNull": [{"$arrayElemAt": ["$history", 2]}, []]
+            }}
+        }},
+        doc! {"$group": {
+            "_id": {"variant": "$variant", "sub_variant": "$sub_variant"},
+            "total": {"$sum": 1},
+            "won": {"$sum": {"$cond": [{"$and": [
+                "$decisive",
+                {"$gte": ["$loser", 0]},
+                {"$ne": ["$loser", "$me"]}
+            ]}, 1, 0]}},
+            "lost": {"$sum": {"$cond": [{"$and": [
+                "$decisive",
+                {"$eq": ["$loser", "$me"]}
+            ]}, 1, 0]}},
+            "length": {"$sum": "$length"}
+        }},
+        doc! {"$project": {
+            "_id": 0,
+            "variant": "$_id.variant",
+            "sub_variant": "$_id.sub_variant",
+            "total": 1,
+            "won": 1,
+            "lost": 1,
+            "drawn": {"$subtract": ["$total", {"$add": ["$won", "$lost"]}]},
+            "avg_length": {"$divide": ["$length", "$total"]}
+        }},
+        doc! {"$sort": {"total": -1}},
+    ];
+    // white purchases are uppercase
+    let purchases = vec![
+        finished,
+        doc! {"$project": {
+            "me": &me,
+            "buys": {"$ifNull": [{"$arrayElemAt": ["$history", 0]}, []]}
+        }},
+        doc! {"$project": {"buys": {"$filter": {
+            "input": "$buys",
+            "as": "b",
+            "cond": {"$eq": [
+                {"$eq": [{"$toUpper": "$$b"}, "$$b"]},
+                {"$eq": ["$me", 0]}
+            ]}
+        }}}},
+        doc! {"$match": {"buys.0": {"$exists": true}}},
+        doc! {"$group": {
+            "_id": {"$slice": ["$buys", OPENING_PURCHASES]},
+            "count": {"$sum": 1}
+        }},
+        doc! {"$sort": {"count": -1}},
+        doc! {"$limit": 5},
+        doc! {"$project": {"_id": 0, "opening": "$_id", "count": 1}},
+    ];
+    let variants = aggregate_json(db, variants).await;
+    let purchases = aggregate_json(db, purchases).await;
+    let (mut games, mut length) = (0.0, 0.0);
+    for v in &variants {
+        let total = v["total"].as_f64().unwrap_or(0.0);
+        games += total;
+        length += v["avg_length"].as_f64().unwrap_or(0.0) * total;
+    }
+    ProfileStats {
+        _id: String::from(username),
+        variants,
+        avg_length: if games > 0.0 { length / games } else { 0.0 },
+        purchases,
+    }
+}
+
+/// Count next choices after prefix in shop (0) or deploy (1) history.
+/// Deploy history starts with plinth layout, followed by placement moves.
+/// For shop only purchases from one color are used.
+pub async fn explore(
+    db: &Collection<ShuuroGame>,
+    filter: Document,
+    stage: usize,
+    color: Option<usize>,
+    prefix: &[String],
+) -> Vec<Value> {
+    let n = prefix.len() as i32;
+    let mut pipeline = vec![doc! {"$match": filter}];
+    if stage == 1 {
+        pipeline.push(doc! {"$match": {"history.1.0": {"$exists": true}}});
+    }
+    pipeline.append(&mut vec![doc! {"$project": {
+        "loser": {"$indexOfArray": [["w", "b"], "$result"]},
+        "decisive": {"$in": ["$status", [1, 7, 8]]},
+        "moves": {
+            "$ifNull": [{"$arrayElemAt": ["$history", stage as i32]}, []]
+        }
+    }}]);
+    if stage == 1 {
+        // First entry is starting sfen, placements are saved as `Q@d1_sfen`.
+        // Board from starting sfen is kept, so games with same plinths
+        // are compared.
+        let layout = doc! {"$arrayElemAt": [
+            {"$split": [{"$arrayElemAt": ["$moves", 0]}, " "]}, 0
+        ]};
+        pipeline.push(doc! {"$project": {
+            "loser": 1,
+            "decisive": 1,
+            "moves": {"$concatArrays": [[layout], {"$map": {
+                "input": {"$filter": {
+                    "input": "$moves",
+                    "as": "m",
+                    "cond": {"$gt": [{"$indexOfBytes": ["$$m", "_"]}, 0]}
+                }},
+                "as": "m",
+                "in": {"$arrayElemAt": [{"$split": ["$$m", "_"]}, 0]}
+            }}]}
+        }});
+    }
+    if let Some(color) = color {
+        // white purchases are uppercase
+        pipeline.push(doc! {"$project": {
+            "loser": 1,
+            "decisive": 1,
+            "moves": {"$filter": {
+                "input": "$moves",
+                "as": "m",
+                "cond": {"$eq": [
+                    {"$eq": [{"$toUpper": "$$m"}, "$$m"]},
+                    color == 0
+                ]}
+            }}
+        }});
+    }
+    let mut matches = vec![doc! {"$gt": [{"$size": "$moves"}, n]}];
+    if n > 0 {
+        matches.push(doc! {"$eq": [{"$slice": ["$moves", n]}, prefix]});
+    }
+    pipeline.append(&mut vec![
+        doc! {"$match": {"$expr": {"$and": matches}}},
+        doc! {"$group": {
+            "_id": {"$arrayElemAt": ["$moves", n]},
+            "total": {"$sum": 1},
+            "white": {"$sum": {"$cond": [
+                {"$and": ["$decisive", {"$eq": ["$loser", 1]}]}, 1, 0
+            ]}},
+            "black": {"$sum": {"$cond": [
+                {"$and": ["$decisive", {"$eq": ["$loser", 0]}]}, 1, 0
+            ]}}
+        }},
+        doc! {"$project": {
+            "_id": 0,
+            "move": "$_id",
+            "total": 1,
+            "white": 1,
+            "black": 1,
+            "draws": {"$subtract": ["$total", {"$add": ["$white", "$black"]}]}
+        }},
+        doc! {"$sort": {"total": -1}},
+        doc! {"$limit": 50},
+    ]);
+    aggregate_json(db, pipeline).await
+}
+
+/// Save new puzzle, returns false if it already exist.
+pub async fn add_puzzle(db: &Collection<Puzzle>, puzzle: &Puzzle) -> bool {
+    db.insert_one(puzzle, None).await.is_ok()
+}
+
+/// Get puzzle by ID.
+pub async fn get_puzzle(
+    db: &Collection<Puzzle>,
+    id: &String,
+) -> Option<Puzzle> {
+    if let Ok(puzzle) = db.find_one(doc! {"_id": id}, None).await {
+        return puzzle;
+    }
+    None
+}
+
+/// Get random puzzle close to player rating.
+pub async fn random_puzzle(
+    db: &Collection<Puzzle>,
+    rating: f64,
+) -> Option<Puzzle> {
+    let near = doc! {"rating.rating": {
+        "$gte": rating - 200.0,
+        "$lte": rating + 200.0
+    }};
+    for filter in [near, doc! {}] {
+        let pipeline =
+            vec![doc! {"$match": filter}, doc! {"$sample": {"size": 1}}];
+        if let Ok(res) = db.aggregate(pipeline, None).await {
+            let all: Vec<Document> =
+                res.try_collect().await.unwrap_or_else(|_| vec![]);
+            if let Some(puzzle) = all.into_iter().next() {
+                return bson::from_document(puzzle).ok();
+            }
+        }
+    }
+    None
+}
+
+/// Save new rating for puzzle.
+pub async fn set_puzzle_rating(
+    db: &Collection<Puzzle>,
+    id: &String,
+    rating: &Rating,
+) {
+    if let Ok(rating) = bson::to_bson(rating) {
+        let query = doc! {"_id": id};
+        let update = doc! {"$set": {"rating": rating}, "$inc": {"plays": 1}};
+        if let Err(e) = db.update_one(query, update, None).await {
+            error!(puzzle = %id, error = %e, "can't save puzzle rating");
+        }
+    }
+}
+
+/// Save first attempt, returns false if player already tried this puzzle.
+pub async fn add_puzzle_attempt(
+    db: &Collection<PuzzleAttempt>,
+    attempt: &PuzzleAttempt,
+) -> bool {
+    db.insert_one(attempt, None).await.is_ok()
+}
+
+/// Get finished games not yet scanned for puzzles.
+pub async fn unscanned_games(
+    db: &Collection<ShuuroGame>,
+    variant: &str,
+) -> Vec<ShuuroGame> {
+    let filter = doc! {
+        "variant": variant,
+        "status": {"$gt": 0},
+        "puzzles_scanned": {"$ne": true}
+    };
+    if let Ok(res) = db.find(filter, None).await {
+        return res.try_collect().await.unwrap_or_else(|_| vec![]);
+    }
+    vec![]
+}
+
+/// Game is scanned and won't be used again.
+pub async fn set_puzzles_scanned(db: &Collection<ShuuroGame>, id: &String) {
+    let query = doc! {"_id": id};
+    let update = doc! {"$set": {"puzzles_scanned": true}};
+    if let Err(e) = db.update_one(query, update, None).await {
+        error!(game_id = %id, error = %e, "can't mark game as scanned");
+    }
+}
+
+/// Get cached profile statistics.
+pub async fn get_profile_stats(
+    db: &Collection<ProfileStats>,
+    username: &String,
+) -> Option<ProfileStats> {
+    if let Ok(stats) = db.find_one(doc! {"_id": username}, None).await {
+        return stats;
+    }
+    None
+}
+
+/// Save profile statistics.
+pub async fn set_profile_stats(
+    db: &Collection<ProfileStats>,
+    stats: &ProfileStats,
+) {
+    let options = ReplaceOptions::builder().upsert(true).build();
+    let filter = doc! {"_id": &stats._id};
+    if let Err(e) = db.replace_one(filter, stats, options).await {
+        error!(user = %stats._id, error = %e, "can't save profile stats");
+    }
+}
+
+/// Remove cached statistics after game.
+pub async fn delete_profile_stats(
+    db: &Collection<ProfileStats>,
+    players: &[String; 2],
+) {
+    let filter = doc! {"_id": {"$in": players.to_vec()}};
+    if let Err(e) = db.delete_many(filter, None).await {
+        error!(players = ?players, error = %e, "can't delete profile stats");
+    }
+}
+
 /// Get article if ID exist.
 pub async fn get_article(
     db: &Collection<Article>,
-    id: &String,
+    id: &str,
 ) -> Option<Article> {
     let filter = doc! {"_id": id};
     if let Ok(n) = db.find_one(filter, None).await {
@@ -147,6 +1021,85 @@ pub async fn get_article(
     None
 }
 
+/// Get 10 articles per page, optionally filtered by category.
+pub async fn get_articles(
+    db: &Collection<Article>,
+    category: Option<&String>,
+    page: u64,
+    drafts: bool,
+) -> Option<Vec<Article>> {
+    let options = FindOptions::builder()
+        .projection(doc! {"text": 0, "html": 0})
+        .sort(doc! {"date": -1})
+        .skip(Some(page * 10))
+        .limit(Some(10))
+        .build();
+    let mut filter = doc! {};
+    if let Some(category) = category {
+        filter.insert("category", category);
+    }
+    if !drafts {
+        filter.insert("draft", doc! {"$ne": true});
+    }
+    let articles = match db.find(filter, options).await {
+        Ok(res) => res.try_collect::<Vec<Article>>().await,
+        Err(e) => Err(e),
+    };
+    match articles {
+        Ok(articles) => Some(articles),
+        Err(e) => {
+            error!(error = %e, "can't get articles");
+            None
+        }
+    }
+}
+
+/// Add new article.
+pub async fn add_article(db: &Collection<Article>, article: &Article) -> bool {
+    db.insert_one(article, None).await.is_ok()
+}
+
+/// Update all editable fields for article.
+pub async fn update_article(
+    db: &Collection<Article>,
+    article: &Article,
+) -> bool {
+    let query = doc! {"_id": &article._id};
+    let update = doc! {"$set": {
+        "title": &article.title,
+        "category": &article.category,
+        "text": &article.text,
+        "html": &article.html,
+        "headline": &article.headline
+    }};
+    if let Ok(res) = db.update_one(query, update, None).await {
+        return res.matched_count == 1;
+    }
+    false
+}
+
+/// Publish or unpublish article.
+pub async fn set_article_draft(
+    db: &Collection<Article>,
+    id: &str,
+    draft: bool,
+) -> bool {
+    let query = doc! {"_id": id};
+    let update = doc! {"$set": {"draft": draft}};
+    if let Ok(res) = db.update_one(query, update, None).await {
+        return res.matched_count == 1;
+    }
+    false
+}
+
+/// Delete article if ID exist.
+pub async fn delete_article(db: &Collection<Article>, id: &str) -> bool {
+    if let Ok(res) = db.delete_one(doc! {"_id": id}, None).await {
+        return res.deleted_count == 1;
+    }
+    false
+}
+
 /// get all unfinished matches
 pub async fn unfinished(
     db: &Collection<ShuuroGame>,
@@ -176,5 +1129,12 @@ pub async fn insert_move(db: &Collection<ShuuroGame>, json: &GameGet) {
     };
     let field = format!("history.{}", field);
     let update = doc! {"$push": {field: &json.game_move}};
-    db.update_one(query, update, None).await.ok();
+    if let Err(e) = db.update_one(query, update, None).await {
+        error!(
+            game_id = %json.game_id,
+            game_move = %json.game_move,
+            error = %e,
+            "can't save move"
+        );
+    }
 }
@@ -0,0 +1,141 @@
+use std::f64::consts::PI;
+
+use bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use super::mongo::ShuuroGame;
+
+const DEFAULT_RATING: f64 = 1500.0;
+const DEFAULT_DEVIATION: f64 = 350.0;
+const DEFAULT_VOLATILITY: f64 = 0.06;
+/// Rating with higher deviation is provisional.
+pub const PROVISIONAL_DEVIATION: f64 = 110.0;
+const SCALE: f64 = 173.7178;
+const TAU: f64 = 0.5;
+const EPSILON: f64 = 0.000001;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+/// Glicko-2 rating for one variant and speed.
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+    pub games: u32,
+    pub last_played: Option<DateTime>,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+            games: 0,
+            last_played: None,
+        }
+    }
+}
+
+impl Rating {
+    pub fn is_provisional(&self) -> bool {
+        self.deviation > PROVISIONAL_DEVIATION
+    }
+
+    /// New rating after one game. Score is 1 for win, 0.5 for draw.
+    pub fn update(&self, opponent: &Rating, score: f64) -> Rating {
+        let mu = (self.rating - DEFAULT_RATING) / SCALE;
+        let phi = self.deviation / SCALE;
+        let mu_j = (opponent.rating - DEFAULT_RATING) / SCALE;
+        let phi_j = opponent.deviation / SCALE;
+
+        let g = 1.0 / (1.0 + 3.0 * phi_j.powi(2) / PI.powi(2)).sqrt();
+        let e = 1.0 / (1.0 + (-g * (mu - mu_j)).exp());
+        let v = 1.0 / (g.powi(2) * e * (1.0 - e));
+        let delta = v * g * (score - e);
+
+        let sigma = self.new_volatility(phi, v, delta);
+        let phi_star = (phi.powi(2) + sigma.powi(2)).sqrt();
+        let phi = 1.0 / (1.0 / phi_star.powi(2) + 1.0 / v).sqrt();
+        let mu = mu + phi.powi(2) * g * (score - e);
+
+        Rating {
+            rating: mu * SCALE + DEFAULT_RATING,
+            deviation: (phi * SCALE).min(DEFAULT_DEVIATION),
+            volatility: sigma,
+            games: self.games + 1,
+            last_played: Some(DateTime::now()),
+        }
+    }
+
+    /// Illinois algorithm from Glicko-2 paper.
+    fn new_volatility(&self, phi: f64, v: f64, delta: f64) -> f64 {
+        let a = self.volatility.powi(2).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            ex * (delta.powi(2) - phi.powi(2) - v - ex)
+                / (2.0 * (phi.powi(2) + v + ex).powi(2))
+                - (x - a) / TAU.powi(2)
+        };
+        let mut big_a = a;
+        let mut big_b = {
+            if delta.powi(2) > phi.powi(2) + v {
+                (delta.powi(2) - phi.powi(2) - v).ln()
+            } else {
+                let mut k = 1.0;
+                while f(a - k * TAU) < 0.0 {
+                    k += 1.0;
+                }
+                a - k * TAU
+            }
+        };
+        let mut f_a = f(big_a);
+        let mut f_b = f(big_b);
+        while (big_b - big_a).abs() > EPSILON {
+            let c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+            let f_c = f(c);
+            if f_c * f_b <= 0.0 {
+                big_a = big_b;
+                f_a = f_b;
+            } else {
+                f_a /= 2.0;
+            }
+            big_b = c;
+            f_b = f_c;
+        }
+        (big_a / 2.0).exp()
+    }
+}
+
+/// Speed category from estimated game duration.
+pub fn speed(min_secs: i64, incr_secs: i64) -> &'static str {
+    let total = min_secs + 40 * incr_secs;
+    if total < 180 {
+        "bullet"
+    } else if total < 480 {
+        "blitz"
+    } else if total < 1500 {
+        "rapid"
+    } else {
+        "classical"
+    }
+}
+
+/// Key used for player ratings, for example `shuuro_blitz`.
+pub fn rating_key(game: &ShuuroGame) -> String {
+    let speed = speed(game.min.num_seconds(), game.incr.num_seconds());
+    format!("{}_{}", game.variant, speed)
+}
+
+/// Score for both players, `None` if game is not finished.
+/// For decisive games `result` is color of player who lost.
+pub fn game_scores(game: &ShuuroGame) -> Option<[f64; 2]> {
+    match game.status {
+        1 | 7 | 8 => match game.result.as_str() {
+            "w" => Some([0.0, 1.0]),
+            "b" => Some([1.0, 0.0]),
+            _ => Some([0.5, 0.5]),
+        },
+        3..=6 => Some([0.5, 0.5]),
+        _ => None,
+    }
+}
@@ -8,16 +8,26 @@ use axum::{
 };
 use bson::DateTime;
 use hyper::{header::SET_COOKIE, HeaderMap, StatusCode};
-use mongodb::Collection;
 use redis::{aio::ConnectionManager, AsyncCommands, Client};
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+use tracing::error;
 
-use crate::{arc2, lichess::cookies, AppState};
+use crate::{arc2, lichess::cookies, metrics::timed, AppState};
 
-use super::{mongo::Player, queries::create_player};
+use super::{
+    mongo::Player,
+    repos::{PlayerRepo, SessionStore},
+    StorageError, ID_RETRIES,
+};
 
 pub const AXUM_SESSION_COOKIE_NAME: &str = "axum_session";
+pub const ADMINS: [&str; 1] = ["iiiurosiii"];
+pub const MODERATORS: [&str; 0] = [];
 
 /// Struct representing current user.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -84,6 +94,17 @@ impl UserSession {
         headers
     }
 
+    /// Only registered admins can manage server and news.
+    pub fn is_admin(&self) -> bool {
+        self.reg && ADMINS.contains(&self.username.as_str())
+    }
+
+    /// Moderators can delete messages, mute players and see reports.
+    pub fn is_moderator(&self) -> bool {
+        self.is_admin()
+            || (self.reg && MODERATORS.contains(&self.username.as_str()))
+    }
+
     pub fn watch(&self, watching: &String) {
         *self.watches.lock().unwrap() = String::from(watching);
     }
@@ -129,15 +150,28 @@ pub struct RedisCli {
 }
 
 impl RedisCli {
-    pub async fn default() -> Self {
-        let cli = Client::open("redis://127.0.0.1/").unwrap();
+    pub async fn new(uri: &str) -> Self {
+        let cli = Client::open(uri).expect("bad redis uri");
         let con = ConnectionManager::new(cli).await.unwrap();
         Self { con }
     }
 
-    /// Get session if it exist.
-    pub async fn get_session(&mut self, key: &str) -> Option<UserSession> {
-        if let Ok(s) = self.con.get::<String, String>(String::from(key)).await {
+    /// Returns one year ttl for registered user.
+    pub fn ttl_days(&self, reg: bool) -> usize {
+        let day = 60 * 60 * 24;
+        if reg {
+            return day * 365;
+        }
+        day * 2
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisCli {
+    async fn get_session(&self, key: &str) -> Option<UserSession> {
+        let mut con = self.con.clone();
+        let get = con.get::<String, String>(String::from(key));
+        if let Ok(s) = timed("redis", "get_session", get).await {
             if let Ok(value) = serde_json::from_str::<UserSession>(&s) {
                 let value = self.set_session(key, value, false).await;
                 return Some(value);
@@ -146,9 +180,8 @@ impl RedisCli {
         None
     }
 
-    /// Set new session.
-    pub async fn set_session(
-        &mut self,
+    async fn set_session(
+        &self,
         key: &str,
         mut value: UserSession,
         force_set: bool,
@@ -157,55 +190,43 @@ impl RedisCli {
             if force_set == false {
                 value.not_new();
             }
-            self.con
-                .set::<String, String, String>(
+            let mut con = self.con.clone();
+            let set = async {
+                con.set::<String, String, String>(
                     String::from(key),
                     serde_json::to_string(&value).unwrap(),
                 )
                 .await
                 .unwrap();
-            let _e = self
-                .con
-                .expire::<String, usize>(
-                    String::from(key),
-                    self.ttl_days(value.reg),
-                )
-                .await;
+                let _e = con
+                    .expire::<String, usize>(
+                        String::from(key),
+                        self.ttl_days(value.reg),
+                    )
+                    .await;
+            };
+            timed("redis", "set_session", set).await;
         }
         value
     }
+}
 
-    /// Create session.
-    pub async fn new_session(
-        &mut self,
-        players: &Collection<Player>,
-        cookie_value: CookieValue,
-    ) -> UserSession {
-        let username = create_player(players).await;
-        loop {
-            let s = Session::new();
-            if (self.get_session(s.id()).await).is_some() {
-            } else {
-                let value = UserSession::new(
-                    &username,
-                    s.id(),
-                    false,
-                    "",
-                    cookie_value,
-                );
-                return self.set_session(s.id(), value, true).await;
-            }
-        }
-    }
-
-    /// Returns one year ttl for registered user.
-    pub fn ttl_days(&self, reg: bool) -> usize {
-        let day = 60 * 60 * 24;
-        if reg {
-            return day * 365;
+/// Create session for new anonymous player.
+pub async fn new_session(
+    sessions: &dyn SessionStore,
+    players: &dyn PlayerRepo,
+    cookie_value: CookieValue,
+) -> Result<UserSession, StorageError> {
+    let username = players.create_player().await?;
+    for _ in 0..ID_RETRIES {
+        let s = Session::new();
+        if sessions.get_session(s.id()).await.is_none() {
+            let value =
+                UserSession::new(&username, s.id(), false, "", cookie_value);
+            return Ok(sessions.set_session(s.id(), value, true).await);
         }
-        day * 2
     }
+    Err(StorageError::IdsExhausted)
 }
 
 #[async_trait]
@@ -221,25 +242,59 @@ where
         state: &S,
     ) -> Result<Self, Self::Rejection> {
         let store = AppState::from_ref(state);
-        let cookie: Option<TypedHeader<Cookie>> =
-            parts.extract().await.unwrap();
-
-        let mut redis = store.db.redis.clone();
-        let prod = store.db.key.prod;
-        let cookie_value = cookies(prod);
-
-        let session_cookie = cookie
-            .as_ref()
-            .and_then(|cookie| cookie.get(AXUM_SESSION_COOKIE_NAME));
-        if let Some(session) = session_cookie {
-            if let Some(session) = redis.get_session(session).await {
-                return Ok(session);
+        if let Some(session) = existing_session(parts, &store).await {
+            return Ok(session);
+        }
+        let sessions = store.db.sessions.as_ref();
+        let cookie_value = cookies(store.db.key.prod);
+        let ip = store.ws.rate_limits.client_ip(parts);
+        if !store.ws.rate_limits.new_account(ip) {
+            return Err((StatusCode::TOO_MANY_REQUESTS, "too many accounts"));
+        }
+        let players = store.db.players.as_ref();
+        match new_session(sessions, players, cookie_value).await {
+            Ok(session) => Ok(session),
+            Err(e) => {
+                error!(error = %e, "can't create anonymous player");
+                Err((StatusCode::SERVICE_UNAVAILABLE, "can't create player"))
             }
         }
-        let session = redis
-            .new_session(&store.db.mongo.players, cookie_value)
-            .await;
-        return Ok(session);
+    }
+}
+
+/// Session from cookie, if it exists.
+async fn existing_session(
+    parts: &mut Parts,
+    store: &AppState,
+) -> Option<UserSession> {
+    let cookie: Option<TypedHeader<Cookie>> = parts.extract().await.unwrap();
+    let session = cookie.as_ref()?.get(AXUM_SESSION_COOKIE_NAME)?;
+    store.db.sessions.get_session(session).await
+}
+
+/// Session for read-only routes. Anonymous player is never created.
+pub struct OptionalSession(pub Option<UserSession>);
+
+impl OptionalSession {
+    pub fn is_admin(&self) -> bool {
+        self.0.as_ref().is_some_and(|user| user.is_admin())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for OptionalSession
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let store = AppState::from_ref(state);
+        Ok(Self(existing_session(parts, &store).await))
     }
 }
 
@@ -265,6 +320,10 @@ impl From<&UserSession> for Player {
             _id: String::from(&other.username),
             reg: other.reg,
             created_at: DateTime::now(),
+            muted_until: None,
+            blocked: vec![],
+            following: vec![],
+            ratings: HashMap::new(),
         }
     }
 }
@@ -0,0 +1,625 @@
+use std::collections::HashMap;
+
+use async_session::async_trait;
+use bson::DateTime;
+use mongodb::Collection;
+
+use crate::{
+    metrics::timed,
+    websockets::{rooms::ChatMsg, GameGet},
+};
+
+use super::{
+    mongo::{
+        Article, ChatReport, Conversation, DirectMessage, FilteredMsg, Mongo,
+        Notification, Player, ProfileGame, ShuuroGame,
+    },
+    queries,
+    ratings::{game_scores, rating_key, Rating},
+    redis::UserSession,
+    StorageError,
+};
+
+/// Storage for players.
+#[async_trait]
+pub trait PlayerRepo: Send + Sync {
+    /// Create anonymous player with random username.
+    async fn create_player(&self) -> Result<String, StorageError>;
+
+    /// Add player after lichess login if it doesn't exist.
+    async fn player_exist(
+        &self,
+        username: &str,
+        session: &UserSession,
+    ) -> Option<UserSession>;
+
+    async fn get_player(&self, username: &str) -> Option<Player>;
+
+    async fn set_rating(&self, username: &str, key: &str, rating: &Rating);
+
+    /// Get top active players with established rating.
+    async fn get_leaderboard(&self, key: &str, since: DateTime) -> Vec<Player>;
+
+    /// Mute player until selected time. If time is `None` mute is removed.
+    async fn set_mute(&self, username: &str, until: Option<DateTime>) -> bool;
+
+    /// Add or remove player from block list.
+    async fn set_blocked(
+        &self,
+        username: &str,
+        other: &str,
+        block: bool,
+    ) -> bool;
+
+    /// Follow or unfollow player.
+    async fn set_following(
+        &self,
+        username: &str,
+        other: &str,
+        follow: bool,
+    ) -> bool;
+
+    /// Get usernames of all players following this player.
+    async fn followers(&self, username: &str) -> Vec<String>;
+
+    /// Update ratings for both players after rated game.
+    async fn update_ratings(
+        &self,
+        game: &ShuuroGame,
+    ) -> Option<(String, [Rating; 2])> {
+        if !game.rated {
+            return None;
+        }
+        let scores = game_scores(game)?;
+        let key = rating_key(game);
+        let mut ratings = vec![];
+        for username in &game.players {
+            match self.get_player(username).await {
+                Some(player) if player.reg => ratings.push(
+                    player.ratings.get(&key).copied().unwrap_or_default(),
+                ),
+                _ => return None,
+            }
+        }
+        let ratings = [
+            ratings[0].update(&ratings[1], scores[0]),
+            ratings[1].update(&ratings[0], scores[1]),
+        ];
+        for (username, rating) in game.players.iter().zip(ratings.iter()) {
+            self.set_rating(username, &key, rating).await;
+        }
+        Some((key, ratings))
+    }
+}
+
+/// Storage for games.
+#[async_trait]
+pub trait GameRepo: Send + Sync {
+    async fn get_game(&self, id: &str) -> Option<ShuuroGame>;
+
+    /// Insert new game with unused public and player IDs.
+    async fn add_game(&self, game: &mut ShuuroGame)
+        -> Result<(), StorageError>;
+
+    /// Public ID of game with this private player ID.
+    async fn player_game_id(&self, id: &str) -> Option<String>;
+
+    /// Update all fields for game.
+    async fn update_game(&self, game: &ShuuroGame);
+
+    /// Push new player move to history.
+    async fn insert_move(&self, json: &GameGet);
+
+    /// Get 5 games per page for player.
+    async fn player_games(
+        &self,
+        username: &str,
+        page: u64,
+    ) -> Option<Vec<ProfileGame>>;
+
+    async fn unfinished(&self) -> HashMap<String, ShuuroGame>;
+}
+
+/// Storage for news articles.
+#[async_trait]
+pub trait ArticleRepo: Send + Sync {
+    async fn get_article(&self, id: &str) -> Option<Article>;
+
+    /// Get 10 articles per page, optionally filtered by category.
+    async fn get_articles(
+        &self,
+        category: Option<&String>,
+        page: u64,
+        drafts: bool,
+    ) -> Option<Vec<Article>>;
+
+    async fn add_article(&self, article: &Article) -> bool;
+
+    async fn update_article(&self, article: &Article) -> bool;
+
+    async fn set_article_draft(&self, id: &str, draft: bool) -> bool;
+
+    async fn delete_article(&self, id: &str) -> bool;
+}
+
+/// Storage for game chats, filtered messages and chat reports.
+#[async_trait]
+pub trait ChatRepo: Send + Sync {
+    /// Save new chat line for game.
+    async fn add_line(&self, id: &str, m: &ChatMsg);
+
+    /// Remove deleted chat line.
+    async fn delete_line(&self, id: &str, msg_id: u64);
+
+    async fn game_chat(&self, id: &str) -> Option<Vec<ChatMsg>>;
+
+    /// Get chats for all unfinished games.
+    async fn unfinished_chats(
+        &self,
+        ids: Vec<&String>,
+    ) -> HashMap<String, Vec<ChatMsg>>;
+
+    /// Save message hidden by chat filter.
+    async fn add_filtered(&self, msg: &FilteredMsg);
+
+    /// Get last 50 filtered messages.
+    async fn get_filtered(&self) -> Vec<FilteredMsg>;
+
+    async fn add_report(&self, report: &ChatReport) -> bool;
+
+    /// Get 50 unresolved reports, oldest first.
+    async fn get_reports(&self) -> Vec<ChatReport>;
+
+    async fn resolve_report(&self, id: &str) -> bool;
+}
+
+/// Storage for private messages.
+#[async_trait]
+pub trait InboxRepo: Send + Sync {
+    /// Save private message and update conversation for both players.
+    async fn add_message(&self, m: &DirectMessage) -> bool;
+
+    /// Get last 50 conversations for player.
+    async fn conversations(&self, username: &str) -> Vec<Conversation>;
+
+    /// Get 50 messages per page from conversation, newest first.
+    async fn messages(
+        &self,
+        conversation: &str,
+        page: u64,
+    ) -> Vec<DirectMessage>;
+
+    /// Player has read all messages in conversation.
+    async fn read_conversation(&self, conversation: &str, username: &str);
+
+    /// Count unread messages in all conversations.
+    async fn unread_count(&self, username: &str) -> u32;
+}
+
+/// Storage for notifications and their read state.
+#[async_trait]
+pub trait NotificationRepo: Send + Sync {
+    async fn add_notification(&self, n: &Notification) -> bool;
+
+    /// Save notification for game, or refresh unread one for the same game.
+    async fn upsert_game_notification(
+        &self,
+        n: &Notification,
+    ) -> Option<Notification>;
+
+    /// Get 20 notifications per page for player, newest first.
+    /// Each one is returned with read state for player.
+    async fn get_notifications(
+        &self,
+        username: &str,
+        page: u64,
+    ) -> Vec<(Notification, bool)>;
+
+    /// Mark one or all notifications as read.
+    async fn read_notifications(
+        &self,
+        username: &str,
+        id: Option<&str>,
+    ) -> bool;
+
+    /// Count unread notifications for player.
+    async fn unread_notifications(&self, username: &str) -> u64;
+}
+
+/// Storage for user sessions.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Get session if it exist.
+    async fn get_session(&self, key: &str) -> Option<UserSession>;
+
+    /// Save session if it's new or if saving is forced.
+    async fn set_session(
+        &self,
+        key: &str,
+        value: UserSession,
+        force_set: bool,
+    ) -> UserSession;
+}
+
+// MONGODB PART
+
+#[async_trait]
+impl PlayerRepo for Collection<Player> {
+    async fn create_player(&self) -> Result<String, StorageError> {
+        timed("mongo", "create_player", queries::create_player(self)).await
+    }
+
+    async fn player_exist(
+        &self,
+        username: &str,
+        session: &UserSession,
+    ) -> Option<UserSession> {
+        timed(
+            "mongo",
+            "player_exist",
+            queries::player_exist(self, username, session),
+        )
+        .await
+    }
+
+    async fn get_player(&self, username: &str) -> Option<Player> {
+        timed("mongo", "get_player", queries::get_player(self, username)).await
+    }
+
+    async fn set_rating(&self, username: &str, key: &str, rating: &Rating) {
+        timed(
+            "mongo",
+            "set_rating",
+            queries::set_rating(self, username, key, rating),
+        )
+        .await
+    }
+
+    async fn get_leaderboard(&self, key: &str, since: DateTime) -> Vec<Player> {
+        timed(
+            "mongo",
+            "get_leaderboard",
+            queries::get_leaderboard(self, key, since),
+        )
+        .await
+    }
+
+    async fn set_mute(&self, username: &str, until: Option<DateTime>) -> bool {
+        timed(
+            "mongo",
+            "set_mute",
+            queries::set_mute(self, username, until),
+        )
+        .await
+    }
+
+    async fn set_blocked(
+        &self,
+        username: &str,
+        other: &str,
+        block: bool,
+    ) -> bool {
+        timed(
+            "mongo",
+            "set_blocked",
+            queries::set_blocked(self, username, other, block),
+        )
+        .await
+    }
+
+    async fn set_following(
+        &self,
+        username: &str,
+        other: &str,
+        follow: bool,
+    ) -> bool {
+        timed(
+            "mongo",
+            "set_following",
+            queries::set_following(self, username, other, follow),
+        )
+        .await
+    }
+
+    async fn followers(&self, username: &str) -> Vec<String> {
+        timed("mongo", "followers", queries::get_followers(self, username))
+            .await
+    }
+}
+
+#[async_trait]
+impl GameRepo for Collection<ShuuroGame> {
+    async fn get_game(&self, id: &str) -> Option<ShuuroGame> {
+        timed("mongo", "get_game", queries::get_game_db(self, id)).await
+    }
+
+    async fn add_game(
+        &self,
+        game: &mut ShuuroGame,
+    ) -> Result<(), StorageError> {
+        timed("mongo", "add_game", queries::add_game_to_db(self, game)).await
+    }
+
+    async fn player_game_id(&self, id: &str) -> Option<String> {
+        timed(
+            "mongo",
+            "player_game_id",
+            queries::get_player_game_id(self, id),
+        )
+        .await
+    }
+
+    async fn update_game(&self, game: &ShuuroGame) {
+        timed(
+            "mongo",
+            "update_game",
+            queries::update_entire_game(self, game),
+        )
+        .await
+    }
+
+    async fn insert_move(&self, json: &GameGet) {
+        timed("mongo", "insert_move", queries::insert_move(self, json)).await
+    }
+
+    async fn player_games(
+        &self,
+        username: &str,
+        page: u64,
+    ) -> Option<Vec<ProfileGame>> {
+        timed(
+            "mongo",
+            "player_games",
+            queries::get_player_games(self, username, page),
+        )
+        .await
+    }
+
+    async fn unfinished(&self) -> HashMap<String, ShuuroGame> {
+        timed("mongo", "unfinished", queries::unfinished(self)).await
+    }
+}
+
+#[async_trait]
+impl ArticleRepo for Collection<Article> {
+    async fn get_article(&self, id: &str) -> Option<Article> {
+        timed("mongo", "get_article", queries::get_article(self, id)).await
+    }
+
+    async fn get_articles(
+        &self,
+        category: Option<&String>,
+        page: u64,
+        drafts: bool,
+    ) -> Option<Vec<Article>> {
+        timed(
+            "mongo",
+            "get_articles",
+            queries::get_articles(self, category, page, drafts),
+        )
+        .await
+    }
+
+    async fn add_article(&self, article: &Article) -> bool {
+        timed("mongo", "add_article", queries::add_article(self, article)).await
+    }
+
+    async fn update_article(&self, article: &Article) -> bool {
+        timed(
+            "mongo",
+            "update_article",
+            queries::update_article(self, article),
+        )
+        .await
+    }
+
+    async fn set_article_draft(&self, id: &str, draft: bool) -> bool {
+        timed(
+            "mongo",
+            "set_article_draft",
+            queries::set_article_draft(self, id, draft),
+        )
+        .await
+    }
+
+    async fn delete_article(&self, id: &str) -> bool {
+        timed("mongo", "delete_article", queries::delete_article(self, id))
+            .await
+    }
+}
+
+#[async_trait]
+impl ChatRepo for Mongo {
+    async fn add_line(&self, id: &str, m: &ChatMsg) {
+        timed(
+            "mongo",
+            "add_chat_line",
+            queries::add_chat_line(&self.chat, id, m),
+        )
+        .await
+    }
+
+    async fn delete_line(&self, id: &str, msg_id: u64) {
+        timed(
+            "mongo",
+            "delete_chat_line",
+            queries::delete_chat_line(&self.chat, id, msg_id),
+        )
+        .await
+    }
+
+    async fn game_chat(&self, id: &str) -> Option<Vec<ChatMsg>> {
+        timed("mongo", "game_chat", queries::get_game_chat(&self.chat, id))
+            .await
+    }
+
+    async fn unfinished_chats(
+        &self,
+        ids: Vec<&String>,
+    ) -> HashMap<String, Vec<ChatMsg>> {
+        timed(
+            "mongo",
+            "unfinished_chats",
+            queries::unfinished_chats(&self.chat, ids),
+        )
+        .await
+    }
+
+    async fn add_filtered(&self, msg: &FilteredMsg) {
+        timed(
+            "mongo",
+            "add_filtered",
+            queries::add_filtered(&self.filtered, msg),
+        )
+        .await
+    }
+
+    async fn get_filtered(&self) -> Vec<FilteredMsg> {
+        timed(
+            "mongo",
+            "get_filtered",
+            queries::get_filtered(&self.filtered),
+        )
+        .await
+    }
+
+    async fn add_report(&self, report: &ChatReport) -> bool {
+        timed(
+            "mongo",
+            "add_report",
+            queries::add_report(&self.reports, report),
+        )
+        .await
+    }
+
+    async fn get_reports(&self) -> Vec<ChatReport> {
+        timed("mongo", "get_reports", queries::get_reports(&self.reports)).await
+    }
+
+    async fn resolve_report(&self, id: &str) -> bool {
+        timed(
+            "mongo",
+            "resolve_report",
+            queries::resolve_report(&self.reports, id),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl InboxRepo for Mongo {
+    async fn add_message(&self, m: &DirectMessage) -> bool {
+        timed(
+            "mongo",
+            "add_direct_message",
+            queries::add_direct_message(&self.conversations, &self.messages, m),
+        )
+        .await
+    }
+
+    async fn conversations(&self, username: &str) -> Vec<Conversation> {
+        timed(
+            "mongo",
+            "conversations",
+            queries::get_conversations(&self.conversations, username),
+        )
+        .await
+    }
+
+    async fn messages(
+        &self,
+        conversation: &str,
+        page: u64,
+    ) -> Vec<DirectMessage> {
+        timed(
+            "mongo",
+            "direct_messages",
+            queries::get_direct_messages(&self.messages, conversation, page),
+        )
+        .await
+    }
+
+    async fn read_conversation(&self, conversation: &str, username: &str) {
+        timed(
+            "mongo",
+            "read_conversation",
+            queries::read_conversation(
+                &self.conversations,
+                conversation,
+                username,
+            ),
+        )
+        .await
+    }
+
+    async fn unread_count(&self, username: &str) -> u32 {
+        timed(
+            "mongo",
+            "unread_count",
+            queries::unread_count(&self.conversations, username),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl NotificationRepo for Mongo {
+    async fn add_notification(&self, n: &Notification) -> bool {
+        timed(
+            "mongo",
+            "add_notification",
+            queries::add_notification(&self.notifications, n),
+        )
+        .await
+    }
+
+    async fn upsert_game_notification(
+        &self,
+        n: &Notification,
+    ) -> Option<Notification> {
+        timed(
+            "mongo",
+            "upsert_game_notification",
+            queries::upsert_game_notification(&self.notifications, n),
+        )
+        .await
+    }
+
+    async fn get_notifications(
+        &self,
+        username: &str,
+        page: u64,
+    ) -> Vec<(Notification, bool)> {
+        let (db, reads) = (&self.notifications, &self.notification_reads);
+        timed(
+            "mongo",
+            "get_notifications",
+            queries::get_notifications(db, reads, username, page),
+        )
+        .await
+    }
+
+    async fn read_notifications(
+        &self,
+        username: &str,
+        id: Option<&str>,
+    ) -> bool {
+        let (db, reads) = (&self.notifications, &self.notification_reads);
+        timed(
+            "mongo",
+            "read_notifications",
+            queries::read_notifications(db, reads, username, id),
+        )
+        .await
+    }
+
+    async fn unread_notifications(&self, username: &str) -> u64 {
+        let (db, reads) = (&self.notifications, &self.notification_reads);
+        timed(
+            "mongo",
+            "unread_notifications",
+            queries::unread_notifications(db, reads, username),
+        )
+        .await
+    }
+}
@@ -0,0 +1,142 @@
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json, Router,
+};
+use bson::{doc, Document};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    database::queries::explore, metrics::timed,
+    websockets::server_messages::unsupported, AppState,
+};
+
+pub fn explorer() -> Router<AppState> {
+    Router::new()
+        .route("/shop", get(shop))
+        .route("/placement", get(placement))
+}
+
+/// Query for opening explorer. Moves are separated with comma.
+#[derive(Deserialize)]
+pub struct ExplorerQuery {
+    pub variant: Option<String>,
+    #[serde(default)]
+    pub moves: String,
+    #[serde(default = "white")]
+    pub color: String,
+    pub min_rating: Option<i32>,
+    pub max_rating: Option<i32>,
+    /// Starting deploy sfen, only board with plinths is used.
+    pub position: Option<String>,
+}
+
+fn white() -> String {
+    String::from("white")
+}
+
+impl ExplorerQuery {
+    /// Index of color for which results are shown.
+    fn color(&self) -> usize {
+        usize::from(self.color == "black")
+    }
+
+    /// Plinth layout followed by placement moves.
+    /// Without position, next choices are plinth layouts.
+    fn placement_prefix(&self) -> Vec<String> {
+        match &self.position {
+            Some(position) => {
+                let layout = position.split(' ').next().unwrap_or_default();
+                let mut prefix = vec![String::from(layout)];
+                prefix.append(&mut self.prefix());
+                prefix
+            }
+            None => vec![],
+        }
+    }
+
+    fn prefix(&self) -> Vec<String> {
+        self.moves
+            .split(',')
+            .map(|m| m.trim())
+            .filter(|m| !m.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /// Only finished games are used.
+    fn filter(&self) -> Document {
+        let mut filter = doc! {"status": {"$gt": 0}};
+        if let Some(variant) = &self.variant {
+            filter.insert("variant", variant);
+        }
+        let mut band = vec![];
+        if let Some(min) = self.min_rating {
+            band.push(doc! {"$gte": [{"$avg": "$ratings"}, min]});
+        }
+        if let Some(max) = self.max_rating {
+            band.push(doc! {"$lte": [{"$avg": "$ratings"}, max]});
+        }
+        if !band.is_empty() {
+            filter.insert("ratings", doc! {"$ne": null});
+            filter.insert("$expr", doc! {"$and": band});
+        }
+        filter
+    }
+}
+
+/// Next purchases for one color.
+pub async fn shop(
+    Query(query): Query<ExplorerQuery>,
+    State(state): State<AppState>,
+) -> Json<Value> {
+    let color = query.color();
+    let db = match &state.db.mongo {
+        Some(mongo) => &mongo.games,
+        None => return Json(unsupported()),
+    };
+    let prefix = query.prefix();
+    let moves = explore(db, query.filter(), 0, Some(color), &prefix);
+    let moves = timed("mongo", "explore_shop", moves).await;
+    Json(serde_json::json!({"exist": true, "moves": with_ratio(moves, color)}))
+}
+
+/// Next placements on plinth layout after placement moves, like `Q@d1`.
+pub async fn placement(
+    Query(query): Query<ExplorerQuery>,
+    State(state): State<AppState>,
+) -> Json<Value> {
+    let color = query.color();
+    let db = match &state.db.mongo {
+        Some(mongo) => &mongo.games,
+        None => return Json(unsupported()),
+    };
+    let prefix = query.placement_prefix();
+    let moves = explore(db, query.filter(), 1, None, &prefix);
+    let moves = timed("mongo", "explore_placement", moves).await;
+    Json(serde_json::json!({"exist": true, "moves": with_ratio(moves, color)}))
+}
+
+/// Add win, draw and loss ratio for selected color.
+fn with_ratio(moves: Vec<Value>, color: usize) -> Vec<Value> {
+    moves
+        .into_iter()
+        .map(|mut m| {
+            let count = |key: &str| m[key].as_f64().unwrap_or(0.0);
+            let total = count("total").max(1.0);
+            let (won, lost) = {
+                if color == 0 {
+                    (count("white"), count("black"))
+                } else {
+                    (count("black"), count("white"))
+                }
+            };
+            let draws = count("draws");
+            m["win"] = Value::from(won / total);
+            m["draw"] = Value::from(draws / total);
+            m["loss"] = Value::from(lost / total);
+            m
+        })
+        .collect()
+}
@@ -1,25 +1,28 @@
 use reqwest::{Client, Url};
 
 use super::{
-    curr_url,
     login_helpers::{create_challenge, create_verifier},
     LoginData, PostLoginToken, Token,
 };
+use crate::config::Config;
 use base64::encode;
-use rand::Rng;
+use rand::{distributions::Alphanumeric, Rng};
 
 /// Start of login process.
-pub fn login_url(login_state: &str, prod: bool) -> (Url, String) {
-    let url = "https://lichess.org/oauth?";
+pub fn login_url(
+    config: &Config,
+    login_state: &str,
+    prod: bool,
+) -> (Url, String) {
     let verifier: String = create_verifier();
     let challenge: String = create_challenge(&verifier);
-    let mut final_url = Url::parse(url).unwrap();
-    let r = format!("{}/callback", curr_url(prod).0);
+    let mut final_url = Url::parse(&config.lichess.oauth_url).unwrap();
+    let r = format!("{}/callback", config.curr_url(prod).0);
 
     let queries = [
         ("state", login_state),
         ("response_type", "code"),
-        ("client_id", "lishuuro"),
+        ("client_id", &config.lichess.client_id),
         ("redirect_uri", &r),
         ("code_challenge", &challenge[..]),
         ("code_challenge_method", "S256"),
@@ -46,17 +49,47 @@ pub fn random_game_id() -> String {
     encode(rand::thread_rng().gen::<[u8; 10]>()).replace(['+', '/', '='], "")
 }
 
+/// Length of public game id.
+pub const GAME_ID_LEN: usize = 8;
+
+/// Length of private player id. It's not derived from public game id,
+/// so it can't be guessed from spectator link.
+pub const PLAYER_ID_LEN: usize = 16;
+
+/// Generate public game id, used in spectator URL.
+pub fn public_game_id() -> String {
+    random_chars(GAME_ID_LEN)
+}
+
+/// Generate private ids for both players of game.
+pub fn player_ids() -> [String; 2] {
+    [0, 1].map(|_| random_chars(PLAYER_ID_LEN))
+}
+
+/// `id` looks like private player id.
+pub fn is_player_id(id: &str) -> bool {
+    id.len() == PLAYER_ID_LEN && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn random_chars(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
 /// Getting lichess token.
 pub async fn get_lichess_token(
+    config: &Config,
     code: &String,
     code_verifier: &String,
     prod: bool,
 ) -> Token {
-    let url = "https://lichess.org/api/token";
     let body = PostLoginToken::new(code_verifier, code);
-    let body = body.to_json(prod);
+    let body = body.to_json(config, prod);
     let client = Client::default();
-    let req = client.post(url).json(&body).send();
+    let req = client.post(&config.lichess.token_url).json(&body).send();
     if let Ok(i) = req.await {
         let json = i.json::<Token>().await;
 
@@ -68,11 +101,10 @@ pub async fn get_lichess_token(
 }
 
 /// If user exist then we have login data.
-pub async fn get_lichess_user(token: String) -> String {
-    let url = "https://lichess.org/api/account";
+pub async fn get_lichess_user(config: &Config, token: String) -> String {
     let client = Client::default();
     let res = client
-        .get(url)
+        .get(&config.lichess.account_url)
         .header("Authorization", format!("Bearer {}", token))
         .send()
         .await;
@@ -3,7 +3,7 @@ use std::env;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::database::redis::CookieValue;
+use crate::{config::Config, database::redis::CookieValue};
 
 pub mod login;
 pub mod login_helpers;
@@ -25,14 +25,14 @@ impl PostLoginToken {
     }
 
     /// Function used to post.
-    pub fn to_json(&self, prod: bool) -> Value {
-        let uri = curr_url(prod);
+    pub fn to_json(&self, config: &Config, prod: bool) -> Value {
+        let uri = config.curr_url(prod);
         let uri = format!("{}/callback", uri.0);
 
         serde_json::json!({
             "grant_type": "authorization_code",
             "redirect_uri": uri.as_str(),
-            "client_id": "lishuuro",
+            "client_id": &config.lichess.client_id,
             "code": self.code,
             "code_verifier": self.code_verifier
         })
@@ -84,15 +84,6 @@ impl Default for MyKey {
     }
 }
 
-/// My server url.
-pub fn curr_url(prod: bool) -> (&'static str, &'static str) {
-    if prod {
-        ("https://lishuuro.org/w", "https://lishuuro.org")
-    } else {
-        ("http://localhost:8080", "http://localhost:3000")
-    }
-}
-
 pub fn cookies(prod: bool) -> CookieValue {
     if prod {
         CookieValue::new("None", "true", "true")
@@ -0,0 +1,77 @@
+use std::sync::OnceLock;
+
+use axum::{http::StatusCode, routing::get, Json, Router};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter, Registry};
+
+use crate::{
+    config::LogConfig,
+    database::redis::{OptionalSession, UserSession},
+    AppState,
+};
+
+/// Handle for changing filter while server is running.
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Install global subscriber. Filter can be changed later by admin.
+pub fn init(config: &LogConfig) {
+    let (filter, handle) = reload::Layer::new(EnvFilter::new(&config.level));
+    let registry = tracing_subscriber::registry().with(filter);
+    if config.json {
+        let layer = fmt::layer().json().with_current_span(true);
+        registry.with(layer.with_span_list(true)).init();
+    } else {
+        registry.with(fmt::layer()).init();
+    }
+    let _ = FILTER.set(handle);
+}
+
+/// Current filter, if logging is enabled.
+pub fn current_filter() -> Option<String> {
+    FILTER.get()?.with_current(|f| f.to_string()).ok()
+}
+
+/// Replace filter, for example with `info,lishuuro=debug`.
+pub fn set_filter(directives: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+    let handle = FILTER.get().ok_or("logging is not enabled")?;
+    handle.reload(filter).map_err(|e| e.to_string())
+}
+
+pub fn logging() -> Router<AppState> {
+    Router::new().route("/", get(filter).put(change_filter))
+}
+
+#[derive(Deserialize)]
+pub struct FilterForm {
+    pub filter: String,
+}
+
+/// Get current log filter.
+pub async fn filter(user: OptionalSession) -> Result<Json<Value>, StatusCode> {
+    if !user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(Json(serde_json::json!({"filter": current_filter()})))
+}
+
+/// Change log filter without restarting server.
+pub async fn change_filter(
+    user: UserSession,
+    Json(form): Json<FilterForm>,
+) -> Result<Json<Value>, StatusCode> {
+    if !user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if let Err(e) = set_filter(&form.filter) {
+        tracing::warn!(filter = %form.filter, error = %e, "bad log filter");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    tracing::info!(
+        filter = %form.filter,
+        by = %user.username,
+        "log filter changed"
+    );
+    Ok(Json(serde_json::json!({"ok": true, "filter": form.filter})))
+}
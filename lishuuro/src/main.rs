@@ -1,4 +1,4 @@
-use axum::{http::HeaderValue, routing::get, Router};
+use axum::{http::HeaderValue, middleware, routing::get, Router};
 
 use std::{
     net::SocketAddr,
@@ -6,44 +6,87 @@ use std::{
 };
 use tokio::sync::Mutex as Mutex2;
 use tower_http::cors::CorsLayer;
+use tracing::error;
 
+mod config;
 mod database;
+mod explorer;
 mod lichess;
+mod logging;
+mod metrics;
+mod news;
+mod notifications;
 mod nuxt;
+mod puzzles;
 mod routes;
+#[cfg(test)]
+mod tests;
+mod users;
 mod websockets;
 
-use lichess::{curr_url, MyKey};
+use explorer::explorer;
+use lichess::MyKey;
+use logging::logging;
+use metrics::{admin_metrics, metrics_router};
+use news::news;
+use notifications::notifications;
 use nuxt::nuxt;
-use routes::{article, callback, get_games, login, vue_user};
+use puzzles::{mining::mine_puzzles, puzzles};
+use routes::{
+    callback, find_games, game_chat, get_games, hints, leaderboard, login,
+    vue_user,
+};
+use users::users;
 
 use crate::{
+    config::Config,
     database::Database,
-    websockets::{websocket_handler, WsState},
+    websockets::{
+        clock::system_clock, leaderboards::run_leaderboards,
+        rate_limit::limit_http, scheduler::run_scheduler, websocket_handler,
+        WsState,
+    },
 };
 
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
-    let db = Database::new().await;
-    let cors_layer = cors(&db.key);
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("config error: {e}");
+            std::process::exit(1);
+        }
+    };
+    logging::init(&config.log);
+    let db = Database::new(&config).await;
+    if std::env::args().any(|arg| arg == "--mine-puzzles") {
+        match &db.mongo {
+            Some(mongo) => mine_puzzles(mongo).await,
+            None => error!("puzzles can't be mined without MongoDB"),
+        }
+        return;
+    }
     let db = Arc::new(db);
-    let ws = Arc::new(WsState::default());
-    ws.load_unfinished(&db.mongo.games).await;
-    let state = AppState::new(db, ws);
-    let app = Router::new()
-        .route("/login", get(login))
-        .route("/callback", get(callback))
-        .route("/vue_user", get(vue_user))
-        .route("/ws/", get(websocket_handler))
-        .route("/news/:id", get(article))
-        .route("/games/:username/:page", get(get_games))
-        .nest("/nuxt", nuxt())
-        .with_state(state)
-        .layer(cors_layer);
-    let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let ws = Arc::new(WsState::new(&config, system_clock()));
+    ws.load_unfinished(&db).await;
+    ws.load_leaderboards(&db).await;
+    tokio::spawn(run_scheduler(ws.clone(), db.clone()));
+    tokio::spawn(run_leaderboards(ws.clone(), db.clone()));
+    let addr = config.bind_addr();
+    let state = AppState::new(db, ws, config);
+    if let Some(metrics_addr) = state.config.metrics_addr() {
+        let admin = metrics_router().with_state(state.clone());
+        tokio::spawn(async move {
+            axum::Server::bind(&metrics_addr)
+                .serve(admin.into_make_service())
+                .await
+                .unwrap();
+        });
+    }
+    let app = app(state);
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
@@ -52,16 +95,51 @@ async fn main() {
 pub struct AppState {
     pub db: Arc<Database>,
     pub ws: Arc<WsState>,
+    pub config: Arc<Config>,
 }
 
 impl AppState {
-    pub fn new(db: Arc<Database>, ws: Arc<WsState>) -> Self {
-        Self { db, ws }
+    pub fn new(
+        db: Arc<Database>,
+        ws: Arc<WsState>,
+        config: Arc<Config>,
+    ) -> Self {
+        Self { db, ws, config }
     }
 }
 
-fn cors(key: &MyKey) -> CorsLayer {
-    let addr = curr_url(key.prod);
+/// All routes with state.
+pub fn app(state: AppState) -> Router {
+    let cors_layer = cors(&state.config, &state.db.key);
+    let limits = middleware::from_fn_with_state(state.clone(), limit_http);
+    let mut router = Router::new();
+    if state.config.metrics_bind.is_none() {
+        router = router.route("/metrics", get(admin_metrics));
+    }
+    router
+        .route("/login", get(login))
+        .route("/callback", get(callback))
+        .route("/vue_user", get(vue_user))
+        .route("/ws/", get(websocket_handler))
+        .route("/games/search", get(find_games))
+        .route("/games/hints", get(hints))
+        .route("/games/:username/:page", get(get_games))
+        .route("/chat/:id", get(game_chat))
+        .route("/leaderboard/:variant/:speed", get(leaderboard))
+        .nest("/explorer", explorer())
+        .nest("/logging", logging())
+        .nest("/news", news())
+        .nest("/notifications", notifications())
+        .nest("/nuxt", nuxt())
+        .nest("/puzzles", puzzles())
+        .nest("/users", users())
+        .with_state(state)
+        .layer(limits)
+        .layer(cors_layer)
+}
+
+fn cors(config: &Config, key: &MyKey) -> CorsLayer {
+    let addr = config.curr_url(key.prod);
     let cors = CorsLayer::new();
     cors.allow_origin(addr.1.parse::<HeaderValue>().unwrap())
         .allow_credentials(true)
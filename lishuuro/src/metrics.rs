@@ -0,0 +1,267 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    future::Future,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+
+use crate::{database::redis::OptionalSession, websockets::WsState, AppState};
+
+/// Counters shared by all connections and database queries.
+pub static METRICS: Metrics = Metrics::new();
+
+/// Labels above this limit are counted as `other`.
+/// Message types come from clients, so they can be anything.
+const MAX_LABELS: usize = 64;
+
+/// Upper bounds for query latency, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+pub struct Metrics {
+    sockets: AtomicI64,
+    messages_in: Counters,
+    messages_out: Counters,
+    broadcast_lagged: AtomicU64,
+    broadcast_dropped: AtomicU64,
+    game_results: Counters,
+    queries: Mutex<BTreeMap<(&'static str, &'static str), Histogram>>,
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self {
+            sockets: AtomicI64::new(0),
+            messages_in: Counters::new(),
+            messages_out: Counters::new(),
+            broadcast_lagged: AtomicU64::new(0),
+            broadcast_dropped: AtomicU64::new(0),
+            game_results: Counters::new(),
+            queries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn socket_opened(&self) {
+        self.sockets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn socket_closed(&self) {
+        self.sockets.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Message received from client.
+    pub fn message_in(&self, t: &str) {
+        self.messages_in.inc(t);
+    }
+
+    /// Message sent to client.
+    pub fn message_out(&self, t: &str) {
+        self.messages_out.inc(t);
+    }
+
+    /// Slow receiver missed `skipped` messages.
+    pub fn broadcast_lagged(&self, skipped: u64) {
+        self.broadcast_lagged.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    /// Message was sent while nobody was connected.
+    pub fn broadcast_dropped(&self) {
+        self.broadcast_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn game_result(&self, status: i32) {
+        self.game_results.inc(&status.to_string());
+    }
+
+    pub fn observe_query(
+        &self,
+        backend: &'static str,
+        query: &'static str,
+        secs: f64,
+    ) {
+        let mut queries = self.queries.lock().unwrap();
+        queries.entry((backend, query)).or_default().observe(secs);
+    }
+
+    /// All metrics in Prometheus text format.
+    pub fn render(&self, ws: &WsState) -> String {
+        let mut out = String::new();
+
+        let name = "lishuuro_live_games";
+        header(&mut out, name, "gauge", "Live games.");
+        for ((variant, stage), count) in ws.shuuro_games.stage_counts() {
+            let variant = escape(&variant);
+            let labels = format!("variant=\"{variant}\",stage=\"{stage}\"");
+            let _ = writeln!(out, "{name}{{{labels}}} {count}");
+        }
+
+        let name = "lishuuro_sockets";
+        header(&mut out, name, "gauge", "Open websockets.");
+        let sockets = self.sockets.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name} {sockets}");
+
+        let name = "lishuuro_online_players";
+        header(&mut out, name, "gauge", "Online players.");
+        let online = ws.players.get_online().len();
+        let _ = writeln!(out, "{name} {online}");
+
+        let name = "lishuuro_lobby_seeks";
+        header(&mut out, name, "gauge", "Seeks in lobby.");
+        let _ = writeln!(out, "{name} {}", ws.game_reqs.count());
+
+        let name = "lishuuro_messages_in_total";
+        header(&mut out, name, "counter", "Messages from clients.");
+        self.messages_in.render(&mut out, name, "type");
+
+        let name = "lishuuro_messages_out_total";
+        header(&mut out, name, "counter", "Messages sent to clients.");
+        self.messages_out.render(&mut out, name, "type");
+
+        let name = "lishuuro_broadcast_lagged_total";
+        header(&mut out, name, "counter", "Missed by slow sockets.");
+        let lagged = self.broadcast_lagged.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name} {lagged}");
+
+        let name = "lishuuro_broadcast_dropped_total";
+        header(&mut out, name, "counter", "Messages without receivers.");
+        let dropped = self.broadcast_dropped.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name} {dropped}");
+
+        let name = "lishuuro_game_results_total";
+        header(&mut out, name, "counter", "Finished games by status.");
+        self.game_results.render(&mut out, name, "status");
+
+        let name = "lishuuro_query_seconds";
+        header(&mut out, name, "histogram", "Database query latency.");
+        let queries = self.queries.lock().unwrap();
+        for ((backend, query), histogram) in queries.iter() {
+            let labels = format!("backend=\"{backend}\",query=\"{query}\"");
+            histogram.render(&mut out, name, &labels);
+        }
+
+        out
+    }
+}
+
+/// Counters with one label.
+struct Counters {
+    all: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            all: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn inc(&self, label: &str) {
+        let mut all = self.all.lock().unwrap();
+        if let Some(count) = all.get_mut(label) {
+            *count += 1;
+        } else if all.len() < MAX_LABELS {
+            all.insert(String::from(label), 1);
+        } else {
+            *all.entry(String::from("other")).or_default() += 1;
+        }
+    }
+
+    fn render(&self, out: &mut String, name: &str, label: &str) {
+        for (value, count) in self.all.lock().unwrap().iter() {
+            let value = escape(value);
+            let _ = writeln!(out, "{name}{{{label}=\"{value}\"}} {count}");
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (count, bound) in self.buckets.iter().zip(BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels},le=\"{bound}\"}} {count}"
+            );
+        }
+        let count = self.count;
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {count}");
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Escape label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Run database query and record how long it took.
+pub async fn timed<T>(
+    backend: &'static str,
+    query: &'static str,
+    fut: impl Future<Output = T>,
+) -> T {
+    let start = Instant::now();
+    let res = fut.await;
+    let secs = start.elapsed().as_secs_f64();
+    METRICS.observe_query(backend, query, secs);
+    res
+}
+
+/// Router for separate admin address.
+pub fn metrics_router() -> Router<AppState> {
+    Router::new().route("/metrics", get(metrics))
+}
+
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let body = METRICS.render(&state.ws);
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+/// Metrics on public address are only for admins.
+pub async fn admin_metrics(
+    state: State<AppState>,
+    user: OptionalSession,
+) -> Result<impl IntoResponse, StatusCode> {
+    if !user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(metrics(state).await)
+}
@@ -0,0 +1,289 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{NaiveDate, Utc};
+use pulldown_cmark::{html::push_html, Options, Parser};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    database::{
+        mongo::{Article, NotificationKind},
+        redis::{OptionalSession, UserSession},
+    },
+    lichess::login::random_game_id,
+    notifications::{notify, ALL_USERS},
+    AppState,
+};
+
+pub fn news() -> Router<AppState> {
+    Router::new()
+        .route("/", get(articles).post(new_article))
+        .route("/feed", get(feed))
+        .route(
+            "/:id",
+            get(article).put(edit_article).delete(remove_article),
+        )
+        .route("/:id/publish", post(publish))
+        .route("/:id/unpublish", post(unpublish))
+}
+
+/// Query for list of articles.
+#[derive(Deserialize)]
+pub struct ArticlesQuery {
+    pub category: Option<String>,
+    #[serde(default)]
+    pub page: u64,
+    #[serde(default)]
+    pub drafts: bool,
+}
+
+/// Data sent by admin when creating or editing article.
+#[derive(Deserialize)]
+pub struct ArticleForm {
+    pub title: String,
+    pub category: String,
+    pub headline: String,
+    pub text: String,
+}
+
+impl ArticleForm {
+    /// Check if all fields are filled.
+    fn is_valid(&self) -> bool {
+        !self.title.trim().is_empty()
+            && !self.category.trim().is_empty()
+            && !self.text.trim().is_empty()
+    }
+}
+
+/// Get article.
+pub async fn article(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    user: OptionalSession,
+) -> Json<Value> {
+    if let Some(article) = state.db.articles.get_article(&id).await {
+        if !article.draft || user.is_admin() {
+            return Json(serde_json::json!({"exist": true, "news": article}));
+        }
+    }
+    Json(serde_json::json!({"exist": false}))
+}
+
+/// Get 10 articles. Drafts are visible only to admins.
+pub async fn articles(
+    Query(query): Query<ArticlesQuery>,
+    State(state): State<AppState>,
+    user: OptionalSession,
+) -> Json<Value> {
+    let drafts = query.drafts && user.is_admin();
+    let db = &state.db.articles;
+    if let Some(news) = db
+        .get_articles(query.category.as_ref(), query.page, drafts)
+        .await
+    {
+        return Json(serde_json::json!({"exist": true, "news": news}));
+    }
+    Json(serde_json::json!({"exist": false}))
+}
+
+/// Create new article as draft.
+pub async fn new_article(
+    State(state): State<AppState>,
+    user: UserSession,
+    Json(form): Json<ArticleForm>,
+) -> Result<Json<Value>, StatusCode> {
+    if !user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    } else if !form.is_valid() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let db = &state.db.articles;
+    let mut id = slug(&form.title);
+    if id.is_empty() {
+        id = String::from(&random_game_id()[0..5]);
+    } else if db.get_article(&id).await.is_some() {
+        id = format!("{}-{}", id, &random_game_id()[0..5]);
+    }
+    let article = Article {
+        _id: id,
+        title: form.title,
+        user: String::from(&user.username),
+        date: Utc::now().format("%Y-%m-%d").to_string(),
+        category: form.category,
+        html: render_markdown(&form.text),
+        text: form.text,
+        headline: form.headline,
+        draft: true,
+    };
+    if db.add_article(&article).await {
+        return Ok(Json(serde_json::json!({"ok": true, "news": article})));
+    }
+    Err(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Edit existing article.
+pub async fn edit_article(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    user: UserSession,
+    Json(form): Json<ArticleForm>,
+) -> Result<Json<Value>, StatusCode> {
+    if !user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    } else if !form.is_valid() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let db = &state.db.articles;
+    if let Some(mut article) = db.get_article(&id).await {
+        article.title = form.title;
+        article.category = form.category;
+        article.headline = form.headline;
+        article.html = render_markdown(&form.text);
+        article.text = form.text;
+        if db.update_article(&article).await {
+            return Ok(Json(serde_json::json!({"ok": true, "news": article})));
+        }
+    }
+    Err(StatusCode::NOT_FOUND)
+}
+
+/// Delete article.
+pub async fn remove_article(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    user: UserSession,
+) -> Result<Json<Value>, StatusCode> {
+    if !user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if state.db.articles.delete_article(&id).await {
+        return Ok(Json(serde_json::json!({"ok": true})));
+    }
+    Err(StatusCode::NOT_FOUND)
+}
+
+/// Make article visible for everyone.
+pub async fn publish(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    user: UserSession,
+) -> Result<Json<Value>, StatusCode> {
+    change_draft(&id, &state, &user, false).await
+}
+
+/// Return article back to drafts.
+pub async fn unpublish(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    user: UserSession,
+) -> Result<Json<Value>, StatusCode> {
+    change_draft(&id, &state, &user, true).await
+}
+
+async fn change_draft(
+    id: &str,
+    state: &AppState,
+    user: &UserSession,
+    draft: bool,
+) -> Result<Json<Value>, StatusCode> {
+    if !user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let db = &state.db.articles;
+    let article = db.get_article(id).await.ok_or(StatusCode::NOT_FOUND)?;
+    if !db.set_article_draft(id, draft).await {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if article.draft && !draft {
+        let data = serde_json::json!({
+            "id": &article._id,
+            "title": &article.title,
+            "headline": &article.headline
+        });
+        let kind = NotificationKind::ArticlePublished;
+        let db = &state.db.notifications;
+        notify(db, &state.ws.tx, ALL_USERS, kind, data).await;
+    }
+    Ok(Json(serde_json::json!({"ok": true, "draft": draft})))
+}
+
+/// RSS feed with last published articles.
+pub async fn feed(State(state): State<AppState>) -> impl IntoResponse {
+    let site = state.config.curr_url(state.db.key.prod).1;
+    let articles = state
+        .db
+        .articles
+        .get_articles(None, 0, false)
+        .await
+        .unwrap_or_default();
+    let mut items = String::new();
+    for article in articles {
+        let link = format!("{}/news/{}", site, &article._id);
+        items.push_str(&format!(
+            "<item><title>{}</title><link>{}</link><guid>{}</guid>\
+            <category>{}</category><author>{}</author>{}\
+            <description>{}</description></item>",
+            escape_xml(&article.title),
+            link,
+            link,
+            escape_xml(&article.category),
+            escape_xml(&article.user),
+            pub_date(&article.date),
+            escape_xml(&article.headline)
+        ));
+    }
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <rss version=\"2.0\"><channel><title>lishuuro news</title>\
+        <link>{site}/news</link><description>News from lishuuro</description>\
+        {items}</channel></rss>"
+    );
+    ([(CONTENT_TYPE, "application/rss+xml; charset=utf-8")], body)
+}
+
+/// Render markdown and remove all unsafe html.
+pub fn render_markdown(text: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let parser = Parser::new_ext(text, options);
+    let mut html = String::new();
+    push_html(&mut html, parser);
+    ammonia::clean(&html)
+}
+
+/// Create article ID from title.
+fn slug(title: &str) -> String {
+    let title: String = title
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    title
+        .split('-')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+/// RSS date from article date, empty if it can't be parsed.
+fn pub_date(date: &str) -> String {
+    match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        Ok(date) => {
+            let date = date.format("%a, %d %b %Y 00:00:00 +0000");
+            format!("<pubDate>{date}</pubDate>")
+        }
+        Err(_) => String::new(),
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::broadcast::Sender;
+
+use crate::{
+    database::{
+        mongo::{Notification, NotificationKind},
+        redis::{OptionalSession, UserSession},
+        repos::NotificationRepo,
+    },
+    lichess::login::random_game_id,
+    metrics::METRICS,
+    websockets::{server_messages::notification, ClientMessage, SendTo},
+    AppState,
+};
+
+/// Notifications for this user are visible to everyone.
+/// It's not valid username, so no player can own it.
+pub const ALL_USERS: &str = "*";
+
+pub fn notifications() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list))
+        .route("/read", post(read_all))
+        .route("/:id/read", post(read_one))
+}
+
+#[derive(Deserialize)]
+pub struct NotificationsQuery {
+    #[serde(default)]
+    pub page: u64,
+}
+
+/// Save notification and push it to user if connected.
+/// For `ALL_USERS` every connected player gets it.
+pub async fn notify(
+    db: &Arc<dyn NotificationRepo>,
+    tx: &Sender<ClientMessage>,
+    username: &str,
+    kind: NotificationKind,
+    data: Value,
+) {
+    let n = Notification {
+        _id: random_game_id(),
+        user: String::from(username),
+        kind,
+        data,
+        read: false,
+        created_at: bson::DateTime::now(),
+    };
+    let saved = match kind {
+        NotificationKind::MoveMade => db.upsert_game_notification(&n).await,
+        _ => db.add_notification(&n).await.then_some(n),
+    };
+    if let Some(n) = saved {
+        let to = {
+            if username == ALL_USERS {
+                SendTo::All
+            } else {
+                SendTo::User(String::from(username))
+            }
+        };
+        let msg = ClientMessage::system(notification(&n, false), to);
+        if tx.send(msg).is_err() {
+            METRICS.broadcast_dropped();
+        }
+    }
+}
+
+/// Get 20 notifications per page for current user.
+pub async fn list(
+    Query(query): Query<NotificationsQuery>,
+    State(state): State<AppState>,
+    OptionalSession(user): OptionalSession,
+) -> Json<Value> {
+    let user = match user {
+        Some(user) => user,
+        None => return Json(serde_json::json!({"exist": false})),
+    };
+    let db = &state.db.notifications;
+    let all = db.get_notifications(&user.username, query.page).await;
+    let all: Vec<Value> = all
+        .iter()
+        .map(|(n, read)| notification(n, *read)["data"].clone())
+        .collect();
+    Json(serde_json::json!({"exist": true, "notifications": all}))
+}
+
+/// Mark all notifications as read.
+pub async fn read_all(
+    State(state): State<AppState>,
+    user: UserSession,
+) -> Json<Value> {
+    let db = &state.db.notifications;
+    let ok = db.read_notifications(&user.username, None).await;
+    Json(serde_json::json!({ "ok": ok }))
+}
+
+/// Mark one notification as read.
+pub async fn read_one(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    user: UserSession,
+) -> Json<Value> {
+    let db = &state.db.notifications;
+    let ok = db.read_notifications(&user.username, Some(&id)).await;
+    Json(serde_json::json!({ "ok": ok }))
+}
@@ -5,7 +5,7 @@ use axum::{
 };
 use serde_json::Value;
 
-use crate::{database::queries::get_game_db, AppState};
+use crate::AppState;
 
 pub fn nuxt() -> Router<AppState> {
     Router::new().route("/shuuro/:id", get(shuuro))
@@ -16,7 +16,14 @@ pub async fn shuuro(
     Path(id): Path<String>,
     state: State<AppState>,
 ) -> Json<Value> {
-    let game = get_game_db(&state.db.mongo.games, &id).await;
+    let games = state.db.games.as_ref();
+    let id = state
+        .ws
+        .shuuro_games
+        .public_id(&id, games)
+        .await
+        .unwrap_or(id);
+    let game = games.get_game(&id).await;
     if let Some(game) = game {
         Json(
             serde_json::json!({"exist": true, "players": game.players, "result": game.result, "status": game.status}),
@@ -0,0 +1,168 @@
+use std::{io, process::Stdio};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdin, ChildStdout, Command},
+};
+
+/// Evaluation from side to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    Cp(i32),
+    Mate(i32),
+}
+
+impl Score {
+    /// Side to move is clearly winning.
+    pub fn is_winning(&self, cp: i32) -> bool {
+        match self {
+            Score::Cp(score) => *score >= cp,
+            Score::Mate(moves) => *moves > 0,
+        }
+    }
+}
+
+/// One line from multipv search.
+#[derive(Debug, Clone)]
+pub struct EngineLine {
+    pub score: Score,
+    pub pv: Vec<String>,
+}
+
+/// Local UCI engine, for example Fairy-Stockfish.
+pub struct Engine {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl Engine {
+    pub async fn new(path: &str) -> io::Result<Self> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or(io::ErrorKind::BrokenPipe)?;
+        let stdout = child.stdout.take().ok_or(io::ErrorKind::BrokenPipe)?;
+        let mut engine = Self {
+            _child: child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+        };
+        engine.send("uci").await?;
+        engine.wait_for("uciok").await?;
+        Ok(engine)
+    }
+
+    /// Select variant and number of lines.
+    pub async fn set_variant(
+        &mut self,
+        variant: &str,
+        multipv: u8,
+    ) -> io::Result<()> {
+        self.send(&format!("setoption name UCI_Variant value {variant}"))
+            .await?;
+        self.send(&format!("setoption name MultiPV value {multipv}"))
+            .await?;
+        self.send("isready").await?;
+        self.wait_for("readyok").await
+    }
+
+    /// Analyse position, best line is first.
+    pub async fn analyse(
+        &mut self,
+        sfen: &str,
+        depth: u8,
+    ) -> io::Result<Vec<EngineLine>> {
+        self.send(&format!("position fen {sfen}")).await?;
+        self.send(&format!("go depth {depth}")).await?;
+        let mut lines: Vec<Option<EngineLine>> = vec![];
+        while let Some(line) = self.stdout.next_line().await? {
+            if line.starts_with("bestmove") {
+                break;
+            } else if let Some((index, engine_line)) = parse_info(&line) {
+                if lines.len() <= index {
+                    lines.resize(index + 1, None);
+                }
+                lines[index] = Some(engine_line);
+            }
+        }
+        Ok(lines.into_iter().flatten().collect())
+    }
+
+    async fn send(&mut self, command: &str) -> io::Result<()> {
+        self.stdin
+            .write_all(format!("{command}\n").as_bytes())
+            .await?;
+        self.stdin.flush().await
+    }
+
+    async fn wait_for(&mut self, answer: &str) -> io::Result<()> {
+        while let Some(line) = self.stdout.next_line().await? {
+            if line.trim() == answer {
+                return Ok(());
+            }
+        }
+        Err(io::ErrorKind::UnexpectedEof.into())
+    }
+}
+
+/// Parse `info ... multipv 1 score cp 35 ... pv e2e4 e7e5`.
+fn parse_info(line: &str) -> Option<(usize, EngineLine)> {
+    if !line.starts_with("info") {
+        return None;
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let mut index = 0;
+    let mut score = None;
+    let mut pv = vec![];
+    let mut i = 0;
+    while i < words.len() {
+        match words[i] {
+            "multipv" => {
+                index = words.get(i + 1)?.parse::<usize>().ok()?.max(1) - 1;
+                i += 1;
+            }
+            "score" => {
+                let value = words.get(i + 2)?.parse().ok()?;
+                score = match *words.get(i + 1)? {
+                    "cp" => Some(Score::Cp(value)),
+                    "mate" => Some(Score::Mate(value)),
+                    _ => None,
+                };
+                i += 2;
+            }
+            "pv" => {
+                pv = words[i + 1..].iter().map(|m| String::from(*m)).collect();
+                break;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    Some((index, EngineLine { score: score?, pv }))
+}
+
+/// Convert UCI move like `a10a12` to fight move `a10_a12`.
+pub fn uci_to_move(uci: &str) -> Option<String> {
+    let chars: Vec<char> = uci.chars().collect();
+    let split = chars
+        .iter()
+        .enumerate()
+        .skip(1)
+        .find(|(_, c)| c.is_ascii_alphabetic())?
+        .0;
+    let to: String = chars[split..]
+        .iter()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .enumerate()
+        .take_while(|(i, c)| *i == 0 || c.is_ascii_digit())
+        .map(|(_, c)| *c)
+        .collect();
+    let from: String = chars[..split].iter().collect();
+    if from.len() < 2 || to.len() < 2 {
+        return None;
+    }
+    Some(format!("{from}_{to}"))
+}
@@ -0,0 +1,115 @@
+use std::env;
+
+use tracing::{error, info, warn};
+
+use crate::{
+    database::{
+        mongo::{Mongo, Puzzle},
+        queries::{add_puzzle, set_puzzles_scanned, unscanned_games},
+        ratings::Rating,
+    },
+    websockets::VARIANTS,
+};
+
+use super::{
+    engine::{uci_to_move, Engine, EngineLine},
+    fight_positions, play_line,
+};
+
+const DEPTH: u8 = 18;
+/// Best move must be at least this good.
+const WINNING_CP: i32 = 300;
+/// Second best move must be worse than this.
+const SECOND_CP: i32 = 50;
+/// Longest solution, in plies.
+const MAX_PLIES: usize = 7;
+/// Opening moves are skipped.
+const SKIP_PLIES: usize = 4;
+
+/// Scan all finished games and save new puzzles.
+pub async fn mine_puzzles(db: &Mongo) {
+    let path = env::var("ENGINE_PATH").unwrap_or(String::from("stockfish"));
+    let mut engine = match Engine::new(&path).await {
+        Ok(engine) => engine,
+        Err(e) => {
+            warn!(engine = %path, error = %e, "engine not started");
+            return;
+        }
+    };
+    for variant in VARIANTS {
+        if engine.set_variant(variant, 2).await.is_err() {
+            continue;
+        }
+        let games = unscanned_games(&db.games, variant).await;
+        let mut count = 0;
+        'games: for game in games {
+            let positions = fight_positions(variant, &game.history.2);
+            for (ply, sfen) in positions.iter().enumerate() {
+                if ply < SKIP_PLIES {
+                    continue;
+                }
+                let lines = match engine.analyse(sfen, DEPTH).await {
+                    Ok(lines) => lines,
+                    Err(e) => {
+                        error!(
+                            game_id = %game._id,
+                            error = %e,
+                            "engine can't analyse game"
+                        );
+                        continue 'games;
+                    }
+                };
+                if let Some(solution) = solution(variant, sfen, &lines) {
+                    let puzzle = Puzzle {
+                        _id: format!("{}{}", &game._id, ply),
+                        game_id: String::from(&game._id),
+                        variant: String::from(variant),
+                        sfen: String::from(sfen),
+                        solution,
+                        rating: Rating::default(),
+                        plays: 0,
+                    };
+                    if add_puzzle(&db.puzzles, &puzzle).await {
+                        count += 1;
+                    }
+                    // one puzzle per game
+                    break;
+                }
+            }
+            set_puzzles_scanned(&db.games, &game._id).await;
+        }
+        info!(variant = %variant, count, "new puzzles");
+    }
+}
+
+/// Return solution if only one move is clearly winning.
+fn solution(
+    variant: &str,
+    sfen: &str,
+    lines: &[EngineLine],
+) -> Option<Vec<String>> {
+    let best = lines.first()?;
+    if !best.score.is_winning(WINNING_CP) {
+        return None;
+    }
+    if let Some(second) = lines.get(1) {
+        if second.score.is_winning(SECOND_CP) {
+            return None;
+        }
+    }
+    let mut moves: Vec<String> = best
+        .pv
+        .iter()
+        .take(MAX_PLIES)
+        .map_while(|m| uci_to_move(m))
+        .collect();
+    // last move is always from player
+    if moves.len().is_multiple_of(2) {
+        moves.pop();
+    }
+    if moves.is_empty() {
+        return None;
+    }
+    play_line(variant, sfen, &moves)?;
+    Some(moves)
+}
@@ -0,0 +1,261 @@
+use std::{
+    hash::Hash,
+    ops::{BitAnd, BitOr, Not},
+};
+
+use axum::{
+    extract::{Path, State},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use shuuro::{
+    attacks::Attacks,
+    bitboard::BitBoard,
+    position::Position,
+    shuuro12::{
+        attacks12::Attacks12, bitboard12::BB12, position12::P12,
+        square12::Square12,
+    },
+    shuuro8::{
+        attacks8::Attacks8, bitboard8::BB8, position8::P8, square8::Square8,
+    },
+    Move, Square, Variant,
+};
+
+use crate::{
+    database::{
+        mongo::{Mongo, Puzzle, PuzzleAttempt},
+        queries::{
+            add_puzzle_attempt, get_player, get_puzzle, random_puzzle,
+            set_puzzle_rating, set_rating,
+        },
+        ratings::Rating,
+        redis::UserSession,
+    },
+    metrics::timed,
+    websockets::server_messages::{puzzle, puzzle_move, unsupported},
+    AppState,
+};
+
+pub mod engine;
+pub mod mining;
+
+/// Rating key for puzzles.
+pub const PUZZLE_KEY: &str = "puzzle";
+
+pub fn puzzles() -> Router<AppState> {
+    Router::new()
+        .route("/next", get(next))
+        .route("/:id", get(one))
+        .route("/:id/move", post(submit_move))
+}
+
+/// Player move for puzzle. Index is position in solution.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PuzzleMove {
+    pub id: String,
+    pub index: usize,
+    pub game_move: String,
+}
+
+/// Get random puzzle for player.
+pub async fn next(
+    State(state): State<AppState>,
+    user: UserSession,
+) -> Json<Value> {
+    match &state.db.mongo {
+        Some(db) => Json(next_puzzle(db, &user).await),
+        None => Json(unsupported()),
+    }
+}
+
+/// Get puzzle by ID.
+pub async fn one(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Json<Value> {
+    let db = match &state.db.mongo {
+        Some(db) => db,
+        None => return Json(unsupported()),
+    };
+    let p = timed("mongo", "get_puzzle", get_puzzle(&db.puzzles, &id)).await;
+    if let Some(p) = p {
+        return Json(puzzle(&p));
+    }
+    Json(serde_json::json!({"exist": false}))
+}
+
+/// Check one move from solution.
+pub async fn submit_move(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    user: UserSession,
+    Json(mut req): Json<PuzzleMove>,
+) -> Json<Value> {
+    req.id = id;
+    match &state.db.mongo {
+        Some(db) => Json(check_move(db, &user, &req).await),
+        None => Json(unsupported()),
+    }
+}
+
+/// Find puzzle close to player puzzle rating.
+pub async fn next_puzzle(db: &Mongo, user: &UserSession) -> Value {
+    let rating = player_rating(db, user).await;
+    let p = random_puzzle(&db.puzzles, rating.rating);
+    if let Some(p) = timed("mongo", "random_puzzle", p).await {
+        return puzzle(&p);
+    }
+    serde_json::json!({"exist": false})
+}
+
+/// Validate move and return reply from solution.
+pub async fn check_move(
+    db: &Mongo,
+    user: &UserSession,
+    req: &PuzzleMove,
+) -> Value {
+    let p = get_puzzle(&db.puzzles, &req.id);
+    let p = match timed("mongo", "get_puzzle", p).await {
+        Some(p) => p,
+        None => return serde_json::json!({"exist": false}),
+    };
+    if !req.index.is_multiple_of(2) || req.index >= p.solution.len() {
+        return serde_json::json!({"exist": false});
+    }
+    let played = &p.solution[..req.index];
+    let mut moves = played.to_vec();
+    moves.push(String::from(&req.game_move));
+    let correct = match play_line(&p.variant, &p.sfen, &moves) {
+        Some(sfen) => {
+            let expected = &p.solution[..=req.index];
+            play_line(&p.variant, &p.sfen, expected) == Some(sfen)
+        }
+        None => false,
+    };
+    let reply = p.solution.get(req.index + 1).filter(|_| correct);
+    let done = correct && req.index + 2 >= p.solution.len();
+    let mut rating = None;
+    if !correct || done {
+        rating = finish(db, user, &p, correct).await;
+    }
+    puzzle_move(&p._id, correct, reply, done, rating)
+}
+
+async fn player_rating(db: &Mongo, user: &UserSession) -> Rating {
+    let player = get_player(&db.players, &user.username);
+    if let Some(player) = timed("mongo", "get_player", player).await {
+        if let Some(rating) = player.ratings.get(PUZZLE_KEY) {
+            return *rating;
+        }
+    }
+    Rating::default()
+}
+
+/// Update ratings only after first attempt.
+async fn finish(
+    db: &Mongo,
+    user: &UserSession,
+    p: &Puzzle,
+    success: bool,
+) -> Option<f64> {
+    if !user.reg {
+        return None;
+    }
+    let attempt = PuzzleAttempt {
+        _id: format!("{}|{}", &user.username, &p._id),
+        user: String::from(&user.username),
+        puzzle: String::from(&p._id),
+        success,
+        time: bson::DateTime::now(),
+    };
+    let added = add_puzzle_attempt(&db.puzzle_attempts, &attempt);
+    if !timed("mongo", "add_puzzle_attempt", added).await {
+        return None;
+    }
+    let score = if success { 1.0 } else { 0.0 };
+    let rating = player_rating(db, user).await;
+    let new_rating = rating.update(&p.rating, score);
+    let puzzle_rating = p.rating.update(&rating, 1.0 - score);
+    let rating =
+        set_rating(&db.players, &user.username, PUZZLE_KEY, &new_rating);
+    timed("mongo", "set_rating", rating).await;
+    let rating = set_puzzle_rating(&db.puzzles, &p._id, &puzzle_rating);
+    timed("mongo", "set_puzzle_rating", rating).await;
+    Some(new_rating.rating.round())
+}
+
+/// Positions after each fight move, starting with first position.
+/// Fight history has starting SFEN and then moves like `a1_a8_sfen`.
+pub fn fight_positions(variant: &str, history: &[String]) -> Vec<String> {
+    let mut positions: Vec<String> = history.iter().take(1).cloned().collect();
+    for entry in history.iter().skip(1) {
+        let mut parts = entry.splitn(3, '_');
+        let m = match (parts.next(), parts.next()) {
+            (Some(from), Some(to)) => format!("{from}_{to}"),
+            _ => break,
+        };
+        let last = positions.last().unwrap();
+        match play_line(variant, last, &[m]) {
+            Some(sfen) => positions.push(sfen),
+            None => break,
+        }
+    }
+    positions
+}
+
+/// Play all moves from position and return new SFEN.
+pub fn play_line(
+    variant: &str,
+    sfen: &str,
+    moves: &[String],
+) -> Option<String> {
+    if variant.contains("shuuro") {
+        play::<
+            Square12,
+            BB12<Square12>,
+            Attacks12<Square12, BB12<Square12>>,
+            P12<Square12, BB12<Square12>>,
+        >(variant, sfen, moves)
+    } else {
+        play::<
+            Square8,
+            BB8<Square8>,
+            Attacks8<Square8, BB8<Square8>>,
+            P8<Square8, BB8<Square8>>,
+        >(variant, sfen, moves)
+    }
+}
+
+fn play<S, B, A, P>(
+    variant: &str,
+    sfen: &str,
+    moves: &[String],
+) -> Option<String>
+where
+    S: Square + Hash,
+    B: BitBoard<S>,
+    A: Attacks<S, B>,
+    P: Position<S, B, A>,
+    for<'a> &'a B: BitOr<&'a B, Output = B>,
+    for<'a> &'a B: BitAnd<&'a B, Output = B>,
+    for<'a> &'a B: Not<Output = B>,
+    for<'a> &'a B: BitOr<&'a S, Output = B>,
+    for<'a> &'a B: BitAnd<&'a S, Output = B>,
+{
+    let mut position = P::new();
+    position.update_variant(Variant::from(&String::from(variant)));
+    position.set_sfen(sfen).ok()?;
+    for m in moves {
+        if let Some(Move::Normal { from, to, .. }) = Move::<S>::from_sfen(m) {
+            position
+                .play(from.to_string().as_str(), to.to_string().as_str())
+                .ok()?;
+        } else {
+            return None;
+        }
+    }
+    Some(position.generate_sfen())
+}
@@ -5,17 +5,22 @@ use axum::{
     response::Redirect,
     Json,
 };
+use bson::{doc, Document};
 use hyper::HeaderMap;
+use serde::Deserialize;
 use serde_json::Value;
 
 use crate::{
     database::{
-        queries::{get_article, get_player_games, player_exist},
-        redis::{UserSession, VueUser},
+        queries::search_games,
+        redis::{OptionalSession, UserSession, VueUser},
     },
-    lichess::{
-        curr_url,
-        login::{get_lichess_token, get_lichess_user, login_url},
+    lichess::login::{get_lichess_token, get_lichess_user, login_url},
+    metrics::timed,
+    websockets::{
+        analysis::{square_hints, HintsReq},
+        leaderboards::LEADERBOARD_SIZE,
+        server_messages::unsupported,
     },
     AppState,
 };
@@ -26,10 +31,13 @@ pub async fn login(
     State(state): State<AppState>,
 ) -> Redirect {
     let key = &state.db.key;
-    let mut redis = state.db.redis.clone();
-    let url = login_url(&key.login_state, key.prod);
+    let url = login_url(&state.config, &key.login_state, key.prod);
     user.new_cv(&url.1);
-    redis.set_session(&user.session, user.clone(), true).await;
+    state
+        .db
+        .sessions
+        .set_session(&user.session, user.clone(), true)
+        .await;
     Redirect::permanent(url.0.as_str())
 }
 
@@ -40,22 +48,26 @@ pub async fn callback(
     user: UserSession,
 ) -> Redirect {
     let key = &state.db.key;
-    let mongo = &state.db.mongo;
-    let mut redis = state.db.redis.clone();
-    let r = curr_url(key.prod);
+    let r = state.config.curr_url(key.prod);
     let r = format!("{}/logged", r.1);
     if let Some(code) = params.get(&String::from("code")) {
-        let lichess_token =
-            get_lichess_token(code, &user.code_verifier, key.prod).await;
+        let lichess_token = get_lichess_token(
+            &state.config,
+            code,
+            &user.code_verifier,
+            key.prod,
+        )
+        .await;
         if !lichess_token.access_token.is_empty() {
             let lichess_user =
-                get_lichess_user(lichess_token.access_token).await;
+                get_lichess_user(&state.config, lichess_token.access_token)
+                    .await;
             if !lichess_user.is_empty() {
-                let player =
-                    player_exist(&mongo.players, &lichess_user, &user).await;
+                let players = &state.db.players;
+                let player = players.player_exist(&lichess_user, &user).await;
                 if let Some(player) = player {
                     let session = String::from(&player.session);
-                    redis.set_session(&session, player, true).await;
+                    state.db.sessions.set_session(&session, player, true).await;
                 }
             }
         }
@@ -74,21 +86,183 @@ pub async fn get_games(
     Path((username, page)): Path<(String, u64)>,
     State(state): State<AppState>,
 ) -> Json<Value> {
-    if let Some(games) =
-        get_player_games(&state.db.mongo.games, &username, page).await
-    {
+    if let Some(games) = state.db.games.player_games(&username, page).await {
         return Json(serde_json::json!({"exist": true, "games": games}));
     }
     Json(serde_json::json!({"exist": false}))
 }
 
-/// Get article.
-pub async fn article(
+/// Games per page for search.
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// Filters for game search. Time is in minutes, dates are in milliseconds.
+#[derive(Deserialize)]
+pub struct GameSearch {
+    pub player: Option<String>,
+    pub opponent: Option<String>,
+    pub variant: Option<String>,
+    pub sub_variant: Option<u8>,
+    /// Color of player who lost.
+    pub result: Option<String>,
+    pub status: Option<i32>,
+    pub min_time: Option<i64>,
+    pub max_time: Option<i64>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub rated: Option<bool>,
+    /// Last game from previous page.
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+impl GameSearch {
+    /// Create mongodb filter.
+    pub fn filter(&self) -> Document {
+        let mut filter = doc! {};
+        match (&self.player, &self.opponent) {
+            (Some(player), Some(opponent)) => {
+                filter.insert("players", doc! {"$all": [player, opponent]});
+            }
+            (Some(player), None) | (None, Some(player)) => {
+                filter.insert("players", player);
+            }
+            _ => (),
+        }
+        if let Some(variant) = &self.variant {
+            filter.insert("variant", variant);
+        }
+        if let Some(sub_variant) = self.sub_variant {
+            filter.insert("sub_variant", sub_variant as i32);
+        }
+        if let Some(result) = &self.result {
+            filter.insert("result", result);
+        }
+        if let Some(status) = self.status {
+            filter.insert("status", status);
+        }
+        let mut min = doc! {};
+        if let Some(min_time) = self.min_time {
+            min.insert("$gte", min_time * 60 * 1000);
+        }
+        if let Some(max_time) = self.max_time {
+            min.insert("$lte", max_time * 60 * 1000);
+        }
+        if !min.is_empty() {
+            filter.insert("min", min);
+        }
+        let mut date = doc! {};
+        if let Some(from) = self.from {
+            date.insert("$gte", bson::DateTime::from_millis(from));
+        }
+        if let Some(to) = self.to {
+            date.insert("$lte", bson::DateTime::from_millis(to));
+        }
+        if !date.is_empty() {
+            filter.insert("last_clock", date);
+        }
+        if let Some(rated) = self.rated {
+            filter.insert("rated", rated);
+        }
+        if let Some((time, id)) = self.parse_cursor() {
+            filter.insert(
+                "$or",
+                vec![
+                    doc! {"last_clock": {"$lt": time}},
+                    doc! {"last_clock": time, "_id": {"$lt": id}},
+                ],
+            );
+        }
+        filter
+    }
+
+    pub fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    fn parse_cursor(&self) -> Option<(bson::DateTime, String)> {
+        let cursor = self.cursor.as_ref()?;
+        let (time, id) = cursor.split_once('_')?;
+        let time = bson::DateTime::from_millis(time.parse().ok()?);
+        Some((time, String::from(id)))
+    }
+}
+
+/// Search finished and live games.
+pub async fn find_games(
+    Query(search): Query<GameSearch>,
+    State(state): State<AppState>,
+) -> Json<Value> {
+    let limit = search.limit();
+    let games = match &state.db.mongo {
+        Some(mongo) => {
+            let games = search_games(&mongo.games, search.filter(), limit);
+            timed("mongo", "search_games", games).await
+        }
+        None => return Json(unsupported()),
+    };
+    let cursor = {
+        if games.len() as i64 == limit {
+            games.last().map(|g| {
+                format!("{}_{}", g.last_clock.timestamp_millis(), &g._id)
+            })
+        } else {
+            None
+        }
+    };
+    Json(serde_json::json!({"exist": true, "games": games, "cursor": cursor}))
+}
+
+/// Legal moves for one square in live game or in any position.
+pub async fn hints(
+    Query(req): Query<HintsReq>,
+    State(state): State<AppState>,
+) -> Json<Value> {
+    if let Some(moves) = square_hints(&state.ws.shuuro_games, &req) {
+        return Json(serde_json::json!({"exist": true, "moves": moves}));
+    }
+    Json(serde_json::json!({"exist": false}))
+}
+
+/// Get top 100 players for variant and speed.
+pub async fn leaderboard(
+    Path((variant, speed)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> Json<Value> {
+    let key = format!("{variant}_{speed}");
+    if let Some(players) = state.ws.leaderboards.get(&key, LEADERBOARD_SIZE) {
+        return Json(serde_json::json!({"exist": true, "players": players}));
+    }
+    Json(serde_json::json!({"exist": false}))
+}
+
+/// Get chat for finished game. Only players and moderators can see it.
+pub async fn game_chat(
     Path(id): Path<String>,
     State(state): State<AppState>,
+    OptionalSession(user): OptionalSession,
 ) -> Json<Value> {
-    if let Some(article) = get_article(&state.db.mongo.articles, &id).await {
-        return Json(serde_json::json!({"exist": true, "news": article}));
+    let user = match user {
+        Some(user) => user,
+        None => return Json(serde_json::json!({"exist": false})),
+    };
+    let games = state.db.games.as_ref();
+    let id = state
+        .ws
+        .shuuro_games
+        .public_id(&id, games)
+        .await
+        .unwrap_or(id);
+    if let Some(game) = games.get_game(&id).await {
+        if game.status >= 0
+            && (game.players.contains(&user.username) || user.is_moderator())
+        {
+            let lines = state.db.chat.game_chat(&id).await;
+            let lines = lines.unwrap_or_default();
+            return Json(serde_json::json!({"exist": true, "lines": lines}));
+        }
     }
     Json(serde_json::json!({"exist": false}))
 }
@@ -0,0 +1,55 @@
+use crate::{
+    config::ChatConfig,
+    websockets::chat_filter::{ChatFilter, FilterResult},
+};
+
+/// Filter without bad words, where every player is new.
+fn filter() -> ChatFilter {
+    let config = ChatConfig {
+        burst: 100.0,
+        ..ChatConfig::default()
+    };
+    ChatFilter::with_words(vec![], &config)
+}
+
+#[test]
+fn links_are_hidden_for_new_accounts() {
+    let filter = filter();
+    for (i, message) in [
+        "visit https://example.org",
+        "www.example",
+        "come to example.com/play",
+        "see lishuuro.gg!",
+    ]
+    .iter()
+    .enumerate()
+    {
+        let username = format!("p{i}");
+        let check = filter.check(&username, message);
+        assert_eq!(check, FilterResult::Hidden("link"), "{message}");
+    }
+}
+
+#[test]
+fn sentences_are_not_links() {
+    let filter = filter();
+    for (i, message) in ["wait...ok", "the end.Next game", "e.g. this", "gg"]
+        .iter()
+        .enumerate()
+    {
+        let username = format!("p{i}");
+        assert_eq!(filter.check(&username, message), FilterResult::Ok);
+    }
+}
+
+#[test]
+fn messages_above_burst_are_rate_limited() {
+    let config = ChatConfig {
+        burst: 1.0,
+        refill_secs: 1000.0,
+        ..ChatConfig::default()
+    };
+    let filter = ChatFilter::with_words(vec![], &config);
+    assert_eq!(filter.check("p", "hello"), FilterResult::Ok);
+    assert_eq!(filter.check("p", "again"), FilterResult::RateLimited);
+}
@@ -0,0 +1,308 @@
+use chrono::Duration;
+use serde_json::{json, Value};
+
+use super::{new_game, seek, TestClient, TestServer};
+
+/// Fight position where white mates with `a1_a8`.
+const MATE_IN_ONE: &str = "6k1/5ppp/8/8/8/8/8/R5K1 w - 1";
+
+/// Game between two connected players.
+struct Game {
+    id: String,
+    white: TestClient,
+    black: TestClient,
+}
+
+impl Game {
+    fn player(&mut self, color: usize) -> &mut TestClient {
+        if color == 0 {
+            &mut self.white
+        } else {
+            &mut self.black
+        }
+    }
+
+    /// Both players get same message.
+    async fn expect(&mut self, t: &str) -> Value {
+        let data = self.white.expect(t).await;
+        assert_eq!(self.black.expect(t).await, data);
+        data
+    }
+
+    async fn expect_silence(&mut self) {
+        self.white.expect_silence().await;
+        self.black.expect_silence().await;
+    }
+
+    fn msg(&self, t: &str, game_move: &str) -> Value {
+        json!({
+            "t": t,
+            "game_id": &self.id,
+            "game_move": game_move,
+            "variant": "standard"
+        })
+    }
+
+    async fn send(&mut self, color: usize, t: &str, game_move: &str) {
+        let msg = self.msg(t, game_move);
+        self.player(color).send(msg).await;
+    }
+
+    /// Legal moves for piece or square.
+    async fn hints(&mut self, color: usize, square: &str) -> Vec<String> {
+        let msg = json!({"t": "legal_hints", "data": {
+            "game_id": &self.id,
+            "variant": "standard",
+            "square": square
+        }});
+        let client = self.player(color);
+        client.send(msg).await;
+        let data = client.expect("legal_hints").await;
+        assert_eq!(data["exist"], true);
+        serde_json::from_value(data["moves"].clone()).unwrap()
+    }
+
+    /// Both players buy queen and confirm.
+    async fn shop(&mut self) {
+        self.send(0, "live_game_buy", "+Q").await;
+        self.send(1, "live_game_buy", "+q").await;
+        self.send(0, "live_game_hand", "").await;
+        let hand = self.white.expect("live_game_hand").await;
+        assert!(hand["hand"].as_str().unwrap().contains('Q'));
+
+        self.send(0, "live_game_confirm", "cc").await;
+        let confirmed = self.expect("pause_confirmed").await;
+        assert_eq!(confirmed["confirmed"], json!([true, false]));
+        self.send(1, "live_game_confirm", "cc").await;
+        let confirmed = self.expect("pause_confirmed").await;
+        assert_eq!(confirmed["confirmed"], json!([true, true]));
+
+        let deploy = self.expect("redirect_deploy").await;
+        assert_eq!(deploy["w"], self.white.username.as_str());
+        assert_eq!(deploy["b"], self.black.username.as_str());
+    }
+
+    /// Place first legal piece on first legal square until fight starts.
+    async fn deploy(&mut self) {
+        for _ in 0..64 {
+            let (color, m) = self.next_placement().await;
+            self.send(color, "live_game_place", &m).await;
+            let place = self.expect("live_game_place").await;
+            assert_eq!(place["game_id"], self.id.as_str());
+            if place["to_fight"] == true {
+                assert_eq!(place["first_move_error"], false);
+                return;
+            }
+        }
+        panic!("deploy is not finished");
+    }
+
+    async fn next_placement(&mut self) -> (usize, String) {
+        for (color, pieces) in [(0, "KQRBNP"), (1, "kqrbnp")] {
+            for piece in pieces.chars() {
+                let piece = piece.to_string();
+                if let Some(square) = self.hints(color, &piece).await.first() {
+                    return (color, format!("{piece}@{square}"));
+                }
+            }
+        }
+        panic!("no legal placement");
+    }
+
+    /// Side to move in fight.
+    async fn side_to_move(&mut self) -> usize {
+        self.send(0, "live_game_sfen", "").await;
+        let sfen = self.white.expect("live_game_sfen").await;
+        assert_eq!(sfen["current_stage"], 2);
+        let fen = sfen["fen"].as_str().unwrap();
+        usize::from(fen.split_whitespace().nth(1) == Some("b"))
+    }
+
+    /// Play first legal move for side to move.
+    async fn fight(&mut self) -> usize {
+        let color = self.side_to_move().await;
+        for rank in 1..=8 {
+            for file in 'a'..='h' {
+                let from = format!("{file}{rank}");
+                if let Some(to) = self.hints(color, &from).await.first() {
+                    let m = format!("{from}_{to}");
+                    self.send(color, "live_game_play", &m).await;
+                    let play = self.expect("live_game_play").await;
+                    assert_eq!(play["game_move"], m.as_str());
+                    assert!(play["status"].as_i64().unwrap() < 0);
+                    return color;
+                }
+            }
+        }
+        panic!("no legal move");
+    }
+
+    async fn expect_games_count(&mut self, cnt: usize) {
+        let count = self.expect("active_games_count").await;
+        assert_eq!(count["cnt"], cnt);
+    }
+}
+
+/// First player adds seek, second accepts it.
+async fn start_game(server: &TestServer, mut seek: Value) -> Game {
+    let (mut first, mut second) = server.pair().await;
+    let username = String::from(&first.username);
+    seek["username"] = json!(&username);
+
+    first
+        .send(json!({"t": "home_lobby_add", "data": &seek}))
+        .await;
+    for client in [&mut first, &mut second] {
+        let added = client.expect("home_lobby_add").await;
+        assert_eq!(added["username"], username.as_str());
+    }
+
+    second
+        .send(json!({"t": "home_lobby_accept", "data": &seek}))
+        .await;
+    let mut id = Value::Null;
+    let mut players = Value::Null;
+    for client in [&mut first, &mut second] {
+        client.expect("home_lobby_remove").await;
+        let start = client.expect("live_game_start").await;
+        id = start["game_id"].clone();
+        players = start["game_info"]["players"].clone();
+        let player_id = start["player_id"].as_str().unwrap();
+        assert!(player_id.starts_with(id.as_str().unwrap()));
+        assert!(start["game_info"].get("player_ids").is_none());
+        let count = client.expect("active_games_count").await;
+        assert_eq!(count["cnt"], 1);
+    }
+
+    let (white, black) = if players[0] == first.username.as_str() {
+        (first, second)
+    } else {
+        (second, first)
+    };
+    assert_eq!(players[1], black.username.as_str());
+    Game {
+        id: String::from(id.as_str().unwrap()),
+        white,
+        black,
+    }
+}
+
+#[tokio::test]
+async fn challenge_is_seen_only_by_both_players() {
+    let server = TestServer::start().await;
+    let (mut first, mut second) = server.pair().await;
+    let mut third = server.connect().await;
+    for client in [&mut first, &mut second] {
+        let count = client.expect("active_players_count").await;
+        assert_eq!(count["cnt"], 3);
+    }
+    let mut seek = seek();
+    seek["username"] = json!(&first.username);
+    seek["opponent"] = json!(&second.username);
+    first
+        .send(json!({"t": "home_lobby_add", "data": &seek}))
+        .await;
+    let opponent = String::from(&second.username);
+    for client in [&mut first, &mut second] {
+        let added = client.expect("home_lobby_add").await;
+        assert_eq!(added["opponent"], opponent.as_str());
+    }
+    let notification = second.expect("notification").await;
+    assert_eq!(notification["kind"], "challenge");
+
+    third.send(json!({"t": "home_lobby_full"})).await;
+    let full = third.expect("home_lobby_full").await;
+    assert_eq!(full["lobbyGames"], json!([]));
+    second.send(json!({"t": "home_lobby_full"})).await;
+    let full = second.expect("home_lobby_full").await;
+    assert_eq!(full["lobbyGames"][0]["username"], first.username.as_str());
+}
+
+#[tokio::test]
+async fn full_game_ends_with_resign() {
+    let server = TestServer::start().await;
+    let mut game = start_game(&server, seek()).await;
+    game.shop().await;
+    game.deploy().await;
+    let color = game.fight().await;
+
+    let loser = 1 - color;
+    game.send(loser, "live_game_resign", "").await;
+    let resign = game.expect("live_game_resign").await;
+    assert_eq!(resign["player"], game.player(loser).username.as_str());
+    game.expect_games_count(0).await;
+    game.expect_silence().await;
+}
+
+#[tokio::test]
+async fn fight_ends_with_checkmate() {
+    let server = TestServer::start().await;
+    let mut seek = seek();
+    seek["sfen"] = json!(MATE_IN_ONE);
+    seek["stage"] = json!(2);
+    let mut game = start_game(&server, seek).await;
+
+    game.send(0, "live_game_play", "a1_a8").await;
+    game.expect_games_count(0).await;
+    let play = game.expect("live_game_play").await;
+    assert_eq!(play["game_move"], "a1_a8");
+    assert!(play["status"].as_i64().unwrap() > 0);
+    game.expect_silence().await;
+
+    let url = format!("http://{}/games/{}/0", server.addr, game.white.username);
+    let res: Value = reqwest::get(url).await.unwrap().json().await.unwrap();
+    let exported = &res["games"][0];
+    assert_eq!(exported["start_sfen"], MATE_IN_ONE);
+    assert_eq!(exported["rated"], false);
+}
+
+#[tokio::test]
+async fn custom_position_is_exported() {
+    let server = TestServer::start().await;
+    let mut seek = seek();
+    seek["sfen"] = json!(MATE_IN_ONE);
+    seek["stage"] = json!(2);
+    let mut game = new_game(seek);
+    server.db.games.add_game(&mut game).await.unwrap();
+
+    let url = format!("http://{}/games/first/0", server.addr);
+    let res: Value = reqwest::get(url).await.unwrap().json().await.unwrap();
+    let exported = &res["games"][0];
+    assert_eq!(exported["start_sfen"], MATE_IN_ONE);
+    assert_eq!(exported["rated"], false);
+}
+
+#[tokio::test]
+async fn players_agree_to_draw() {
+    let server = TestServer::start().await;
+    let mut game = start_game(&server, seek()).await;
+
+    game.send(0, "live_game_draw", "").await;
+    let draw = game.expect("live_game_draw").await;
+    assert_eq!(draw["draw"], false);
+    assert_eq!(draw["player"], game.white.username.as_str());
+
+    game.send(1, "live_game_draw", "").await;
+    let draw = game.expect("live_game_draw").await;
+    assert_eq!(draw["draw"], true);
+    game.expect_games_count(0).await;
+    game.expect_silence().await;
+}
+
+#[tokio::test]
+async fn player_loses_on_time_in_shop() {
+    let server = TestServer::start().await;
+    let mut game = start_game(&server, seek()).await;
+
+    game.send(0, "live_game_confirm", "cc").await;
+    let confirmed = game.expect("pause_confirmed").await;
+    assert_eq!(confirmed["confirmed"], json!([true, false]));
+    game.expect_silence().await;
+
+    server.clock.advance(Duration::minutes(11));
+    let lost = game.expect("live_game_lot").await;
+    assert_eq!(lost["status"], 8);
+    assert_eq!(lost["result"], "b");
+    game.expect_games_count(0).await;
+    game.expect_silence().await;
+}
@@ -0,0 +1,108 @@
+use serde_json::{json, Value};
+
+use crate::{
+    database::{
+        memory::{MemoryGames, MemoryPlayers},
+        repos::{GameRepo, PlayerRepo},
+        StorageError,
+    },
+    lichess::login::{
+        is_player_id, player_ids, public_game_id, GAME_ID_LEN, PLAYER_ID_LEN,
+    },
+    websockets::server_messages::live_game_start,
+};
+
+use super::{new_game, seek, TestServer};
+
+fn same_id() -> String {
+    String::from("AAAAAAAA")
+}
+
+#[test]
+fn private_ids_are_independent_of_public_id() {
+    let id = public_game_id();
+    assert_eq!(id.len(), GAME_ID_LEN);
+    assert!(id.chars().all(|c| c.is_ascii_alphanumeric()));
+    let [white, black] = player_ids();
+    assert_ne!(white, black);
+    for player_id in [&white, &black] {
+        assert!(player_id.len() >= 16);
+        assert_eq!(player_id.len(), PLAYER_ID_LEN);
+        assert!(is_player_id(player_id));
+    }
+    assert!(!is_player_id(&id));
+    assert!(!is_player_id("aaaaaaaaaaaaaaa/"));
+}
+
+#[tokio::test]
+async fn game_ids_are_retried_and_then_exhausted() {
+    let games = MemoryGames::with_ids(same_id);
+    let mut first = new_game(seek());
+    games.add_game(&mut first).await.unwrap();
+    assert_eq!(first._id, same_id());
+    assert!(first.player_ids.iter().all(|id| is_player_id(id)));
+
+    let mut second = new_game(seek());
+    let res = games.add_game(&mut second).await;
+    assert_eq!(res, Err(StorageError::IdsExhausted));
+    let stored = games.get_game(&same_id()).await.unwrap();
+    assert_eq!(stored.player_ids, first.player_ids);
+}
+
+#[tokio::test]
+async fn usernames_are_retried_and_then_exhausted() {
+    let players = MemoryPlayers::with_usernames(same_id);
+    assert_eq!(players.create_player().await, Ok(same_id()));
+    let res = players.create_player().await;
+    assert_eq!(res, Err(StorageError::IdsExhausted));
+}
+
+#[tokio::test]
+async fn only_player_gets_private_id() {
+    let games = MemoryGames::default();
+    let mut game = new_game(seek());
+    games.add_game(&mut game).await.unwrap();
+
+    let msg = live_game_start(&game, "second");
+    assert_eq!(msg["data"]["player_id"], game.player_ids[1].as_str());
+    assert!(msg["data"]["game_info"].get("player_ids").is_none());
+
+    let msg = live_game_start(&game, "spectator");
+    assert!(msg["data"]["player_id"].is_null());
+    assert!(!msg.to_string().contains(&game.player_ids[0]));
+}
+
+#[tokio::test]
+async fn game_is_opened_by_player_id() {
+    let server = TestServer::start().await;
+    let mut game = new_game(seek());
+    server.db.games.add_game(&mut game).await.unwrap();
+
+    let url = |id: &str| format!("http://{}/nuxt/shuuro/{id}", server.addr);
+    for id in [&game._id, &game.player_ids[0], &game.player_ids[1]] {
+        let res: Value =
+            reqwest::get(url(id)).await.unwrap().json().await.unwrap();
+        assert_eq!(res["exist"], true, "{id}");
+    }
+    let guess = format!("{}00000000", &game._id);
+    let res: Value = reqwest::get(url(&guess))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(res["exist"], false);
+
+    let mut client = server.connect().await;
+    let private = &game.player_ids[0];
+    client
+        .send(json!({
+            "t": "live_game_start",
+            "game_id": private,
+            "variant": "standard"
+        }))
+        .await;
+    let start = client.expect("live_game_start").await;
+    assert_eq!(start["game_id"], game._id.as_str());
+    assert!(start["player_id"].is_null());
+}
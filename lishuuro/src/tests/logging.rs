@@ -0,0 +1,28 @@
+use hyper::StatusCode;
+use serde_json::json;
+
+use crate::logging::set_filter;
+
+use super::TestServer;
+
+#[tokio::test]
+async fn only_admin_can_change_log_filter() {
+    let server = TestServer::start().await;
+    let url = format!("http://{}/logging", server.addr);
+    let client = reqwest::Client::new();
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = client
+        .put(&url)
+        .json(&json!({"filter": "debug"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn bad_filter_is_rejected() {
+    let err = set_filter("lishuuro=loud").unwrap_err();
+    assert_ne!(err, "logging is not enabled");
+}
@@ -0,0 +1,84 @@
+use hyper::StatusCode;
+use serde_json::json;
+
+use crate::{
+    config::Config,
+    metrics::{Metrics, METRICS},
+    websockets::{clock::system_clock, WsState},
+};
+
+use super::{seek, TestServer};
+
+#[test]
+fn render_counters_and_histograms() {
+    let metrics = Metrics::new();
+    let ws = WsState::new(&Config::default(), system_clock());
+    metrics.message_in("live_game_play");
+    metrics.message_in("live_game_play");
+    metrics.message_in("bad\"type");
+    metrics.game_result(8);
+    metrics.observe_query("mongo", "get_game", 0.02);
+    metrics.observe_query("mongo", "get_game", 3.0);
+
+    let text = metrics.render(&ws);
+    let lines: Vec<&str> = text.lines().collect();
+    for line in [
+        "# TYPE lishuuro_messages_in_total counter",
+        "lishuuro_messages_in_total{type=\"live_game_play\"} 2",
+        "lishuuro_messages_in_total{type=\"bad\\\"type\"} 1",
+        "lishuuro_game_results_total{status=\"8\"} 1",
+        "lishuuro_query_seconds_bucket{backend=\"mongo\",query=\"get_game\",le=\"0.01\"} 0",
+        "lishuuro_query_seconds_bucket{backend=\"mongo\",query=\"get_game\",le=\"0.025\"} 1",
+        "lishuuro_query_seconds_bucket{backend=\"mongo\",query=\"get_game\",le=\"5\"} 2",
+        "lishuuro_query_seconds_bucket{backend=\"mongo\",query=\"get_game\",le=\"+Inf\"} 2",
+        "lishuuro_query_seconds_count{backend=\"mongo\",query=\"get_game\"} 2",
+        "lishuuro_lobby_seeks 0",
+    ] {
+        assert!(lines.contains(&line), "{line} is missing");
+    }
+}
+
+#[test]
+fn unknown_labels_are_limited() {
+    let metrics = Metrics::new();
+    let ws = WsState::new(&Config::default(), system_clock());
+    for i in 0..100 {
+        metrics.message_in(&format!("t{i}"));
+    }
+    let text = metrics.render(&ws);
+    let count = text
+        .lines()
+        .filter(|line| line.starts_with("lishuuro_messages_in_total{"))
+        .count();
+    assert_eq!(count, 65);
+    assert!(text.contains("lishuuro_messages_in_total{type=\"other\"} 36"));
+}
+
+#[tokio::test]
+async fn metrics_show_players_and_seeks() {
+    let server = TestServer::start().await;
+    let (mut first, mut second) = server.pair().await;
+    let mut seek = seek();
+    seek["username"] = json!(&first.username);
+    first
+        .send(json!({"t": "home_lobby_add", "data": seek}))
+        .await;
+    first.expect("home_lobby_add").await;
+    second.expect("home_lobby_add").await;
+
+    let text = METRICS.render(&server.ws);
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(lines.contains(&"lishuuro_online_players 2"));
+    assert!(lines.contains(&"lishuuro_lobby_seeks 1"));
+    assert!(
+        text.contains("lishuuro_messages_in_total{type=\"home_lobby_add\"}")
+    );
+}
+
+#[tokio::test]
+async fn public_metrics_are_only_for_admins() {
+    let server = TestServer::start().await;
+    let url = format!("http://{}/metrics", server.addr);
+    let res = reqwest::get(url).await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
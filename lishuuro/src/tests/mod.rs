@@ -0,0 +1,199 @@
+//! Server running on ephemeral port with in-memory storage.
+//! Clients talk to it over websocket, same as frontend.
+
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use futures::{SinkExt, StreamExt};
+use hyper::header::{COOKIE, SET_COOKIE};
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::{
+    app,
+    config::{Config, Limit, RateLimitConfig, Storage},
+    database::{mongo::ShuuroGame, Database},
+    websockets::{
+        clock::ManualClock, scheduler::run_scheduler, GameRequest, WsState,
+    },
+    AppState,
+};
+
+mod chat_filter;
+mod game_flow;
+mod ids;
+mod logging;
+mod metrics;
+mod news;
+mod rate_limit;
+mod scheduler;
+mod storage;
+mod time_control;
+
+/// How long client waits for next message.
+const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long client must not get any message.
+const SILENCE: Duration = Duration::from_millis(200);
+
+/// Seek without username, it's added by player who sends it.
+pub fn seek() -> Value {
+    json!({
+        "variant": "standard",
+        "time": 10,
+        "incr": 0,
+        "sub_variant": 100,
+        "color": "white"
+    })
+}
+
+/// Game from `seek` between `first` and `second`, not saved yet.
+pub fn new_game(mut seek: Value) -> ShuuroGame {
+    seek["username"] = json!("first");
+    let request: GameRequest = serde_json::from_value(seek).unwrap();
+    let players = [String::from("first"), String::from("second")];
+    ShuuroGame::from((&request, &players, ""))
+}
+
+pub struct TestServer {
+    addr: SocketAddr,
+    /// Clock used by all games and chat.
+    pub clock: Arc<ManualClock>,
+    pub db: Arc<Database>,
+    pub ws: Arc<WsState>,
+}
+
+impl TestServer {
+    /// Server with limits that games in tests never reach.
+    pub async fn start() -> Self {
+        let unlimited = Limit::new(1000.0, 0.001);
+        let rate_limit = RateLimitConfig {
+            messages: HashMap::new(),
+            default: unlimited,
+            ip: unlimited,
+            ..RateLimitConfig::default()
+        };
+        Self::start_with(Config {
+            rate_limit,
+            ..Config::default()
+        })
+        .await
+    }
+
+    /// Server with in-memory storage and other values from `config`.
+    pub async fn start_with(config: Config) -> Self {
+        let config = Config {
+            storage: Storage::Memory,
+            ..config
+        };
+        let config = Arc::new(config);
+        let db = Arc::new(Database::new(&config).await);
+        let clock = Arc::new(ManualClock::default());
+        let ws = Arc::new(WsState::new(&config, clock.clone()));
+        tokio::spawn(run_scheduler(ws.clone(), db.clone()));
+        let app = app(AppState::new(db.clone(), ws.clone(), config));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener).unwrap();
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(server.serve(app));
+        Self {
+            addr,
+            clock,
+            db,
+            ws,
+        }
+    }
+
+    /// Connect two anonymous players.
+    pub async fn pair(&self) -> (TestClient, TestClient) {
+        let mut first = self.connect().await;
+        let second = self.connect().await;
+        let count = first.expect("active_players_count").await;
+        assert_eq!(count["cnt"], 2);
+        (first, second)
+    }
+
+    /// Connect new anonymous player and read messages sent on connect.
+    pub async fn connect(&self) -> TestClient {
+        let res = reqwest::get(format!("http://{}/vue_user", self.addr))
+            .await
+            .unwrap();
+        let cookie = res.headers()[SET_COOKIE].to_str().unwrap();
+        let cookie = String::from(cookie.split(';').next().unwrap());
+        let user: Value = res.json().await.unwrap();
+        let username = String::from(user["username"].as_str().unwrap());
+
+        let url = format!("ws://{}/ws/", self.addr);
+        let mut req = url.into_client_request().unwrap();
+        req.headers_mut().insert(COOKIE, cookie.parse().unwrap());
+        let (stream, _) = connect_async(req).await.unwrap();
+
+        let mut client = TestClient { username, stream };
+        client.expect("active_games_count").await;
+        client.expect("active_players_count").await;
+        let chat = client.expect("live_chat_full").await;
+        assert_eq!(chat["id"], "home");
+        client
+    }
+}
+
+pub struct TestClient {
+    pub username: String,
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl TestClient {
+    pub async fn send(&mut self, msg: Value) {
+        let msg = Message::Text(msg.to_string());
+        self.stream.send(msg).await.unwrap();
+    }
+
+    /// Next JSON message from server.
+    pub async fn recv(&mut self) -> Value {
+        loop {
+            let msg = tokio::time::timeout(RECV_TIMEOUT, self.stream.next())
+                .await
+                .unwrap_or_else(|_| panic!("{} got nothing", &self.username));
+            match msg {
+                Some(Ok(Message::Text(text))) => {
+                    return serde_json::from_str(&text).unwrap();
+                }
+                Some(Ok(_)) => continue,
+                _ => panic!("{} is disconnected", &self.username),
+            }
+        }
+    }
+
+    /// Next message must have type `t`. Returns its data.
+    pub async fn expect(&mut self, t: &str) -> Value {
+        let msg = self.recv().await;
+        assert_eq!(msg["t"], t, "{} got {msg}", &self.username);
+        msg["data"].clone()
+    }
+
+    /// Server closed connection.
+    pub async fn expect_closed(&mut self) {
+        loop {
+            let msg = tokio::time::timeout(RECV_TIMEOUT, self.stream.next())
+                .await
+                .unwrap_or_else(|_| panic!("{} is connected", &self.username));
+            match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(msg)) => panic!("{} got {msg}", &self.username),
+            }
+        }
+    }
+
+    /// There are no messages left.
+    pub async fn expect_silence(&mut self) {
+        let msg = tokio::time::timeout(SILENCE, self.stream.next()).await;
+        if let Ok(Some(Ok(msg))) = msg {
+            panic!("{} got {msg}", &self.username);
+        }
+    }
+}
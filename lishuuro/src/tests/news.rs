@@ -0,0 +1,66 @@
+use bson::doc;
+use hyper::{header::SET_COOKIE, StatusCode};
+
+use crate::{
+    config::{Config, Limit, RateLimitConfig},
+    database::mongo::Article,
+};
+
+use super::TestServer;
+
+#[test]
+fn article_without_text_is_decoded() {
+    // List of articles is loaded without `text` and `html`.
+    let doc = doc! {
+        "_id": "first",
+        "title": "First",
+        "user": "admin",
+        "date": "2024-01-01",
+        "category": "news",
+        "headline": "Hello",
+    };
+    let article: Article = bson::from_document(doc).unwrap();
+    assert_eq!(article.text, "");
+    assert_eq!(article.html, "");
+}
+
+#[tokio::test]
+async fn reading_news_creates_no_accounts() {
+    let rate_limit = RateLimitConfig {
+        accounts: Limit::new(1.0, 1000.0),
+        ..RateLimitConfig::default()
+    };
+    let server = TestServer::start_with(Config {
+        rate_limit,
+        ..Config::default()
+    })
+    .await;
+    for path in ["news", "news/first", "notifications", "chat/first"] {
+        for _ in 0..3 {
+            let url = format!("http://{}/{path}", server.addr);
+            let res = reqwest::get(url).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK, "{path}");
+            assert!(res.headers().get(SET_COOKIE).is_none(), "{path}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn feed_items_have_pub_date() {
+    let server = TestServer::start().await;
+    let article = Article {
+        _id: String::from("first"),
+        title: String::from("First"),
+        user: String::from("admin"),
+        date: String::from("2024-01-01"),
+        category: String::from("news"),
+        text: String::from("Hello"),
+        headline: String::from("Hello"),
+        html: String::from("<p>Hello</p>"),
+        draft: false,
+    };
+    assert!(server.db.articles.add_article(&article).await);
+    let url = format!("http://{}/news/feed", server.addr);
+    let feed = reqwest::get(url).await.unwrap().text().await.unwrap();
+    assert!(feed.contains("<pubDate>Mon, 01 Jan 2024 00:00:00 +0000</pubDate>"));
+}
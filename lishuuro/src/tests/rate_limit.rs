@@ -0,0 +1,137 @@
+use std::net::IpAddr;
+
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+use crate::{
+    config::{Config, Limit, RateLimitConfig},
+    websockets::rate_limit::{RateLimiter, Strikes, MAX_KEYS},
+};
+
+use super::{TestClient, TestServer};
+
+/// Limits that are never refilled during test.
+fn limits(burst: f64) -> RateLimitConfig {
+    let limit = Limit::new(burst, 1000.0);
+    RateLimitConfig {
+        messages: [(String::from("home_lobby_add"), limit)].into(),
+        default: limit,
+        ip: Limit::new(5.0, 1000.0),
+        http: Limit::new(100.0, 1000.0),
+        accounts: limit,
+        strikes: 2,
+        ..RateLimitConfig::default()
+    }
+}
+
+#[test]
+fn message_types_have_own_buckets() {
+    let limiter = RateLimiter::new(&limits(2.0));
+    for _ in 0..2 {
+        assert!(limiter.message("s1", None, "home_lobby_add"));
+    }
+    assert!(!limiter.message("s1", None, "home_lobby_add"));
+    assert!(limiter.message("s2", None, "home_lobby_add"));
+
+    assert!(limiter.message("s1", None, "live_tv"));
+    assert!(limiter.message("s1", None, "unknown"));
+    assert!(!limiter.message("s1", None, "live_tv"));
+}
+
+#[test]
+fn sessions_share_ip_bucket() {
+    let limiter = RateLimiter::new(&limits(2.0));
+    let ip: IpAddr = "10.0.0.1".parse().unwrap();
+    for session in ["s1", "s2", "s3", "s4", "s5"] {
+        assert!(limiter.message(session, Some(ip), "live_tv"));
+    }
+    assert!(!limiter.message("s6", Some(ip), "live_tv"));
+    let other: IpAddr = "10.0.0.2".parse().unwrap();
+    assert!(limiter.message("s6", Some(other), "live_tv"));
+}
+
+#[test]
+fn limited_ip_keeps_session_token() {
+    let limiter = RateLimiter::new(&limits(6.0));
+    let ip: IpAddr = "10.0.0.1".parse().unwrap();
+    for _ in 0..5 {
+        assert!(limiter.message("s1", Some(ip), "live_tv"));
+    }
+    assert!(!limiter.message("s1", Some(ip), "live_tv"));
+    let other: IpAddr = "10.0.0.2".parse().unwrap();
+    assert!(limiter.message("s1", Some(other), "live_tv"));
+}
+
+#[test]
+fn oldest_buckets_are_evicted() {
+    let limiter = RateLimiter::new(&limits(1.0));
+    assert!(limiter.message("first", None, "live_tv"));
+    for i in 0..MAX_KEYS {
+        assert!(limiter.message(&format!("s{i}"), None, "live_tv"));
+    }
+    assert!(limiter.message("first", None, "live_tv"));
+    let last = format!("s{}", MAX_KEYS - 1);
+    assert!(!limiter.message(&last, None, "live_tv"));
+}
+
+#[test]
+fn strikes_are_reset_after_quiet_period() {
+    let limiter = RateLimiter::new(&limits(1.0));
+    let mut strikes = Strikes::default();
+    let now = Utc::now();
+    assert!(!limiter.strike(&mut strikes, now));
+    let later = now + Duration::seconds(60);
+    assert!(!limiter.strike(&mut strikes, later));
+    assert_eq!(strikes.count(), 1);
+    assert!(limiter.strike(&mut strikes, later + Duration::seconds(59)));
+    assert!(limiter.is_closed(&strikes));
+}
+
+#[tokio::test]
+async fn flooding_socket_is_closed() {
+    let server = TestServer::start_with(Config {
+        rate_limit: limits(2.0),
+        ..Config::default()
+    })
+    .await;
+    let mut client = server.connect().await;
+    let count = json!({"t": "active_players_count"});
+    for _ in 0..3 {
+        client.send(count.clone()).await;
+    }
+    let limited = expect_limited(&mut client).await;
+    assert_eq!(limited["type"], "active_players_count");
+    assert_eq!(limited["closing"], false);
+
+    client.send(count).await;
+    let limited = expect_limited(&mut client).await;
+    assert_eq!(limited["closing"], true);
+    client.expect_closed().await;
+}
+
+/// Skip answers to messages that were sent before limit.
+async fn expect_limited(client: &mut TestClient) -> Value {
+    loop {
+        let msg = client.recv().await;
+        if msg["t"] == "rate_limited" {
+            return msg["data"].clone();
+        }
+        assert_eq!(msg["t"], "active_players_count");
+    }
+}
+
+#[tokio::test]
+async fn anonymous_accounts_are_limited() {
+    let server = TestServer::start_with(Config {
+        rate_limit: limits(2.0),
+        ..Config::default()
+    })
+    .await;
+    let url = format!("http://{}/vue_user", server.addr);
+    for _ in 0..2 {
+        let res = reqwest::get(&url).await.unwrap();
+        assert_eq!(res.status(), 200);
+    }
+    let res = reqwest::get(&url).await.unwrap();
+    assert_eq!(res.status(), 429);
+}
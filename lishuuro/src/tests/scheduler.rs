@@ -0,0 +1,46 @@
+use chrono::{Duration, Utc};
+
+use crate::websockets::{scheduler::ClockScheduler, GameGet};
+
+fn game(id: &str) -> GameGet {
+    GameGet::new(&String::from(id), &String::from("standard"))
+}
+
+fn ids(games: Vec<GameGet>) -> Vec<String> {
+    games.into_iter().map(|g| g.game_id).collect()
+}
+
+#[test]
+fn due_games_are_ordered_by_deadline() {
+    let scheduler = ClockScheduler::default();
+    let now = Utc::now();
+    scheduler.schedule(&game("b"), Some(now + Duration::seconds(2)));
+    scheduler.schedule(&game("a"), Some(now + Duration::seconds(1)));
+    scheduler.schedule(&game("c"), Some(now + Duration::seconds(5)));
+
+    let (due, next) = scheduler.due(now);
+    assert!(due.is_empty());
+    assert_eq!(next, Some(now + Duration::seconds(1)));
+
+    let (due, next) = scheduler.due(now + Duration::seconds(3));
+    assert_eq!(ids(due), ["a", "b"]);
+    assert_eq!(next, Some(now + Duration::seconds(5)));
+}
+
+#[test]
+fn new_deadline_replaces_old_one() {
+    let scheduler = ClockScheduler::default();
+    let now = Utc::now();
+    scheduler.schedule(&game("a"), Some(now + Duration::seconds(1)));
+    scheduler.schedule(&game("a"), Some(now + Duration::seconds(10)));
+    scheduler.schedule(&game("b"), Some(now + Duration::seconds(1)));
+    scheduler.schedule(&game("b"), None);
+
+    let (due, next) = scheduler.due(now + Duration::seconds(5));
+    assert!(due.is_empty());
+    assert_eq!(next, Some(now + Duration::seconds(10)));
+
+    let (due, next) = scheduler.due(now + Duration::seconds(10));
+    assert_eq!(ids(due), ["a"]);
+    assert_eq!(next, None);
+}
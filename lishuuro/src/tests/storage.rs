@@ -0,0 +1,115 @@
+use serde_json::{json, Value};
+
+use crate::{
+    database::{
+        memory::{MemoryChat, MemoryNotifications, MemoryPlayers},
+        mongo::{Notification, NotificationKind},
+        repos::{ChatRepo, NotificationRepo, PlayerRepo},
+    },
+    notifications::ALL_USERS,
+    websockets::rooms::ChatMsg,
+};
+
+use super::TestServer;
+
+fn notification(id: &str, user: &str, kind: NotificationKind) -> Notification {
+    Notification {
+        _id: String::from(id),
+        user: String::from(user),
+        kind,
+        data: json!({"game_id": "game"}),
+        read: false,
+        created_at: bson::DateTime::now(),
+    }
+}
+
+fn chat_line(msg_id: u64) -> ChatMsg {
+    ChatMsg {
+        id: String::from("game"),
+        user: String::from("first"),
+        time: String::from("12:00"),
+        message: format!("line {msg_id}"),
+        variant: String::from("standard"),
+        msg_id,
+    }
+}
+
+#[tokio::test]
+async fn notifications_for_everyone_are_read_per_player() {
+    let db = MemoryNotifications::default();
+    let news = notification("news", ALL_USERS, NotificationKind::ModWarning);
+    let warning = notification("warn", "first", NotificationKind::ModWarning);
+    db.add_notification(&news).await;
+    db.add_notification(&warning).await;
+    assert_eq!(db.unread_notifications("first").await, 2);
+    assert_eq!(db.unread_notifications("second").await, 1);
+
+    assert!(db.read_notifications("first", None).await);
+    assert_eq!(db.unread_notifications("first").await, 0);
+    assert_eq!(db.unread_notifications("second").await, 1);
+    let all = db.get_notifications("second", 0).await;
+    assert_eq!(all.len(), 1);
+    assert!(!all[0].1);
+}
+
+#[tokio::test]
+async fn move_notifications_are_merged_until_read() {
+    let db = MemoryNotifications::default();
+    let first = notification("first", "second", NotificationKind::MoveMade);
+    let again = notification("again", "second", NotificationKind::MoveMade);
+    db.upsert_game_notification(&first).await.unwrap();
+    let saved = db.upsert_game_notification(&again).await.unwrap();
+    assert_eq!(saved._id, "first");
+    assert_eq!(db.unread_notifications("second").await, 1);
+
+    db.read_notifications("second", Some("first")).await;
+    let saved = db.upsert_game_notification(&again).await.unwrap();
+    assert_eq!(saved._id, "again");
+    assert_eq!(db.unread_notifications("second").await, 1);
+}
+
+#[tokio::test]
+async fn followers_and_mutes_are_kept() {
+    let players = MemoryPlayers::default();
+    let first = players.create_player().await.unwrap();
+    let second = players.create_player().await.unwrap();
+    assert!(players.set_following(&first, &second, true).await);
+    assert!(players.set_following(&first, &second, true).await);
+    assert_eq!(players.followers(&second).await, vec![first.clone()]);
+    assert!(players.set_following(&first, &second, false).await);
+    assert!(players.followers(&second).await.is_empty());
+
+    let until = bson::DateTime::now();
+    assert!(players.set_mute(&second, Some(until)).await);
+    let player = players.get_player(&second).await.unwrap();
+    assert_eq!(player.muted_until, Some(until));
+    assert!(!players.set_mute("missing", None).await);
+}
+
+#[tokio::test]
+async fn chat_lines_are_loaded_for_unfinished_games() {
+    let chat = MemoryChat::default();
+    for msg_id in 0..3 {
+        chat.add_line("game", &chat_line(msg_id)).await;
+    }
+    chat.delete_line("game", 1).await;
+    let id = String::from("game");
+    let mut chats = chat.unfinished_chats(vec![&id]).await;
+    let lines = chats.remove("game").unwrap();
+    let ids: Vec<u64> = lines.iter().map(|m| m.msg_id).collect();
+    assert_eq!(ids, vec![0, 2]);
+}
+
+#[tokio::test]
+async fn mongo_only_features_are_unsupported() {
+    let server = TestServer::start().await;
+    for path in ["games/search", "explorer/shop", "explorer/placement"] {
+        let url = format!("http://{}/{path}", server.addr);
+        let res: Value = reqwest::get(url).await.unwrap().json().await.unwrap();
+        assert_eq!(res["reason"], "unsupported", "{path}");
+    }
+    let mut client = server.connect().await;
+    client.send(json!({"t": "puzzle"})).await;
+    let res = client.recv().await;
+    assert_eq!(res["reason"], "unsupported");
+}
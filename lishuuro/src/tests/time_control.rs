@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use chrono::Duration;
+
+use crate::websockets::{
+    clock::{Clock, ManualClock, SharedClock},
+    time_control::TimeControl,
+};
+
+/// Time control with manual clock.
+fn time_control(time: i64, incr: i64) -> (TimeControl, Arc<ManualClock>) {
+    let clock = Arc::new(ManualClock::default());
+    let mut tc = TimeControl::new(time, incr);
+    let shared: SharedClock = clock.clone();
+    tc.start_clock(&shared);
+    (tc, clock)
+}
+
+#[test]
+fn flag_falls_after_time_runs_out() {
+    let (mut tc, clock) = time_control(1, 0);
+    tc.update_stage(2);
+    clock.advance(Duration::seconds(30));
+    assert_eq!(tc.current_duration(0), Some(Duration::seconds(30)));
+    clock.advance(Duration::seconds(31));
+    assert_eq!(tc.current_duration(0), None);
+    assert_eq!(tc.click(0), None);
+}
+
+#[test]
+fn flag_falls_at_deadline() {
+    let (mut tc, clock) = time_control(1, 0);
+    tc.update_stage(2);
+    let deadline = tc.deadline(0);
+    clock.advance(deadline - tc.now() - Duration::milliseconds(1));
+    assert!(tc.current_duration(0).is_some());
+    clock.advance(Duration::milliseconds(1));
+    assert_eq!(tc.now(), deadline);
+    assert_eq!(tc.current_duration(0), None);
+}
+
+#[test]
+fn loaded_game_keeps_last_click() {
+    let (mut tc, clock) = time_control(1, 0);
+    tc.update_stage(2);
+    clock.advance(Duration::seconds(61));
+    let shared: SharedClock = clock.clone();
+    tc.set_clock(&shared);
+    assert_eq!(tc.current_duration(0), None);
+}
+
+#[test]
+fn increment_is_added_after_click() {
+    let (mut tc, clock) = time_control(1, 5);
+    tc.update_stage(2);
+    clock.advance(Duration::seconds(10));
+    assert_eq!(tc.click(0), Some([60_000, 65_000]));
+    clock.advance(Duration::seconds(20));
+    assert_eq!(tc.click(1), Some([60_000, 50_000]));
+}
+
+#[test]
+fn shop_clicks_keep_clocks() {
+    let (mut tc, clock) = time_control(1, 0);
+    clock.advance(Duration::seconds(20));
+    assert_eq!(tc.click(0), Some([60_000, 60_000]));
+    assert_eq!(tc.current_duration(1), Some(Duration::seconds(40)));
+}
+
+#[test]
+fn new_stage_starts_from_current_time() {
+    let (mut tc, clock) = time_control(1, 0);
+    clock.advance(Duration::seconds(50));
+    tc.update_stage(1);
+    assert_eq!(tc.current_duration(0), Some(Duration::seconds(60)));
+    clock.advance(Duration::seconds(15));
+    tc.update_stage(2);
+    assert_eq!(tc.current_duration(1), Some(Duration::seconds(60)));
+}
+
+#[tokio::test]
+async fn sleep_ends_when_clock_is_advanced() {
+    let clock = Arc::new(ManualClock::default());
+    let sleeping = tokio::spawn({
+        let clock = clock.clone();
+        async move { clock.sleep(std::time::Duration::from_secs(1)).await }
+    });
+    tokio::task::yield_now().await;
+    clock.advance(Duration::milliseconds(500));
+    tokio::task::yield_now().await;
+    assert!(!sleeping.is_finished());
+    clock.advance(Duration::milliseconds(500));
+    let timeout = std::time::Duration::from_secs(1);
+    tokio::time::timeout(timeout, sleeping)
+        .await
+        .unwrap()
+        .unwrap();
+}
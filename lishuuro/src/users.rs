@@ -0,0 +1,97 @@
+use axum::{
+    extract::{Path, State},
+    routing::get,
+    Json, Router,
+};
+use serde_json::Value;
+
+use crate::{
+    database::{
+        mongo::{Mongo, ProfileStats},
+        queries::{get_profile_stats, profile_stats, set_profile_stats},
+    },
+    metrics::timed,
+    AppState,
+};
+
+pub fn users() -> Router<AppState> {
+    Router::new()
+        .route("/:name", get(profile))
+        .route("/:name/following", get(following))
+}
+
+/// Get public profile with game statistics.
+pub async fn profile(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Json<Value> {
+    if let Some(player) = state.db.players.get_player(&name).await {
+        let stats = cached_stats(state.db.mongo.as_ref(), &name).await;
+        let created_at = player.created_at.timestamp_millis();
+        let age = bson::DateTime::now().timestamp_millis() - created_at;
+        let ratings: serde_json::Map<String, Value> = player
+            .ratings
+            .iter()
+            .map(|(key, r)| {
+                let rating = serde_json::json!({
+                    "rating": r.rating.round(),
+                    "deviation": r.deviation.round(),
+                    "games": r.games,
+                    "provisional": r.is_provisional()
+                });
+                (String::from(key), rating)
+            })
+            .collect();
+        return Json(serde_json::json!({
+            "exist": true,
+            "username": &player._id,
+            "reg": player.reg,
+            "created_at": created_at,
+            "age_days": age / (24 * 60 * 60 * 1000),
+            "ratings": ratings,
+            "variants": stats.variants,
+            "avg_length": stats.avg_length,
+            "purchases": stats.purchases
+        }));
+    }
+    Json(serde_json::json!({"exist": false}))
+}
+
+/// Saved statistics or new ones. Empty without MongoDB.
+async fn cached_stats(db: Option<&Mongo>, name: &String) -> ProfileStats {
+    let db = match db {
+        Some(db) => db,
+        None => {
+            return ProfileStats {
+                _id: String::from(name),
+                variants: vec![],
+                avg_length: 0.0,
+                purchases: vec![],
+            }
+        }
+    };
+    let stats = get_profile_stats(&db.profiles, name);
+    if let Some(stats) = timed("mongo", "get_profile_stats", stats).await {
+        stats
+    } else {
+        let stats = profile_stats(&db.games, name);
+        let stats = timed("mongo", "profile_stats", stats).await;
+        let set = set_profile_stats(&db.profiles, &stats);
+        timed("mongo", "set_profile_stats", set).await;
+        stats
+    }
+}
+
+/// Get all followed players with their current status.
+pub async fn following(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Json<Value> {
+    if let Some(player) = state.db.players.get_player(&name).await {
+        let following = state.ws.friends_status(&player.following);
+        return Json(
+            serde_json::json!({"exist": true, "following": following}),
+        );
+    }
+    Json(serde_json::json!({"exist": false}))
+}
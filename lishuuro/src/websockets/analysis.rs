@@ -0,0 +1,440 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    ops::{BitAnd, BitOr, BitOrAssign, Not},
+    sync::{Arc, Mutex},
+};
+
+use async_session::chrono::Duration;
+use bson::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use shuuro::{
+    attacks::Attacks,
+    bitboard::BitBoard,
+    position::Position,
+    shuuro12::{
+        attacks12::Attacks12, bitboard12::BB12, position12::P12,
+        square12::Square12,
+    },
+    shuuro8::{
+        attacks8::Attacks8, bitboard8::BB8, position8::P8, square8::Square8,
+    },
+    Square,
+};
+
+use crate::{arc2, database::mongo::ShuuroGame};
+
+use super::{
+    games::ShuuroGames, live_game::LiveGame, server_messages::analysis_state,
+    time_control::TimeControl, GameGet, SHOP_CREDIT, VARIANTS,
+};
+
+/// Request for analysis board. Stage and SFEN are used for new position.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AnalysisReq {
+    #[serde(default)]
+    pub variant: String,
+    #[serde(default)]
+    pub stage: u8,
+    #[serde(default)]
+    pub sfen: String,
+    #[serde(default)]
+    pub game_move: String,
+}
+
+/// Saved position with shop credit left, used for undo and redo.
+struct Snapshot {
+    stage: u8,
+    sfen: String,
+    credits: [i32; 2],
+}
+
+/// One analysis session with undo and redo positions.
+pub struct AnalysisBoard<S, B, A, P>
+where
+    S: Square + Hash,
+    B: BitBoard<S>,
+    A: Attacks<S, B>,
+    P: Position<S, B, A>,
+    for<'a> &'a B: BitOr<&'a B, Output = B>,
+    for<'a> &'a B: BitAnd<&'a B, Output = B>,
+    for<'a> &'a B: Not<Output = B>,
+    for<'a> &'a B: BitOr<&'a S, Output = B>,
+    for<'a> &'a B: BitAnd<&'a S, Output = B>,
+    for<'a> B: BitOrAssign<&'a S>,
+{
+    current: LiveGame<S, B, A, P>,
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+}
+
+impl<S, B, A, P> AnalysisBoard<S, B, A, P>
+where
+    S: Square + Hash,
+    B: BitBoard<S> + Iterator<Item = S>,
+    A: Attacks<S, B>,
+    P: Position<S, B, A>,
+    for<'a> &'a B: BitOr<&'a B, Output = B>,
+    for<'a> &'a B: BitAnd<&'a B, Output = B>,
+    for<'a> &'a B: Not<Output = B>,
+    for<'a> &'a B: BitOr<&'a S, Output = B>,
+    for<'a> &'a B: BitAnd<&'a S, Output = B>,
+    for<'a> B: BitOrAssign<&'a S>,
+{
+    fn new(username: &str, variant: &str) -> Self {
+        let game = analysis_game(username, variant);
+        Self {
+            current: LiveGame::analysis(game),
+            undo: vec![],
+            redo: vec![],
+        }
+    }
+
+    /// Change board and save previous position if change is valid.
+    fn step(&mut self, f: impl FnOnce(&mut LiveGame<S, B, A, P>) -> bool) {
+        let before = self.snapshot();
+        if f(&mut self.current) {
+            self.undo.push(before);
+            self.redo.clear();
+        }
+    }
+
+    fn undo(&mut self) {
+        if let Some(snapshot) = self.undo.pop() {
+            self.redo.push(self.snapshot());
+            self.restore(snapshot);
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(snapshot) = self.redo.pop() {
+            self.undo.push(self.snapshot());
+            self.restore(snapshot);
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let (stage, sfen) = self.current.position();
+        Snapshot {
+            stage,
+            sfen,
+            credits: self.current.credits_left(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.current.set_position(snapshot.stage, &snapshot.sfen);
+        if snapshot.stage == 0 {
+            self.current.set_credits_left(snapshot.credits);
+        }
+    }
+
+    fn state(&self) -> Value {
+        analysis_state(
+            &self.current.game,
+            self.current.outcome(),
+            &self.current.legal_moves(),
+            !self.undo.is_empty(),
+            !self.redo.is_empty(),
+        )
+    }
+}
+
+/// Game used for analysis, both sides belong to same player.
+fn analysis_game(username: &str, variant: &str) -> ShuuroGame {
+    ShuuroGame {
+        _id: format!("analysis_{username}"),
+        min: Duration::zero(),
+        incr: Duration::zero(),
+        players: [String::from(username), String::from(username)],
+        side_to_move: 0,
+        clocks: [Duration::zero(), Duration::zero()],
+        last_clock: DateTime::now(),
+        current_stage: 0,
+        result: String::from(""),
+        status: -1,
+        variant: String::from(variant),
+        credits: [SHOP_CREDIT, SHOP_CREDIT],
+        hands: [String::from(""), String::from("")],
+        sfen: String::from(""),
+        history: (vec![], vec![], vec![]),
+        tc: TimeControl::new(0, 0),
+        draws: [false, false],
+        sub_variant: None,
+        rated: false,
+        ratings: None,
+        start_sfen: None,
+        player_ids: [String::new(), String::new()],
+    }
+}
+
+/// Request for legal moves in live game or in any position.
+/// In deployment square is piece from hand.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HintsReq {
+    #[serde(default)]
+    pub game_id: String,
+    pub variant: String,
+    #[serde(default)]
+    pub sfen: String,
+    #[serde(default)]
+    pub stage: Option<u8>,
+    pub square: String,
+}
+
+/// Legal destinations for one square, without changing any game.
+pub fn square_hints(
+    games: &ShuuroGames,
+    req: &HintsReq,
+) -> Option<Vec<String>> {
+    let mut moves = {
+        if !req.game_id.is_empty() {
+            let json = GameGet {
+                t: String::from(""),
+                game_id: String::from(&req.game_id),
+                game_move: String::from(""),
+                variant: String::from(&req.variant),
+            };
+            games.legal_moves(&json)?
+        } else if !VARIANTS.contains(&req.variant.as_str()) {
+            return None;
+        } else if req.variant.contains("shuuro") {
+            position_moves::<
+                Square12,
+                BB12<Square12>,
+                Attacks12<Square12, BB12<Square12>>,
+                P12<Square12, BB12<Square12>>,
+            >(req)?
+        } else {
+            position_moves::<
+                Square8,
+                BB8<Square8>,
+                Attacks8<Square8, BB8<Square8>>,
+                P8<Square8, BB8<Square8>>,
+            >(req)?
+        }
+    };
+    Some(moves.remove(&req.square).unwrap_or_default())
+}
+
+/// Check if custom starting position can be used for game.
+pub fn valid_position(variant: &str, stage: u8, sfen: &str) -> bool {
+    if !(1..=2).contains(&stage) || !VARIANTS.contains(&variant) {
+        return false;
+    }
+    if variant.contains("shuuro") {
+        parse_position::<
+            Square12,
+            BB12<Square12>,
+            Attacks12<Square12, BB12<Square12>>,
+            P12<Square12, BB12<Square12>>,
+        >(variant, stage, sfen)
+        .is_some()
+    } else {
+        parse_position::<
+            Square8,
+            BB8<Square8>,
+            Attacks8<Square8, BB8<Square8>>,
+            P8<Square8, BB8<Square8>>,
+        >(variant, stage, sfen)
+        .is_some()
+    }
+}
+
+/// Analysis game in given position, if position is valid.
+fn parse_position<S, B, A, P>(
+    variant: &str,
+    stage: u8,
+    sfen: &str,
+) -> Option<LiveGame<S, B, A, P>>
+where
+    S: Square + Hash,
+    B: BitBoard<S> + Iterator<Item = S>,
+    A: Attacks<S, B>,
+    P: Position<S, B, A>,
+    for<'a> &'a B: BitOr<&'a B, Output = B>,
+    for<'a> &'a B: BitAnd<&'a B, Output = B>,
+    for<'a> &'a B: Not<Output = B>,
+    for<'a> &'a B: BitOr<&'a S, Output = B>,
+    for<'a> &'a B: BitAnd<&'a S, Output = B>,
+    for<'a> B: BitOrAssign<&'a S>,
+{
+    let game = analysis_game("", variant);
+    let mut live_game = LiveGame::<S, B, A, P>::analysis(game);
+    if !live_game.set_position(stage, sfen) {
+        return None;
+    }
+    Some(live_game)
+}
+
+fn position_moves<S, B, A, P>(
+    req: &HintsReq,
+) -> Option<HashMap<String, Vec<String>>>
+where
+    S: Square + Hash,
+    B: BitBoard<S> + Iterator<Item = S>,
+    A: Attacks<S, B>,
+    P: Position<S, B, A>,
+    for<'a> &'a B: BitOr<&'a B, Output = B>,
+    for<'a> &'a B: BitAnd<&'a B, Output = B>,
+    for<'a> &'a B: Not<Output = B>,
+    for<'a> &'a B: BitOr<&'a S, Output = B>,
+    for<'a> &'a B: BitAnd<&'a S, Output = B>,
+    for<'a> B: BitOrAssign<&'a S>,
+{
+    let stage = req.stage.unwrap_or(2);
+    let live_game =
+        parse_position::<S, B, A, P>(&req.variant, stage, &req.sfen)?;
+    Some(live_game.legal_moves())
+}
+
+type Boards<S, B, A, P> =
+    Arc<Mutex<HashMap<String, AnalysisBoard<S, B, A, P>>>>;
+
+type Boards8 = Boards<
+    Square8,
+    BB8<Square8>,
+    Attacks8<Square8, BB8<Square8>>,
+    P8<Square8, BB8<Square8>>,
+>;
+
+type Boards12 = Boards<
+    Square12,
+    BB12<Square12>,
+    Attacks12<Square12, BB12<Square12>>,
+    P12<Square12, BB12<Square12>>,
+>;
+
+/// Run closure on analysis board for this player.
+macro_rules! with_board {
+    ($self: ident, $username: expr, $board: ident, $body: expr) => {
+        if let Some($board) = $self.boards8.lock().unwrap().get_mut($username) {
+            Some($body)
+        } else if let Some($board) =
+            $self.boards12.lock().unwrap().get_mut($username)
+        {
+            Some($body)
+        } else {
+            None
+        }
+    };
+}
+
+/// Analysis boards for all players, one board per player.
+/// Board is shared by all sockets of player.
+#[derive(Clone)]
+pub struct AnalysisBoards {
+    boards8: Boards8,
+    boards12: Boards12,
+    sockets: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl Default for AnalysisBoards {
+    fn default() -> Self {
+        Self {
+            boards8: arc2(HashMap::new()),
+            boards12: arc2(HashMap::new()),
+            sockets: arc2(HashMap::new()),
+        }
+    }
+}
+
+impl AnalysisBoards {
+    /// Start new board, previous board is closed.
+    pub fn new_board(
+        &self,
+        username: &str,
+        req: &AnalysisReq,
+    ) -> Option<Value> {
+        if !VARIANTS.contains(&req.variant.as_str()) {
+            return None;
+        }
+        self.close(username);
+        let username = String::from(username);
+        if req.variant.contains("shuuro") {
+            let mut board = AnalysisBoard::new(&username, &req.variant);
+            if !req.sfen.is_empty() {
+                board.current.set_position(req.stage, &req.sfen);
+            }
+            let state = board.state();
+            self.boards12.lock().unwrap().insert(username, board);
+            Some(state)
+        } else {
+            let mut board = AnalysisBoard::new(&username, &req.variant);
+            if !req.sfen.is_empty() {
+                board.current.set_position(req.stage, &req.sfen);
+            }
+            let state = board.state();
+            self.boards8.lock().unwrap().insert(username, board);
+            Some(state)
+        }
+    }
+
+    /// Set any position.
+    pub fn set_position(
+        &self,
+        username: &str,
+        req: &AnalysisReq,
+    ) -> Option<Value> {
+        with_board!(self, username, board, {
+            board.step(|g| g.set_position(req.stage, &req.sfen));
+            board.state()
+        })
+    }
+
+    /// Buy, place or move piece.
+    pub fn play(&self, username: &str, req: &AnalysisReq) -> Option<Value> {
+        with_board!(self, username, board, {
+            board.step(|g| g.analysis_move(&req.game_move));
+            board.state()
+        })
+    }
+
+    /// Move from shop to deployment.
+    pub fn deploy(&self, username: &str) -> Option<Value> {
+        with_board!(self, username, board, {
+            board.step(|g| g.analysis_deploy());
+            board.state()
+        })
+    }
+
+    pub fn undo(&self, username: &str) -> Option<Value> {
+        with_board!(self, username, board, {
+            board.undo();
+            board.state()
+        })
+    }
+
+    pub fn redo(&self, username: &str) -> Option<Value> {
+        with_board!(self, username, board, {
+            board.redo();
+            board.state()
+        })
+    }
+
+    /// Count new socket for player.
+    pub fn connect(&self, username: &str) {
+        let mut sockets = self.sockets.lock().unwrap();
+        *sockets.entry(String::from(username)).or_default() += 1;
+    }
+
+    /// Board is closed when last socket of player is closed.
+    pub fn disconnect(&self, username: &str) {
+        let mut sockets = self.sockets.lock().unwrap();
+        if let Some(count) = sockets.get_mut(username) {
+            *count -= 1;
+            if *count > 0 {
+                return;
+            }
+        }
+        sockets.remove(username);
+        self.close(username);
+    }
+
+    /// Remove board after player leaves it.
+    pub fn close(&self, username: &str) {
+        self.boards8.lock().unwrap().remove(username);
+        self.boards12.lock().unwrap().remove(username);
+    }
+}
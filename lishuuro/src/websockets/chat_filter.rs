@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bson::DateTime;
+
+use crate::{arc2, config::ChatConfig};
+
+/// Words used when `chat.words_file` is not set.
+const DEFAULT_WORDS: [&str; 10] = [
+    "fuck", "shit", "bitch", "cunt", "asshole", "dick", "nigger", "faggot",
+    "retard", "whore",
+];
+
+/// Domains that count as link even without `http://` or `www.`.
+const LINK_TLDS: [&str; 24] = [
+    "com", "net", "org", "io", "gg", "tv", "me", "co", "ru", "de", "uk",
+    "info", "biz", "xyz", "app", "dev", "link", "site", "online", "club",
+    "top", "ly", "to", "cc",
+];
+
+/// Token bucket used for rate limiting.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    per_second: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, per_second: f64) -> Self {
+        Self {
+            tokens: capacity,
+            capacity,
+            per_second,
+            last: Instant::now(),
+        }
+    }
+
+    /// Take one token if there is any left.
+    pub fn take(&mut self) -> bool {
+        let elapsed = self.last.elapsed().as_secs_f64();
+        self.last = Instant::now();
+        self.tokens =
+            (self.tokens + elapsed * self.per_second).min(self.capacity);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return true;
+        }
+        false
+    }
+
+    /// There is token to take.
+    pub fn has_token(&self) -> bool {
+        let elapsed = self.last.elapsed().as_secs_f64();
+        self.tokens + elapsed * self.per_second >= 1.0
+    }
+
+    /// Last time token was taken.
+    pub fn last_used(&self) -> Instant {
+        self.last
+    }
+
+    /// Bucket is refilled, so it's same as new one.
+    pub fn is_full(&self) -> bool {
+        let elapsed = self.last.elapsed().as_secs_f64();
+        self.tokens + elapsed * self.per_second >= self.capacity
+    }
+}
+
+/// Last messages for one player with time when they are sent.
+type RecentMessages = Vec<(String, Instant)>;
+
+/// Result of checking one message.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FilterResult {
+    Ok,
+    RateLimited,
+    Hidden(&'static str),
+}
+
+/// Filter for all chat messages.
+#[derive(Clone)]
+pub struct ChatFilter {
+    words: Vec<String>,
+    config: ChatConfig,
+    buckets: Arc<Mutex<HashMap<String, TokenBucket>>>,
+    last: Arc<Mutex<HashMap<String, RecentMessages>>>,
+    accounts: Arc<Mutex<HashMap<String, DateTime>>>,
+}
+
+impl Default for ChatFilter {
+    fn default() -> Self {
+        Self::new(&ChatConfig::default())
+    }
+}
+
+impl ChatFilter {
+    pub fn new(config: &ChatConfig) -> Self {
+        let words = {
+            if let Some(path) = &config.words_file {
+                fs::read_to_string(path)
+                    .map(|f| f.lines().map(normalize).collect())
+                    .unwrap_or_default()
+            } else {
+                DEFAULT_WORDS.iter().map(|w| normalize(w)).collect()
+            }
+        };
+        Self::with_words(words, config)
+    }
+
+    pub fn with_words(words: Vec<String>, config: &ChatConfig) -> Self {
+        Self {
+            words: words.into_iter().filter(|w| !w.is_empty()).collect(),
+            config: config.clone(),
+            buckets: arc2(HashMap::new()),
+            last: arc2(HashMap::new()),
+            accounts: arc2(HashMap::new()),
+        }
+    }
+
+    /// Save when account was created, used for link check.
+    pub fn add_account(&self, username: &str, created_at: DateTime) {
+        self.accounts
+            .lock()
+            .unwrap()
+            .insert(String::from(username), created_at);
+    }
+
+    /// Run all checks for one message.
+    pub fn check(&self, username: &str, message: &str) -> FilterResult {
+        if !self.take_token(username) {
+            return FilterResult::RateLimited;
+        }
+        if self.is_repeated(username, message) {
+            return FilterResult::Hidden("repeated");
+        }
+        if self.is_new_account(username) && has_link(message) {
+            return FilterResult::Hidden("link");
+        }
+        if self.has_bad_word(message) {
+            return FilterResult::Hidden("profanity");
+        }
+        FilterResult::Ok
+    }
+
+    fn take_token(&self, username: &str) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets
+            .entry(String::from(username))
+            .or_insert_with(|| {
+                let refill = 1.0 / self.config.refill_secs;
+                TokenBucket::new(self.config.burst, refill)
+            })
+            .take()
+    }
+
+    /// Check last 3 messages from this player.
+    fn is_repeated(&self, username: &str, message: &str) -> bool {
+        let message = normalize(message);
+        let mut last = self.last.lock().unwrap();
+        let history = last.entry(String::from(username)).or_default();
+        let repeat = Duration::from_secs(self.config.repeat_secs);
+        history.retain(|m| m.1.elapsed() < repeat);
+        let repeated = history.iter().any(|m| m.0 == message);
+        history.push((message, Instant::now()));
+        if history.len() > 3 {
+            history.remove(0);
+        }
+        repeated
+    }
+
+    fn is_new_account(&self, username: &str) -> bool {
+        let accounts = self.accounts.lock().unwrap();
+        if let Some(created_at) = accounts.get(username) {
+            let age = DateTime::now().timestamp_millis()
+                - created_at.timestamp_millis();
+            return age < self.config.new_account_days * 24 * 60 * 60 * 1000;
+        }
+        true
+    }
+
+    fn has_bad_word(&self, message: &str) -> bool {
+        let mut tokens: Vec<String> =
+            message.split_whitespace().map(normalize).collect();
+        // spaced out words like "b a d"
+        let letters: String = message
+            .split_whitespace()
+            .filter(|w| w.chars().count() == 1)
+            .collect();
+        tokens.push(normalize(&letters));
+        tokens
+            .iter()
+            .any(|t| !t.is_empty() && self.words.contains(t))
+    }
+}
+
+/// Lowercase, replace leetspeak, remove symbols and repeated letters.
+pub fn normalize(text: &str) -> String {
+    let mut normalized = String::new();
+    for c in text.to_lowercase().chars() {
+        let c = match c {
+            '0' => 'o',
+            '1' | '!' | '|' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' => 't',
+            '8' => 'b',
+            '9' => 'g',
+            c => c,
+        };
+        if c.is_alphanumeric() && !normalized.ends_with(c) {
+            normalized.push(c);
+        }
+    }
+    normalized
+}
+
+/// Check if message contains url.
+fn has_link(message: &str) -> bool {
+    let message = message.to_lowercase();
+    if message.contains("http://")
+        || message.contains("https://")
+        || message.contains("www.")
+    {
+        return true;
+    }
+    message.split_whitespace().any(|word| {
+        let word = word.trim_end_matches(|c: char| !c.is_alphanumeric());
+        let word = word.split('/').next().unwrap_or_default();
+        if let Some((name, tld)) = word.rsplit_once('.') {
+            return name.ends_with(|c: char| c.is_alphanumeric())
+                && LINK_TLDS.contains(&tld);
+        }
+        false
+    })
+}
@@ -1,8 +1,6 @@
-use std::sync::{Arc, Mutex};
-
 use serde::{Deserialize, Serialize};
 
-use super::{rooms::ChatMsg, time_control::TimeCheck};
+use super::rooms::ChatMsg;
 
 /// This struct is used for most game moves.
 #[derive(Clone, Serialize, Deserialize)]
@@ -14,6 +12,61 @@ pub struct GameGet {
     pub variant: String,
 }
 
+/// Message used for deleting and reporting chat messages.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChatAction {
+    pub id: String,
+    pub msg_id: u64,
+    #[serde(default)]
+    pub variant: String,
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// Moderator request for muting player. Zero minutes removes mute.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MuteRequest {
+    pub username: String,
+    pub minutes: i64,
+}
+
+/// Moderator warning sent to player.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WarnRequest {
+    pub username: String,
+    pub message: String,
+}
+
+/// Moderator request for resolving report.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReportResolve {
+    pub report_id: String,
+}
+
+/// New private message.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DirectMsgReq {
+    pub to: String,
+    pub message: String,
+}
+
+/// Request for one conversation page, or for blocking player.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InboxReq {
+    pub username: String,
+    #[serde(default)]
+    pub page: u64,
+    #[serde(default)]
+    pub block: bool,
+}
+
+/// Follow or unfollow player.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FollowReq {
+    pub username: String,
+    pub follow: bool,
+}
+
 pub enum LiveGameMove {
     BuyMove([bool; 2]),
     LostOnTime(usize),
@@ -24,7 +77,6 @@ pub enum LiveGameMove {
 #[derive(Clone)]
 pub enum MsgDatabase {
     GetGame(String),
-    LostOnTime(Arc<Mutex<TimeCheck>>),
     InsertGameMove(GameGet),
 }
 
@@ -39,6 +91,12 @@ impl From<&ChatMsg> for GameGet {
     }
 }
 
+impl From<&ChatAction> for GameGet {
+    fn from(value: &ChatAction) -> Self {
+        GameGet::new(&value.id, &value.variant)
+    }
+}
+
 impl GameGet {
     pub fn new(id: &String, variant: &String) -> Self {
         Self {
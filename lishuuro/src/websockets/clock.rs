@@ -0,0 +1,76 @@
+use std::{fmt::Debug, sync::Arc, time::Duration};
+
+use async_session::async_trait;
+use chrono::{DateTime, Utc};
+
+/// Source of current time for game clocks, chat and clock tasks.
+#[async_trait]
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Wait until clock moves forward for `duration`.
+    async fn sleep(&self, duration: Duration);
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// Real time clock.
+pub fn system_clock() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+/// Clock that moves only when it's advanced. Used in tests.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct ManualClock {
+    now: tokio::sync::watch::Sender<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+impl Default for ManualClock {
+    fn default() -> Self {
+        let (now, _) = tokio::sync::watch::channel(Utc::now());
+        Self { now }
+    }
+}
+
+#[cfg(test)]
+impl ManualClock {
+    /// Move time forward and wake all tasks that sleep until then.
+    pub fn advance(&self, duration: chrono::Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.borrow()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        let deadline =
+            self.now() + chrono::Duration::from_std(duration).unwrap();
+        let mut now = self.now.subscribe();
+        while *now.borrow_and_update() < deadline {
+            if now.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
@@ -12,7 +12,9 @@ use crate::{
     database::serde_helpers::{deserialize_subvariant, serialize_subvariant},
 };
 
-use super::{server_messages::home_lobby_game, GameGet};
+use super::{
+    analysis::valid_position, server_messages::home_lobby_game, GameGet, SendTo,
+};
 
 pub const VARIANTS: [&str; 4] =
     ["shuuro", "shuuroFairy", "standard", "standardFairy"];
@@ -21,6 +23,25 @@ pub const DURATION_RANGE: [i64; 28] = [
     30, 35, 40, 45, 60, 75, 90,
 ];
 
+/// Credits every player gets in shop.
+pub const SHOP_CREDIT: u16 = 800;
+
+/// Highest credits player can get in handicap game.
+pub const MAX_CREDIT: u16 = 1200;
+
+/// Lowest and highest credits for variant.
+pub fn credit_limits(variant: &str) -> (u16, u16) {
+    if variant.contains("shuuro") {
+        (400, MAX_CREDIT)
+    } else {
+        (300, MAX_CREDIT)
+    }
+}
+
+fn default_credits() -> [u16; 2] {
+    [SHOP_CREDIT, SHOP_CREDIT]
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct GameRequest {
     pub username: String,
@@ -31,14 +52,27 @@ pub struct GameRequest {
     #[serde(deserialize_with = "deserialize_subvariant")]
     pub sub_variant: Option<SubVariant>,
     color: String,
+    /// Only this player can accept request.
+    #[serde(default)]
+    pub opponent: Option<String>,
+    /// Custom starting position for deploy or fight stage.
+    #[serde(default)]
+    pub sfen: Option<String>,
+    #[serde(default)]
+    pub stage: u8,
+    /// Credits for player who made request and for opponent.
+    #[serde(default = "default_credits")]
+    pub credits: [u16; 2],
 }
 
 impl GameRequest {
-    /// Return true if game has valid time.
+    /// Return true if game has valid time and credits.
     pub fn is_valid(&self) -> bool {
+        let (min, max) = credit_limits(&self.variant);
         if VARIANTS.contains(&self.variant.as_str())
             && DURATION_RANGE.contains(&self.time)
             && (DURATION_RANGE.contains(&self.incr) || self.incr == 0)
+            && self.credits.iter().all(|c| (min..=max).contains(c))
         {
             return true;
         }
@@ -63,6 +97,36 @@ impl GameRequest {
         }
     }
 
+    /// Challenge is seen only by both players, other requests by everyone.
+    pub fn audience(&self) -> SendTo {
+        match &self.opponent {
+            Some(opponent) => SendTo::Users(vec![
+                String::from(&self.username),
+                String::from(opponent),
+            ]),
+            None => SendTo::All,
+        }
+    }
+
+    /// Player can see this request in lobby.
+    pub fn is_visible(&self, username: &String) -> bool {
+        match &self.opponent {
+            Some(opponent) => {
+                opponent == username || &self.username == username
+            }
+            None => true,
+        }
+    }
+
+    /// Credits for white and black.
+    pub fn color_credits(&self, players: &[String; 2]) -> [u16; 2] {
+        if players[0] == self.username {
+            self.credits
+        } else {
+            [self.credits[1], self.credits[0]]
+        }
+    }
+
     /// Generate random color.
     fn random_color(&self) -> String {
         if rand::random() {
@@ -107,6 +171,11 @@ impl GameReqs {
                 return None;
             }
         }
+        if let Some(sfen) = &game.sfen {
+            if !valid_position(&game.variant, game.stage, sfen) {
+                return None;
+            }
+        }
         if !all.contains_key(&game.username) && game.is_valid() {
             let res = home_lobby_game("home_lobby_add", &game);
             all.insert(String::from(&game.username), game);
@@ -115,21 +184,41 @@ impl GameReqs {
         None
     }
 
-    /// Remove game from struct.
-    pub fn remove(&self, t: &str, username: &String) -> Option<Value> {
+    /// Remove game from struct, with players who can see it.
+    pub fn remove(
+        &self,
+        t: &str,
+        username: &String,
+    ) -> Option<(Value, SendTo)> {
         let mut all = self.all.lock().unwrap();
         if let Some(game) = all.remove(username) {
             let res = home_lobby_game(t, &game);
-            return Some(res);
+            return Some((res, game.audience()));
         }
         None
     }
 
-    /// Get all game requests.
-    pub fn get_all(&self) -> Vec<GameRequest> {
+    /// Check if other player can accept this request.
+    pub fn can_accept(&self, username: &String, other: &String) -> bool {
+        let all = self.all.lock().unwrap();
+        if let Some(game) = all.get(username) {
+            if let Some(opponent) = &game.opponent {
+                return opponent == other;
+            }
+        }
+        true
+    }
+
+    /// Count all game requests.
+    pub fn count(&self) -> usize {
+        self.all.lock().unwrap().len()
+    }
+
+    /// Get all game requests that player can see.
+    pub fn get_all(&self, username: &String) -> Vec<GameRequest> {
         let all = self.all.lock().unwrap();
         let mut g = vec![];
-        for i in all.values() {
+        for i in all.values().filter(|i| i.is_visible(username)) {
             g.push(i.clone());
         }
         g
@@ -1,9 +1,9 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
 };
 
-use mongodb::Collection;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use shuuro::{
@@ -16,11 +16,16 @@ use shuuro::{
     },
 };
 
-use crate::database::{mongo::ShuuroGame, redis::UserSession};
+use crate::{
+    database::{
+        mongo::ShuuroGame, redis::UserSession, repos::GameRepo, Database,
+    },
+    lichess::login::is_player_id,
+};
 
 use super::{
-    live_game::LiveGames, time_control::TimeCheck, GameGet, LiveGameMove,
-    MessageHandler,
+    leaderboards::Leaderboards, live_game::LiveGames, time_control::TimeCheck,
+    GameGet, LiveGameMove, MessageHandler,
 };
 
 // macro_rules! tv {
@@ -74,9 +79,10 @@ impl ShuuroGames {
     pub async fn remove_game(
         &self,
         json: &GameGet,
-        db: &Collection<ShuuroGame>,
+        db: &Arc<Database>,
+        leaderboards: &Leaderboards,
     ) {
-        send!(1, self, json, remove_game, db, &json.game_id);
+        send!(1, self, json, remove_game, db, leaderboards, &json.game_id);
     }
 
     /// Count all games.
@@ -86,6 +92,14 @@ impl ShuuroGames {
         first + second
     }
 
+    /// Count games by variant and stage.
+    pub fn stage_counts(&self) -> BTreeMap<(String, u8), usize> {
+        let mut counts = BTreeMap::new();
+        self.live_games8.stage_counts(&mut counts);
+        self.live_games12.stage_counts(&mut counts);
+        counts
+    }
+
     /// Load games from db
     /// First game is for `P8`
     pub fn load_unfinished(&self, games: Vec<HashMap<String, ShuuroGame>>) {
@@ -99,18 +113,6 @@ impl ShuuroGames {
         }
     }
 
-    pub fn get_unfinished(&self) -> [Vec<String>; 2] {
-        [
-            self.live_games8.get_unfinished(),
-            self.live_games12.get_unfinished(),
-        ]
-    }
-
-    pub fn delete_unfinished(&self) {
-        self.live_games8.del_unfinished();
-        self.live_games12.del_unfinished();
-    }
-
     // SHOP PART
 
     pub fn change_variant(&self, json: &GameGet) {
@@ -170,28 +172,29 @@ impl ShuuroGames {
 
     /// CLOCK PART
 
-    /// After every 500ms, this function returns who lost on time.
+    /// Update status for game where player lost on time.
     pub fn clock_status(
         &self,
         json: &GameGet,
-        time_check: &Arc<Mutex<TimeCheck>>,
+        time_check: &TimeCheck,
     ) -> Option<(Value, Value, [String; 2])> {
         send!(0, self, json, clock_status, time_check)
     }
 
-    /// Check clocks for current stage.
-    pub fn check_clocks(
-        &self,
-        json: &GameGet,
-        time_check: &Arc<Mutex<TimeCheck>>,
-    ) {
-        send!(0, self, json, check_clocks, time_check);
+    /// Check clocks for current stage. Returns `None` if game doesn't exist.
+    pub fn check_clocks(&self, json: &GameGet) -> Option<TimeCheck> {
+        send!(0, self, json, check_clocks, &json.game_id)
+    }
+
+    /// Next time when one of players can lose on time.
+    pub fn deadline(&self, json: &GameGet) -> Option<DateTime<Utc>> {
+        send!(0, self, json, deadline, &json.game_id)
     }
 
     pub async fn get_game<'a>(
         &self,
         json: &GameGet,
-        db: &Collection<ShuuroGame>,
+        db: &dyn GameRepo,
         s: &'a MessageHandler<'a>,
     ) -> Option<ShuuroGame> {
         let mut json = json.clone();
@@ -210,10 +213,45 @@ impl ShuuroGames {
         send!(1, self, json, get_game, &json.game_id, db, s, true)
     }
 
+    /// Public game ID if `id` is private ID of player.
+    /// Other IDs return `None`.
+    pub async fn public_id(
+        &self,
+        id: &str,
+        db: &dyn GameRepo,
+    ) -> Option<String> {
+        if !is_player_id(id) {
+            return None;
+        }
+        let live = self
+            .live_games8
+            .player_game_id(id)
+            .or_else(|| self.live_games12.player_game_id(id));
+        match live {
+            Some(public) => Some(public),
+            None => db.player_game_id(id).await,
+        }
+    }
+
+    /// Find live game for this player.
+    pub fn player_game(&self, username: &String) -> Option<(String, u8)> {
+        if let Some(game) = self.live_games8.player_game(username) {
+            return Some(game);
+        }
+        self.live_games12.player_game(username)
+    }
+
     pub fn live_sfen(&self, json: &GameGet) -> Option<(u8, String)> {
         send!(0, self, json, live_sfen, &json.game_id)
     }
 
+    pub fn legal_moves(
+        &self,
+        json: &GameGet,
+    ) -> Option<HashMap<String, Vec<String>>> {
+        send!(0, self, json, legal_moves, &json.game_id)
+    }
+
     /// Resign if this player exist in game.
     pub fn resign(
         &self,
@@ -223,7 +261,7 @@ impl ShuuroGames {
         send!(0, self, json, resign, &json.game_id, username)
     }
 
-    pub async fn save_on_exit(&self, games: &Collection<ShuuroGame>) {
+    pub async fn save_on_exit(&self, games: &dyn GameRepo) {
         self.live_games8.save_on_exit(games).await;
         self.live_games12.save_on_exit(games).await;
     }
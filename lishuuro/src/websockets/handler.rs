@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
 use axum::{
     extract::{
@@ -11,21 +11,27 @@ use axum::{
 };
 use futures::{SinkExt, StreamExt};
 use serde_json::Value;
-use tokio::sync::broadcast;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc,
+};
+use tracing::{debug, info_span, warn, Instrument};
 
 use crate::{
-    database::{
-        queries::{get_game_db, insert_move},
-        redis::UserSession,
-        Database,
-    },
+    database::{redis::UserSession, Database},
+    metrics::METRICS,
+    puzzles::PuzzleMove,
     websockets::{rooms::ChatMsg, SendTo},
     AppState,
 };
 
 use super::{
-    server_messages::live_game_start, ClientMessage, GameGet, GameRequest,
-    MessageHandler, MsgDatabase, MsgSender, WsState,
+    analysis::{AnalysisReq, HintsReq},
+    rate_limit::{ClientIp, Strikes},
+    server_messages::{live_game_start, rate_limited},
+    ChatAction, ClientMessage, DirectMsgReq, FollowReq, GameGet, GameRequest,
+    InboxReq, MessageHandler, MsgDatabase, MsgSender, MuteRequest,
+    ReportResolve, WarnRequest, WsState,
 };
 
 macro_rules! send_or_break {
@@ -38,6 +44,7 @@ macro_rules! send_or_break {
             let _ = $sender.close().await;
             break;
         }
+        METRICS.message_out($msg.msg["t"].as_str().unwrap_or("unknown"));
     };
 }
 
@@ -46,12 +53,15 @@ pub async fn websocket_handler(
     ws: WebSocketUpgrade,
     _user_agent: Option<TypedHeader<UserAgent>>,
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
     user: UserSession,
 ) -> impl IntoResponse {
     let headers = &user.headers();
     (
         headers.clone(),
-        ws.on_upgrade(|socket| websocket(socket, state.db, state.ws, user)),
+        ws.on_upgrade(move |socket| {
+            websocket(socket, state.db, state.ws, user, ip)
+        }),
     )
 }
 
@@ -61,197 +71,416 @@ async fn websocket(
     db: Arc<Database>,
     ws: Arc<WsState>,
     user: UserSession,
+    ip: Option<IpAddr>,
 ) {
     let (mut sender, mut receiver) = stream.split();
+    METRICS.socket_opened();
+    // Only prefix, whole session id is enough to log in.
+    let session = user.session.get(..8).unwrap_or_default();
+    let span = info_span!("ws", username = %user.username, session);
+    span.in_scope(|| debug!("connected"));
 
     let mut rx = ws.tx.subscribe();
 
-    let (db_tx, mut db_rx) = broadcast::channel(100);
+    let (db_tx, mut db_rx) = broadcast::channel(ws.broadcast_capacity);
+
+    // Messages only for this socket, with flag for closing it.
+    let (local_tx, mut local_rx) = mpsc::unbounded_channel::<(Value, bool)>();
 
     let username = String::from(&user.username);
     let db2 = db.clone();
     let tx2 = ws.tx.clone();
     let user2 = user.clone();
 
-    let mut socket_send_task = tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
-            match &msg.to {
-                SendTo::Me => {
-                    if msg.username == username {
-                        send_or_break!(&mut sender, msg, &username);
+    let mut socket_send_task = tokio::spawn(
+        async move {
+            loop {
+                let msg = tokio::select! {
+                    msg = rx.recv() => match msg {
+                        Ok(msg) => msg,
+                        Err(RecvError::Lagged(skipped)) => {
+                            METRICS.broadcast_lagged(skipped);
+                            warn!(skipped, "socket is too slow");
+                            break;
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    Some((msg, closing)) = local_rx.recv() => {
+                        METRICS.message_out("rate_limited");
+                        let text = Message::Text(msg.to_string());
+                        if sender.send(text).await.is_err() || closing {
+                            let _ = sender.close().await;
+                            break;
+                        }
+                        continue;
                     }
-                }
-                SendTo::All => {
-                    send_or_break!(&mut sender, msg, &username);
-                }
-                SendTo::Spectators(s) => {
-                    if s.contains(&username) {
-                        send_or_break!(&mut sender, msg, &username);
+                };
+                match &msg.to {
+                    SendTo::Me => {
+                        if msg.username == username {
+                            send_or_break!(&mut sender, msg, &username);
+                        }
                     }
-                }
-                SendTo::Players(players) => {
-                    if players.contains(&username) {
+                    SendTo::All => {
                         send_or_break!(&mut sender, msg, &username);
                     }
-                }
-                SendTo::SpectatorsAndPlayers(sp) => {
-                    if sp.1.contains(&username) {
-                        send_or_break!(&mut sender, msg, &username);
-                    } else if sp.0.contains(&username) {
-                        send_or_break!(&mut sender, msg, &username);
+                    SendTo::User(u) => {
+                        if u == &username {
+                            send_or_break!(&mut sender, msg, &username);
+                        }
+                    }
+                    SendTo::Users(users) => {
+                        if users.contains(&username) {
+                            send_or_break!(&mut sender, msg, &username);
+                        }
+                    }
+                    SendTo::Spectators(s) => {
+                        if s.contains(&username) {
+                            send_or_break!(&mut sender, msg, &username);
+                        }
+                    }
+                    SendTo::Players(players) => {
+                        if players.contains(&username) {
+                            send_or_break!(&mut sender, msg, &username);
+                        }
+                    }
+                    SendTo::SpectatorsAndPlayers(sp) => {
+                        if sp.1.contains(&username) {
+                            send_or_break!(&mut sender, msg, &username);
+                        } else if sp.0.contains(&username) {
+                            send_or_break!(&mut sender, msg, &username);
+                        }
                     }
                 }
             }
         }
-    });
+        .instrument(span.clone()),
+    );
 
     let tx = ws.tx.clone();
 
-    let mut socket_recv_task = tokio::spawn(async move {
-        let msg_sender = MsgSender::new(&user, &tx);
-        let handler =
-            MessageHandler::new(&user, &ws, &tx, &db, &db_tx, msg_sender);
-        handler.connecting(true);
-        handler.start_unfinished_clock().await;
-        while let Some(Ok(msg)) = receiver.next().await {
-            match msg {
-                Message::Text(text) => {
-                    if let Ok(value) = serde_json::from_str::<Value>(&text) {
-                        let data_type = &value["t"];
-                        let data = value["data"].clone();
-                        if let serde_json::Value::String(t) = data_type {
-                            if t == "live_chat_message" {
-                                if let Ok(m) =
-                                    serde_json::from_value::<ChatMsg>(data)
-                                {
-                                    handler.new_chat_msg(m);
-                                }
-                            } else if t == "live_chat_full" {
-                                if let Ok(m) =
-                                    serde_json::from_value::<GameGet>(data)
-                                {
-                                    handler.get_chat(m.game_id);
-                                }
-                            } else if t == "active_players_full" {
-                                handler.get_players();
-                            } else if t == "active_players_count" {
-                                handler.get_players_count();
-                            } else if t == "live_game_remove_spectator" {
-                                if let Ok(m) =
-                                    serde_json::from_str::<GameGet>(&text)
-                                {
-                                    handler.remove_spectator(&m.game_id);
-                                }
-                            } else if t == "home_lobby_add" {
-                                if let Ok(g) =
-                                    serde_json::from_value::<GameRequest>(data)
-                                {
-                                    handler.add_game_req(g);
-                                }
-                            } else if t == "home_lobby_full" {
-                                handler.get_all_game_reqs();
-                            } else if t == "home_lobby_accept" {
-                                if let Ok(g) =
-                                    serde_json::from_value::<GameRequest>(data)
-                                {
-                                    handler.check_game_req(g).await;
-                                }
-                            } else if t == "live_game_hand" {
-                                if let Ok(m) =
-                                    serde_json::from_str::<GameGet>(&text)
-                                {
-                                    handler.get_hand(&m);
-                                }
-                            } else if t == "live_game_confirmed" {
-                                if let Ok(m) =
-                                    serde_json::from_str::<GameGet>(&text)
-                                {
-                                    handler.get_confirmed(&m);
-                                }
-                            } else if t == "live_game_start" {
-                                if let Ok(g) =
-                                    serde_json::from_str::<GameGet>(&text)
-                                {
-                                    handler.get_game(&g, &user.username).await;
-                                }
-                            } else if t == "live_game_buy"
-                                || t == "live_game_confirm"
-                            {
-                                if let Ok(g) =
-                                    serde_json::from_str::<GameGet>(&text)
-                                {
-                                    handler.shop_move(g);
-                                }
-                            } else if t == "live_game_place" {
-                                if let Ok(g) =
-                                    serde_json::from_str::<GameGet>(&text)
-                                {
-                                    handler.place_move(g).await;
-                                }
-                            } else if t == "live_game_play" {
-                                if let Ok(g) =
-                                    serde_json::from_str::<GameGet>(&text)
-                                {
-                                    handler.fight_move(g).await;
+    let mut socket_recv_task = tokio::spawn(
+        async move {
+            let msg_sender = MsgSender::new(&user, &tx);
+            let handler =
+                MessageHandler::new(&user, &ws, &tx, &db, &db_tx, msg_sender);
+            let first_connection = !ws.players.is_online(&user.username);
+            handler.connecting(true);
+            handler.analysis_connect();
+            handler.load_player().await;
+            if first_connection {
+                handler.notify_online().await;
+            }
+            handler.get_unread().await;
+            handler.get_notifications_unread().await;
+            let mut strikes = Strikes::default();
+            while let Some(Ok(msg)) = receiver.next().await {
+                match msg {
+                    Message::Text(text) => {
+                        if let Ok(mut value) =
+                            serde_json::from_str::<Value>(&text)
+                        {
+                            let data_type = value["t"].clone();
+                            if let serde_json::Value::String(t) = &data_type {
+                                METRICS.message_in(t);
+                                debug!(t = %t, "message");
+                                let limits = &ws.rate_limits;
+                                if limits.is_closed(&strikes) {
+                                    continue;
                                 }
-                            } else if t == "live_game_draw" {
-                                if let Ok(g) =
-                                    serde_json::from_str::<GameGet>(&text)
-                                {
-                                    handler.draw_req(&g, &user.username).await;
+                                if !limits.message(&user.session, ip, t) {
+                                    let now = ws.clock.now();
+                                    let closing =
+                                        limits.strike(&mut strikes, now);
+                                    let strikes = strikes.count();
+                                    warn!(t = %t, strikes, "rate limited");
+                                    let msg = rate_limited(t, closing);
+                                    let _ = local_tx.send((msg, closing));
+                                    continue;
                                 }
-                            } else if t == "live_game_resign" {
-                                if let Ok(g) =
-                                    serde_json::from_str::<GameGet>(&text)
+                                let text = if handler
+                                    .public_game_ids(&mut value)
+                                    .await
                                 {
-                                    handler.resign(&g, &user.username).await;
-                                }
-                            } else if t == "live_game_sfen" {
-                                if let Ok(json) =
-                                    serde_json::from_str::<GameGet>(&text)
+                                    value.to_string()
+                                } else {
+                                    text
+                                };
+                                let data = value["data"].clone();
+                                if t == "live_chat_message" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<ChatMsg>(data)
+                                    {
+                                        handler.new_chat_msg(m).await;
+                                    }
+                                } else if t == "live_chat_delete" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<ChatAction>(
+                                            data,
+                                        )
+                                    {
+                                        handler.delete_chat_msg(&m).await;
+                                    }
+                                } else if t == "live_chat_report" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<ChatAction>(
+                                            data,
+                                        )
+                                    {
+                                        handler.report_chat_msg(m).await;
+                                    }
+                                } else if t == "mod_mute" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<MuteRequest>(
+                                            data,
+                                        )
+                                    {
+                                        handler.mute_player(m).await;
+                                    }
+                                } else if t == "mod_warn" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<WarnRequest>(
+                                            data,
+                                        )
+                                    {
+                                        handler.warn_player(m).await;
+                                    }
+                                } else if t == "notifications_unread" {
+                                    handler.get_notifications_unread().await;
+                                } else if t == "mod_filtered" {
+                                    handler.get_filtered().await;
+                                } else if t == "mod_reports" {
+                                    handler.get_reports().await;
+                                } else if t == "mod_report_resolve" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<ReportResolve>(
+                                            data,
+                                        )
+                                    {
+                                        handler.resolve_report(m).await;
+                                    }
+                                } else if t == "inbox_send" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<DirectMsgReq>(
+                                            data,
+                                        )
+                                    {
+                                        handler.send_direct_msg(m).await;
+                                    }
+                                } else if t == "inbox_full" {
+                                    handler.get_inbox().await;
+                                } else if t == "inbox_conversation" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<InboxReq>(data)
+                                    {
+                                        handler.get_conversation(&m).await;
+                                    }
+                                } else if t == "inbox_block" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<InboxReq>(data)
+                                    {
+                                        handler.block_player(&m).await;
+                                    }
+                                } else if t == "follow" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<FollowReq>(
+                                            data,
+                                        )
+                                    {
+                                        handler.follow_player(&m).await;
+                                    }
+                                } else if t == "friends_full" {
+                                    handler.get_friends().await;
+                                } else if t == "live_chat_full" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<GameGet>(data)
+                                    {
+                                        handler.get_chat(m.game_id);
+                                    }
+                                } else if t == "active_players_full" {
+                                    handler.get_players();
+                                } else if t == "active_players_count" {
+                                    handler.get_players_count();
+                                } else if t == "live_game_remove_spectator" {
+                                    if let Ok(m) =
+                                        serde_json::from_str::<GameGet>(&text)
+                                    {
+                                        handler.remove_spectator(&m.game_id);
+                                    }
+                                } else if t == "home_lobby_add" {
+                                    if let Ok(g) =
+                                        serde_json::from_value::<GameRequest>(
+                                            data,
+                                        )
+                                    {
+                                        handler.add_game_req(g).await;
+                                    }
+                                } else if t == "puzzle" {
+                                    handler.get_puzzle().await;
+                                } else if t == "puzzle_move" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<PuzzleMove>(
+                                            data,
+                                        )
+                                    {
+                                        handler.puzzle_move(&m).await;
+                                    }
+                                } else if t == "analysis_new" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<AnalysisReq>(
+                                            data,
+                                        )
+                                    {
+                                        handler.analysis_new(&m);
+                                    }
+                                } else if t == "analysis_set" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<AnalysisReq>(
+                                            data,
+                                        )
+                                    {
+                                        handler.analysis_set(&m);
+                                    }
+                                } else if t == "analysis_move" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<AnalysisReq>(
+                                            data,
+                                        )
+                                    {
+                                        handler.analysis_move(&m);
+                                    }
+                                } else if t == "analysis_deploy" {
+                                    handler.analysis_deploy();
+                                } else if t == "analysis_undo" {
+                                    handler.analysis_undo();
+                                } else if t == "analysis_redo" {
+                                    handler.analysis_redo();
+                                } else if t == "analysis_close" {
+                                    handler.analysis_close();
+                                } else if t == "legal_hints" {
+                                    if let Ok(m) =
+                                        serde_json::from_value::<HintsReq>(data)
+                                    {
+                                        handler.get_hints(&m);
+                                    }
+                                } else if t == "leaderboard_full" {
+                                    handler.get_leaderboards();
+                                } else if t == "home_lobby_full" {
+                                    handler.get_all_game_reqs();
+                                } else if t == "home_lobby_accept" {
+                                    if let Ok(g) =
+                                        serde_json::from_value::<GameRequest>(
+                                            data,
+                                        )
+                                    {
+                                        handler.check_game_req(g).await;
+                                    }
+                                } else if t == "live_game_hand" {
+                                    if let Ok(m) =
+                                        serde_json::from_str::<GameGet>(&text)
+                                    {
+                                        handler.get_hand(&m);
+                                    }
+                                } else if t == "live_game_confirmed" {
+                                    if let Ok(m) =
+                                        serde_json::from_str::<GameGet>(&text)
+                                    {
+                                        handler.get_confirmed(&m);
+                                    }
+                                } else if t == "live_game_start" {
+                                    if let Ok(g) =
+                                        serde_json::from_str::<GameGet>(&text)
+                                    {
+                                        handler
+                                            .get_game(&g, &user.username)
+                                            .await;
+                                    }
+                                } else if t == "live_game_buy"
+                                    || t == "live_game_confirm"
                                 {
-                                    handler.get_sfen(&json);
+                                    if let Ok(g) =
+                                        serde_json::from_str::<GameGet>(&text)
+                                    {
+                                        handler.shop_move(g);
+                                    }
+                                } else if t == "live_game_place" {
+                                    if let Ok(g) =
+                                        serde_json::from_str::<GameGet>(&text)
+                                    {
+                                        handler.place_move(g).await;
+                                    }
+                                } else if t == "live_game_play" {
+                                    if let Ok(g) =
+                                        serde_json::from_str::<GameGet>(&text)
+                                    {
+                                        handler.fight_move(g).await;
+                                    }
+                                } else if t == "live_game_draw" {
+                                    if let Ok(g) =
+                                        serde_json::from_str::<GameGet>(&text)
+                                    {
+                                        handler
+                                            .draw_req(&g, &user.username)
+                                            .await;
+                                    }
+                                } else if t == "live_game_resign" {
+                                    if let Ok(g) =
+                                        serde_json::from_str::<GameGet>(&text)
+                                    {
+                                        handler
+                                            .resign(&g, &user.username)
+                                            .await;
+                                    }
+                                } else if t == "live_game_sfen" {
+                                    if let Ok(json) =
+                                        serde_json::from_str::<GameGet>(&text)
+                                    {
+                                        handler.get_sfen(&json);
+                                    }
+                                } else if t == "live_tv" {
+                                    handler.get_tv();
+                                } else if t == "save_all" {
+                                    handler.save_all().await;
+                                } else {
                                 }
-                            } else if t == "live_tv" {
-                                handler.get_tv();
-                            } else if t == "save_all" {
-                                handler.save_all().await;
-                            } else {
                             }
                         }
                     }
+                    Message::Close(_c) => {
+                        handler.connecting(false);
+                        break;
+                    }
+                    _ => handler.connecting(false),
                 }
-                Message::Close(_c) => {
-                    handler.connecting(false);
-                    break;
-                }
-                _ => handler.connecting(false),
             }
+            handler.connecting(false);
+            handler.analysis_disconnect();
         }
-        handler.connecting(false);
-    });
+        .instrument(span.clone()),
+    );
 
-    let db_send_task = tokio::spawn(async move {
-        while let Ok(msg) = db_rx.recv().await {
-            match msg {
-                MsgDatabase::GetGame(id) => {
-                    if let Some(game) = get_game_db(&db2.mongo.games, &id).await
-                    {
-                        let msg = live_game_start(&game);
-                        let _ = tx2.send(ClientMessage::new(
-                            &user2,
-                            msg,
-                            SendTo::Me,
-                        ));
+    let db_send_task = tokio::spawn(
+        async move {
+            while let Ok(msg) = db_rx.recv().await {
+                match msg {
+                    MsgDatabase::GetGame(id) => {
+                        if let Some(game) = db2.games.get_game(&id).await {
+                            let msg = live_game_start(&game, &user2.username);
+                            let msg =
+                                ClientMessage::new(&user2, msg, SendTo::Me);
+                            if tx2.send(msg).is_err() {
+                                METRICS.broadcast_dropped();
+                            }
+                        }
+                    }
+                    MsgDatabase::InsertGameMove(json) => {
+                        db2.games.insert_move(&json).await;
                     }
                 }
-                MsgDatabase::InsertGameMove(json) => {
-                    insert_move(&db2.mongo.games, &json).await;
-                }
-                _ => (),
             }
         }
-    });
+        .instrument(span.clone()),
+    );
 
     tokio::select! {
         _ = (&mut socket_send_task) => {
@@ -263,4 +492,6 @@ async fn websocket(
             db_send_task.abort();
         }
     }
+    METRICS.socket_closed();
+    span.in_scope(|| debug!("disconnected"));
 }
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bson::DateTime;
+use serde::Serialize;
+use tokio::sync::broadcast::Sender;
+
+use crate::{
+    arc2,
+    database::{mongo::Player, ratings::Rating, Database},
+    metrics::METRICS,
+};
+
+use super::{
+    server_messages::leaderboard_update, ClientMessage, SendTo, WsState,
+    VARIANTS,
+};
+
+pub const SPEEDS: [&str; 4] = ["bullet", "blitz", "rapid", "classical"];
+pub const LEADERBOARD_SIZE: usize = 100;
+/// Number of players shown on home page.
+pub const HOME_SIZE: usize = 10;
+/// Players that haven't played for this many days are removed.
+const ACTIVE_DAYS: i64 = 30;
+/// How often leaderboards are reloaded from database.
+const REFRESH: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Serialize)]
+pub struct LeaderboardEntry {
+    pub username: String,
+    pub rating: f64,
+    pub games: u32,
+    #[serde(skip)]
+    pub last_played: DateTime,
+}
+
+/// Top players for every variant and speed.
+#[derive(Clone)]
+pub struct Leaderboards {
+    all: Arc<Mutex<HashMap<String, Vec<LeaderboardEntry>>>>,
+    tx: Sender<ClientMessage>,
+}
+
+impl Leaderboards {
+    pub fn new(tx: Sender<ClientMessage>) -> Self {
+        Self {
+            all: arc2(HashMap::new()),
+            tx,
+        }
+    }
+
+    /// All rating keys.
+    pub fn keys() -> Vec<String> {
+        let mut keys = vec![];
+        for variant in VARIANTS {
+            for speed in SPEEDS {
+                keys.push(format!("{variant}_{speed}"));
+            }
+        }
+        keys
+    }
+
+    /// Oldest time when player is still active.
+    pub fn active_since() -> DateTime {
+        let days = ACTIVE_DAYS * 24 * 60 * 60 * 1000;
+        DateTime::from_millis(DateTime::now().timestamp_millis() - days)
+    }
+
+    /// Set leaderboard loaded from database.
+    pub fn load(&self, key: &str, players: Vec<Player>) {
+        let entries = players
+            .iter()
+            .filter_map(|p| {
+                let rating = p.ratings.get(key)?;
+                entry(&p._id, rating)
+            })
+            .collect();
+        self.all.lock().unwrap().insert(String::from(key), entries);
+    }
+
+    /// Get leaderboard without inactive players.
+    pub fn get(&self, key: &str, size: usize) -> Option<Vec<LeaderboardEntry>> {
+        let mut all = self.all.lock().unwrap();
+        let entries = all.get_mut(key)?;
+        let since = Self::active_since();
+        entries.retain(|e| e.last_played >= since);
+        Some(entries.iter().take(size).cloned().collect())
+    }
+
+    /// Top players for home page.
+    pub fn home(&self) -> HashMap<String, Vec<LeaderboardEntry>> {
+        Self::keys()
+            .into_iter()
+            .filter_map(|key| {
+                let entries = self.get(&key, HOME_SIZE)?;
+                Some((key, entries))
+            })
+            .collect()
+    }
+
+    /// Update leaderboard after rated game and send it if it changed.
+    pub fn update(
+        &self,
+        key: &str,
+        players: &[String; 2],
+        ratings: [Rating; 2],
+    ) {
+        let changed = {
+            let mut all = self.all.lock().unwrap();
+            let entries = all.entry(String::from(key)).or_default();
+            let before: Vec<String> =
+                entries.iter().map(|e| String::from(&e.username)).collect();
+            let mut changed = false;
+            for (username, rating) in players.iter().zip(ratings.iter()) {
+                if let Some(index) =
+                    entries.iter().position(|e| &e.username == username)
+                {
+                    entries.remove(index);
+                    changed = true;
+                }
+                if let Some(entry) = entry(username, rating) {
+                    entries.push(entry);
+                }
+            }
+            entries.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+            entries.truncate(LEADERBOARD_SIZE);
+            let after: Vec<String> =
+                entries.iter().map(|e| String::from(&e.username)).collect();
+            changed || before != after
+        };
+        if changed {
+            if let Some(entries) = self.get(key, HOME_SIZE) {
+                let msg = leaderboard_update(key, &entries);
+                let msg = ClientMessage::system(msg, SendTo::All);
+                if self.tx.send(msg).is_err() {
+                    METRICS.broadcast_dropped();
+                }
+            }
+        }
+    }
+}
+
+/// Reload leaderboards, so players below top move up after others
+/// become inactive or drop out.
+pub async fn run_leaderboards(ws: Arc<WsState>, db: Arc<Database>) {
+    let mut interval = tokio::time::interval(REFRESH);
+    // first tick is immediate, leaderboards are loaded on start
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        ws.load_leaderboards(&db).await;
+    }
+}
+
+/// Create entry if rating can be on leaderboard.
+fn entry(username: &str, rating: &Rating) -> Option<LeaderboardEntry> {
+    let last_played = rating.last_played?;
+    if rating.is_provisional() || last_played < Leaderboards::active_since() {
+        return None;
+    }
+    Some(LeaderboardEntry {
+        username: String::from(username),
+        rating: rating.rating.round(),
+        games: rating.games,
+        last_played,
+    })
+}
@@ -1,13 +1,13 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     hash::Hash,
     marker::PhantomData,
     ops::{BitAnd, BitOr, BitOrAssign, Not},
-    sync::{Arc, Mutex, MutexGuard},
+    sync::{Arc, Mutex},
 };
 
 use bson::DateTime as DT;
-use mongodb::Collection;
+use chrono::{DateTime, Utc};
 use serde_json::Value;
 use shuuro::{
     attacks::Attacks,
@@ -15,18 +15,22 @@ use shuuro::{
     position::{Outcome, Position},
     Color, Move, Piece, PieceType, Shop, Square, Variant,
 };
+use tracing::{info, info_span, Instrument};
 
 use crate::{
     arc2,
     database::{
-        mongo::ShuuroGame, queries::update_entire_game, redis::UserSession,
+        mongo::ShuuroGame, queries::delete_profile_stats, redis::UserSession,
+        repos::GameRepo, Database,
     },
+    metrics::{timed, METRICS},
 };
 
 use super::{
+    leaderboards::Leaderboards,
     server_messages::{live_game_end, live_game_lot, set_deploy},
     time_control::TimeCheck,
-    GameGet, LiveGameMove, MessageHandler, MsgDatabase, TvGame,
+    GameGet, LiveGameMove, MessageHandler, MsgDatabase, TvGame, SHOP_CREDIT,
 };
 
 #[derive(Debug, Clone)]
@@ -69,20 +73,32 @@ where
     fn new(mut game: ShuuroGame, unfinished: bool) -> Self {
         let mut placement: P = P::new();
         let mut fight: P = P::new();
-        if let Some(sub_variant) = game.sub_variant {
-            let stage = sub_variant.starting_stage();
+        let variant = Variant::from(&game.variant);
+        placement.update_variant(variant);
+        fight.update_variant(variant);
+        let start = {
+            if let Some(sfen) = &game.start_sfen {
+                Some((game.current_stage, String::from(sfen), false))
+            } else {
+                game.sub_variant.map(|sub_variant| {
+                    let sfen = sub_variant.starting_position();
+                    (sub_variant.starting_stage(), String::from(sfen), true)
+                })
+            }
+        };
+        if let Some((stage, start_sfen, plinths)) = start {
             let sfen = {
                 if unfinished {
                     &game.sfen
                 } else {
-                    sub_variant.starting_position()
+                    &start_sfen
                 }
             };
             game.current_stage = stage;
             if stage == 2 {
                 fight.set_sfen(sfen).expect("something gone wrong");
                 {
-                    if !unfinished {
+                    if !unfinished && plinths {
                         fight.generate_plinths();
                     }
                 }
@@ -92,11 +108,15 @@ where
             } else if stage == 1 {
                 placement.set_sfen(sfen).expect("something gone wrong");
                 {
-                    if !unfinished {
+                    if !unfinished && plinths {
                         placement.generate_plinths();
                     }
                 }
                 game.sfen = placement.generate_sfen();
+                game.hands = [
+                    placement.get_hand(Color::White, false),
+                    placement.get_hand(Color::Black, false),
+                ];
                 game.history.1.push(String::from(&game.sfen));
             }
         }
@@ -164,19 +184,62 @@ where
         m: Move<S>,
     ) -> Option<LiveGameMove> {
         let player_color = Color::from(player);
-        if player_color == piece.color {
-            if let Some(confirmed) = self.shop.play(m) {
-                self.game.draws = [false, false];
-                self.game.hands[player] =
-                    self.shop.to_sfen(player_color, false);
-                if confirmed[player_color as usize] {
-                    return Some(LiveGameMove::BuyMove(confirmed));
-                }
-            }
+        if player_color != piece.color {
+            return None;
+        }
+        let mut shop = self.shop.clone();
+        shop.play(m)?;
+        if self.credit(&shop, player_color) < 0 {
+            return None;
+        }
+        self.shop = shop;
+        if !self.can_buy_pawn(player_color) {
+            self.shop.confirm(player_color);
+        }
+        self.game.draws = [false, false];
+        self.game.history.0.push(format!("+{piece}"));
+        self.game.hands[player] = self.shop.to_sfen(player_color, false);
+        let confirmed = self.confirmed();
+        if confirmed[player] {
+            return Some(LiveGameMove::BuyMove(confirmed));
         }
         None
     }
 
+    /// Credit left for color. Shop always starts from `SHOP_CREDIT`,
+    /// difference is taken or added for handicap games.
+    fn credit(&self, shop: &Shop<S>, color: Color) -> i32 {
+        let credits = i32::from(self.game.credits[color.index()]);
+        shop.credit(color) + credits - i32::from(SHOP_CREDIT)
+    }
+
+    /// Shop credit left for white and black.
+    pub fn credits_left(&self) -> [i32; 2] {
+        [Color::White, Color::Black].map(|c| self.credit(&self.shop, c))
+    }
+
+    /// Keep credit left after shop is changed without buying.
+    pub fn set_credits_left(&mut self, left: [i32; 2]) {
+        for (i, color) in [Color::White, Color::Black].iter().enumerate() {
+            let spent = i32::from(SHOP_CREDIT) - self.shop.credit(*color);
+            let credits = (left[i] + spent).clamp(0, i32::from(u16::MAX));
+            self.game.credits[i] = credits as u16;
+        }
+    }
+
+    /// Pawn is cheapest piece, if it can't be bought shop is over.
+    fn can_buy_pawn(&self, color: Color) -> bool {
+        if self.shop.is_confirmed(color) {
+            return false;
+        }
+        let pawn = if color == Color::White { "+P" } else { "+p" };
+        let mut shop = self.shop.clone();
+        match Move::from_sfen(pawn) {
+            Some(m) => shop.play(m).is_some() && self.credit(&shop, color) >= 0,
+            None => true,
+        }
+    }
+
     /// DEPLOY PART
 
     pub fn set_deploy(&mut self, id: &str) -> Value {
@@ -236,7 +299,7 @@ where
             }
             if let Some(clocks) = self.game.tc.click(index) {
                 self.game.clocks = self.game.tc.clocks;
-                self.game.last_clock = DT::now();
+                self.game.last_clock = self.now();
                 return self.place_piece(json, index, clocks);
             } else {
             }
@@ -295,7 +358,7 @@ where
     pub fn set_fight(&mut self) -> bool {
         self.game.current_stage = 2;
         self.game.tc.update_stage(2);
-        self.game.last_clock = DT::now();
+        self.game.last_clock = self.now();
         let sfen = self.placement.generate_sfen();
         let outcome = self.fight.set_sfen(sfen.as_str());
         if let Ok(_o) = outcome {
@@ -320,7 +383,7 @@ where
             if let Some(clocks) = self.game.tc.click(index) {
                 self.game.draws = [false, false];
                 self.game.clocks = self.game.tc.clocks;
-                self.game.last_clock = DT::now();
+                self.game.last_clock = self.now();
                 return self.make_move(json, index, clocks);
             }
         }
@@ -374,6 +437,11 @@ where
         None
     }
 
+    /// Current time from game clock.
+    fn now(&self) -> DT {
+        DT::from_millis(self.game.tc.clock.now().timestamp_millis())
+    }
+
     pub fn player_index(&self, p: &[String; 2], u: &String) -> Option<usize> {
         p.iter().position(|x| x == u)
     }
@@ -400,10 +468,10 @@ where
 
     /// CLOCK PART
 
-    /// After every 500ms, this function returns who lost on time.
+    /// After every clock tick, this function returns who lost on time.
     pub fn clock_status(
         &mut self,
-        time_check: MutexGuard<TimeCheck>,
+        time_check: &TimeCheck,
     ) -> Option<(Value, Value, [String; 2])> {
         if time_check.both_lost {
             self.game.status = 5;
@@ -423,12 +491,11 @@ where
             live_game_lot(&self.game._id, self.game.status, &self.game.result);
         let tv_res = live_game_end(&self.game._id);
         let tv_res = serde_json::json!({"t": "tv_game_update", "data": tv_res});
-        drop(time_check);
         Some((res, tv_res, self.game.players.clone()))
     }
 
     /// Check clocks for current stage.
-    pub fn check_clocks(&self, mut time_check: MutexGuard<TimeCheck>) {
+    pub fn check_clocks(&self, time_check: &mut TimeCheck) {
         if self.game.current_stage == 0 {
             let durations = [
                 self.game.tc.current_duration(0),
@@ -458,7 +525,22 @@ where
                 time_check.lost(stm as usize);
             }
         }
-        drop(time_check);
+    }
+
+    /// Next time when one of players can lose on time.
+    pub fn deadline(&self) -> Option<DateTime<Utc>> {
+        if self.game.status > 0 {
+            return None;
+        }
+        let tc = &self.game.tc;
+        let deadline = {
+            if self.game.current_stage == 0 {
+                tc.deadline(0).min(tc.deadline(1))
+            } else {
+                tc.deadline(self.game.side_to_move as usize)
+            }
+        };
+        Some(deadline.with_timezone(&Utc))
     }
 
     /// After match is finished, update status.
@@ -507,11 +589,175 @@ where
             self.game.status = 7;
             self.game.result = Color::from(index).to_string();
             self.game.tc.click(index);
-            self.game.last_clock = DT::now();
+            self.game.last_clock = self.now();
             return Some(self.game.players.clone());
         }
         None
     }
+
+    // ANALYSIS PART
+
+    /// Game without clocks where one player moves for both sides.
+    pub fn analysis(game: ShuuroGame) -> Self {
+        let variant = String::from(&game.variant);
+        let mut live_game = Self::new(game, false);
+        live_game.change_variant(&variant);
+        live_game
+    }
+
+    /// Current stage and position. In shop position is hand for both players.
+    pub fn position(&self) -> (u8, String) {
+        if self.game.current_stage == 0 {
+            let w = self.shop.to_sfen(Color::White, false);
+            let b = self.shop.to_sfen(Color::Black, false);
+            (0, format!("{w}{b}"))
+        } else {
+            (self.game.current_stage, String::from(&self.game.sfen))
+        }
+    }
+
+    /// Set any position for stage.
+    pub fn set_position(&mut self, stage: u8, sfen: &str) -> bool {
+        match stage {
+            0 => {
+                self.shop = Shop::<S>::default();
+                self.shop.update_variant(Variant::from(&self.game.variant));
+                self.shop.set_hand(sfen);
+                self.game.hands = [
+                    self.shop.to_sfen(Color::White, false),
+                    self.shop.to_sfen(Color::Black, false),
+                ];
+                self.game.sfen = String::from("");
+                self.game.side_to_move = 0;
+            }
+            1 => {
+                if self.placement.set_sfen(sfen).is_err() {
+                    return false;
+                }
+                self.game.sfen = self.placement.generate_sfen();
+                self.game.hands = self.get_hands();
+                self.game.side_to_move =
+                    self.placement.side_to_move().index() as u8;
+            }
+            2 => {
+                if self.fight.set_sfen(sfen).is_err() {
+                    return false;
+                }
+                self.game.sfen = self.fight.generate_sfen();
+                self.game.side_to_move =
+                    self.fight.side_to_move().index() as u8;
+            }
+            _ => return false,
+        }
+        self.game.current_stage = stage;
+        self.game.status = -1;
+        if stage == 2 {
+            self.update_status();
+        }
+        true
+    }
+
+    /// Play shop, deploy or fight move for side that is allowed to move.
+    pub fn analysis_move(&mut self, game_move: &str) -> bool {
+        if self.game.status > 0 {
+            return false;
+        }
+        let json = GameGet {
+            t: String::from(""),
+            game_id: String::from(&self.game._id),
+            game_move: String::from(game_move),
+            variant: String::from(&self.game.variant),
+        };
+        match self.game.current_stage {
+            0 => {
+                if let Some(m) = Move::from_sfen(game_move) {
+                    if let Move::Buy { piece } = m {
+                        let before = self.game.history.0.len();
+                        self.new_piece(piece, piece.color.index(), m);
+                        return self.game.history.0.len() > before;
+                    }
+                }
+                false
+            }
+            1 => {
+                let index = self.placement.side_to_move().index();
+                if self.place_piece(&json, index, [0, 0]).is_none() {
+                    return false;
+                }
+                if self.game.current_stage == 1 {
+                    self.game.side_to_move =
+                        self.placement.side_to_move().index() as u8;
+                } else {
+                    self.game.sfen = self.fight.generate_sfen();
+                    self.game.side_to_move =
+                        self.fight.side_to_move().index() as u8;
+                }
+                true
+            }
+            2 => {
+                let index = self.fight.side_to_move().index();
+                if self.make_move(&json, index, [0, 0]).is_none() {
+                    return false;
+                }
+                self.game.side_to_move =
+                    self.fight.side_to_move().index() as u8;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Finish shopping and start deployment.
+    pub fn analysis_deploy(&mut self) -> bool {
+        if self.game.current_stage != 0 {
+            return false;
+        }
+        let id = String::from(&self.game._id);
+        self.set_deploy(&id);
+        self.game.side_to_move = self.placement.side_to_move().index() as u8;
+        true
+    }
+
+    /// Outcome of fight position.
+    pub fn outcome(&self) -> Option<String> {
+        if self.game.current_stage == 2 {
+            return Some(self.fight.outcome().to_string());
+        }
+        None
+    }
+
+    /// Legal moves for side to move. Key is square in fight and piece
+    /// from hand in deployment, value is list of destinations.
+    pub fn legal_moves(&self) -> HashMap<String, Vec<String>>
+    where
+        B: Iterator<Item = S>,
+    {
+        let mut moves = HashMap::new();
+        if self.game.status > 0 {
+            return moves;
+        }
+        if self.game.current_stage == 1 {
+            let color = self.placement.side_to_move();
+            let hand = self.placement.get_hand(color, false);
+            for piece in hand.chars().filter_map(Piece::from_sfen) {
+                let key = piece.to_string();
+                if piece.color != color || moves.contains_key(&key) {
+                    continue;
+                }
+                let squares = self.placement.empty_squares(piece);
+                moves.insert(key, squares.map(|s| s.to_string()).collect());
+            }
+        } else if self.game.current_stage == 2 {
+            let color = self.fight.side_to_move();
+            for (from, to) in self.fight.legal_moves(&color) {
+                let to: Vec<String> = to.map(|s| s.to_string()).collect();
+                if !to.is_empty() {
+                    moves.insert(from.to_string(), to);
+                }
+            }
+        }
+        moves
+    }
 }
 
 pub type AllGames<S, B, A, P> =
@@ -532,7 +778,6 @@ where
     for<'a> &'a B: BitAnd<&'a S, Output = B>,
 {
     pub all: AllGames<S, B, A, P>,
-    unfinished: Arc<Mutex<Vec<String>>>,
 }
 
 impl<S, B, A, P> LiveGames<S, B, A, P>
@@ -559,14 +804,37 @@ where
     }
 
     /// Remove game after end.
-    pub async fn remove_game(&self, db: &Collection<ShuuroGame>, id: &String) {
+    pub async fn remove_game(
+        &self,
+        db: &Arc<Database>,
+        leaderboards: &Leaderboards,
+        id: &String,
+    ) {
         let mut all = self.all.lock().unwrap();
         if let Some(game) = all.remove(id) {
             let db = db.clone();
+            let leaderboards = leaderboards.clone();
             let game = game.get_game();
-            tokio::spawn(async move {
-                update_entire_game(&db, &game).await;
-            });
+            METRICS.game_result(game.status);
+            info!(game_id = %id, status = game.status, "game finished");
+            let span = info_span!("game", game_id = %id);
+            tokio::spawn(
+                async move {
+                    db.games.update_game(&game).await;
+                    if let Some((key, ratings)) =
+                        db.players.update_ratings(&game).await
+                    {
+                        leaderboards.update(&key, &game.players, ratings);
+                    }
+                    if let Some(mongo) = &db.mongo {
+                        let players = &game.players;
+                        let stats =
+                            delete_profile_stats(&mongo.profiles, players);
+                        timed("mongo", "delete_profile_stats", stats).await;
+                    }
+                }
+                .instrument(span),
+            );
         }
     }
 
@@ -575,16 +843,24 @@ where
         self.all.lock().unwrap().len()
     }
 
+    /// Add number of games for each variant and stage.
+    pub fn stage_counts(&self, counts: &mut BTreeMap<(String, u8), usize>) {
+        let all = self.all.lock().unwrap();
+        for game in all.values() {
+            let key =
+                (String::from(&game.game.variant), game.game.current_stage);
+            *counts.entry(key).or_default() += 1;
+        }
+    }
+
     /// Load games from db
     pub fn load_unfinished(&self, hm: &HashMap<String, ShuuroGame>) {
         let mut temp = HashMap::new();
-        let mut v = vec![];
         for i in hm {
             //self.ws.players.new_spectators(&i.0);
             let mut game: LiveGame<S, B, A, P> =
                 LiveGame::new(i.1.clone(), true);
             let id = String::from(i.0);
-            v.push(id.clone());
             game.game.tc.update_stage(i.1.current_stage);
             if i.1.current_stage == 0 {
                 let hands = format!("{}{}", &i.1.hands[0], &i.1.hands[1]);
@@ -601,20 +877,6 @@ where
             }
         }
         *self.all.lock().unwrap() = temp;
-        *self.unfinished.lock().unwrap() = v;
-    }
-
-    pub fn get_unfinished(&self) -> Vec<String> {
-        let unfinished = self.unfinished.lock().unwrap();
-        let games = unfinished.clone();
-        drop(unfinished);
-        games
-    }
-
-    pub fn del_unfinished(&self) {
-        let mut unfinished = self.unfinished.lock().unwrap();
-        *unfinished = vec![];
-        drop(unfinished);
     }
 
     pub fn change_variant(&self, id: &String, variant: &String) {
@@ -710,33 +972,31 @@ where
 
     pub fn clock_status(
         &self,
-        time_check: &Arc<Mutex<TimeCheck>>,
+        time_check: &TimeCheck,
     ) -> Option<(Value, Value, [String; 2])> {
-        let time_check = time_check.lock().unwrap();
-        if let Some(g) = self
-            .all
-            .lock()
-            .unwrap()
-            .get_mut(&String::from(&time_check.id))
-        {
+        if let Some(g) = self.all.lock().unwrap().get_mut(&time_check.id) {
             return g.clock_status(time_check);
         }
-        drop(time_check);
         None
     }
 
-    pub fn check_clocks(&self, time_check: &Arc<Mutex<TimeCheck>>) {
-        let time_check = time_check.lock().unwrap();
-        let id = String::from(&time_check.id);
-        if let Some(game) = self.all.lock().unwrap().get(&id) {
-            game.check_clocks(time_check);
-        }
+    /// Check clocks if game exist.
+    pub fn check_clocks(&self, id: &String) -> Option<TimeCheck> {
+        let all = self.all.lock().unwrap();
+        let game = all.get(id)?;
+        let mut time_check = TimeCheck::new(id);
+        game.check_clocks(&mut time_check);
+        Some(time_check)
+    }
+
+    pub fn deadline(&self, id: &String) -> Option<DateTime<Utc>> {
+        self.all.lock().unwrap().get(id)?.deadline()
     }
 
     pub async fn get_game<'a>(
         &self,
         id: &String,
-        _db: &Collection<ShuuroGame>,
+        _db: &dyn GameRepo,
         s: &'a MessageHandler<'a>,
         db: bool,
     ) -> Option<ShuuroGame> {
@@ -751,6 +1011,25 @@ where
         None
     }
 
+    /// Public ID of live game with this private player ID.
+    pub fn player_game_id(&self, id: &str) -> Option<String> {
+        let all = self.all.lock().unwrap();
+        all.values()
+            .find(|g| g.game.player_ids.iter().any(|p| p == id))
+            .map(|g| String::from(&g.game._id))
+    }
+
+    /// Find live game for this player. Returns game ID and stage.
+    pub fn player_game(&self, username: &String) -> Option<(String, u8)> {
+        let all = self.all.lock().unwrap();
+        for g in all.values() {
+            if g.game.players.contains(username) {
+                return Some((String::from(&g.game._id), g.game.current_stage));
+            }
+        }
+        None
+    }
+
     pub fn live_sfen(&self, id: &String) -> Option<(u8, String)> {
         if let Some(g) = self.all.lock().unwrap().get(id) {
             return Some(g.live_sfen());
@@ -758,6 +1037,20 @@ where
         None
     }
 
+    /// Legal moves for live game, spectators can see them too.
+    pub fn legal_moves(
+        &self,
+        id: &String,
+    ) -> Option<HashMap<String, Vec<String>>>
+    where
+        B: Iterator<Item = S>,
+    {
+        if let Some(g) = self.all.lock().unwrap().get(id) {
+            return Some(g.legal_moves());
+        }
+        None
+    }
+
     pub fn resign(
         &self,
         id: &String,
@@ -794,10 +1087,10 @@ where
     }
 
     /// Before closing server save on exit.
-    pub async fn save_on_exit(&self, db: &Collection<ShuuroGame>) {
+    pub async fn save_on_exit(&self, db: &dyn GameRepo) {
         let all = self.all.lock().unwrap().clone();
         for (_, game) in all {
-            update_entire_game(db, &game.game).await;
+            db.update_game(&game.game).await;
         }
     }
 }
@@ -820,7 +1113,6 @@ where
         A::init();
         Self {
             all: arc2(HashMap::new()),
-            unfinished: arc2(vec![]),
         }
     }
 }
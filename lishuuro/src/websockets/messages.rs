@@ -4,28 +4,45 @@ use std::{
 };
 
 use serde_json::Value;
-use tokio::{sync::broadcast::Sender, task::JoinHandle};
+use tokio::sync::broadcast::Sender;
+use tracing::{error, info, instrument};
 
 use crate::{
     arc2,
     database::{
-        mongo::ShuuroGame,
-        queries::{add_game_to_db, game_exist},
+        mongo::{
+            ChatReport, Conversation, DirectMessage, FilteredMsg,
+            NotificationKind, ShuuroGame,
+        },
+        ratings::rating_key,
         redis::UserSession,
         Database,
     },
+    lichess::login::random_game_id,
+    metrics::METRICS,
+    notifications::notify,
+    puzzles::{check_move, next_puzzle, PuzzleMove},
 };
 
 use super::{
-    rooms::{ChatMsg, Players},
+    analysis::{square_hints, AnalysisReq, HintsReq},
+    chat_filter::FilterResult,
+    rooms::{ChatMsg, NewChatMsg, Players},
     server_messages::{
-        active_players_full, fmt_chat, fmt_count, home_lobby_full,
-        live_game_confirmed, live_game_draw, live_game_draw2, live_game_end,
-        live_game_hand, live_game_place, live_game_play, live_game_resign,
-        live_game_sfen, live_game_start, live_tv, pause_confirmed, set_deploy,
+        active_players_full, fmt_chat, fmt_count, follow, friend_online,
+        friend_playing, friends_full, home_lobby_error, home_lobby_full,
+        inbox_block, inbox_conversation, inbox_error, inbox_full,
+        inbox_message, inbox_unread, leaderboard_full, legal_hints,
+        live_chat_error, live_chat_muted, live_game_confirmed, live_game_draw,
+        live_game_draw2, live_game_end, live_game_hand, live_game_place,
+        live_game_play, live_game_resign, live_game_sfen, live_game_start,
+        live_tv, mod_filtered, mod_mute, mod_reports, notifications_unread,
+        pause_confirmed, set_deploy, unsupported,
     },
-    time_control::TimeCheck,
-    GameGet, GameRequest, LiveGameMove, MsgDatabase, WsState,
+    state::game_path,
+    ChatAction, DirectMsgReq, FollowReq, GameGet, GameRequest, InboxReq,
+    LiveGameMove, MsgDatabase, MuteRequest, ReportResolve, WarnRequest,
+    WsState, SHOP_CREDIT,
 };
 
 #[derive(Clone)]
@@ -45,12 +62,24 @@ impl ClientMessage {
             to,
         }
     }
+
+    /// Message that is not sent by any player.
+    pub fn system(msg: Value, to: SendTo) -> Self {
+        Self {
+            reg: true,
+            username: String::new(),
+            msg,
+            to,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub enum SendTo {
     Me,
     All,
+    User(String),
+    Users(Vec<String>),
     Spectators(HashSet<String>),
     Players([String; 2]),
     SpectatorsAndPlayers((HashSet<String>, [String; 2])),
@@ -87,23 +116,401 @@ impl<'a> MessageHandler<'a> {
         }
     }
 
-    pub fn new_chat_msg(&self, msg: ChatMsg) {
+    pub async fn new_chat_msg(&self, msg: ChatMsg) {
         let id = String::from(&msg.id);
         let json = GameGet::from(&msg);
-        if let Some(v) = self.ws.chat.add_msg(&id, msg, self.user) {
-            if let Some(s) = self.ws.players.get_spectators(&id) {
-                let to: SendTo;
-                if &id == "home" {
-                    to = SendTo::Spectators(s);
-                } else if let Some(players) =
-                    self.ws.shuuro_games.get_players(&json)
+        match self.ws.chat.add_msg(&id, msg, self.user) {
+            Some(NewChatMsg::Public(v, m)) => {
+                if let Some(to) = self.chat_audience(&json) {
+                    self.msg_sender.send_msg(v, to);
+                }
+                if &id != "home" {
+                    self.db.chat.add_line(&id, &m).await;
+                }
+            }
+            Some(NewChatMsg::Hidden(v, m, reason)) => {
+                self.msg_sender.send_msg(v, SendTo::Me);
+                let filtered = FilteredMsg {
+                    _id: random_game_id(),
+                    user: m.user,
+                    room: m.id,
+                    message: m.message,
+                    reason: String::from(reason),
+                    created_at: bson::DateTime::now(),
+                };
+                self.add_filtered(&filtered).await;
+            }
+            Some(NewChatMsg::RateLimited) => {
+                let msg = live_chat_error(&id, "rate_limited");
+                self.msg_sender.send_msg(msg, SendTo::Me);
+            }
+            None => {
+                if let Some(until) = self.ws.chat.is_muted(&self.user.username)
                 {
-                    to = SendTo::SpectatorsAndPlayers((s, players));
-                } else {
-                    to = SendTo::Spectators(s);
+                    self.msg_sender
+                        .send_msg(live_chat_muted(&until), SendTo::Me);
                 }
+            }
+        }
+    }
+
+    /// Everyone who can see chat for this room.
+    fn chat_audience(&self, json: &GameGet) -> Option<SendTo> {
+        let id = &json.game_id;
+        if let Some(s) = self.ws.players.get_spectators(id) {
+            if id == "home" {
+                return Some(SendTo::Spectators(s));
+            } else if let Some(players) = self.ws.shuuro_games.get_players(json)
+            {
+                return Some(SendTo::SpectatorsAndPlayers((s, players)));
+            }
+            return Some(SendTo::Spectators(s));
+        }
+        None
+    }
+
+    /// Moderator deletes message from room.
+    pub async fn delete_chat_msg(&self, action: &ChatAction) {
+        if !self.user.is_moderator() {
+            return;
+        }
+        if let Some(v) = self.ws.chat.delete_msg(&action.id, action.msg_id) {
+            if let Some(to) = self.chat_audience(&GameGet::from(action)) {
                 self.msg_sender.send_msg(v, to);
             }
+            self.db.chat.delete_line(&action.id, action.msg_id).await;
+        }
+    }
+
+    /// Player reports message. Messages around it are saved too.
+    pub async fn report_chat_msg(&self, action: ChatAction) {
+        if !self.user.reg {
+            return;
+        }
+        if let Some(context) = self.ws.chat.context(&action.id, action.msg_id) {
+            let reported = context
+                .iter()
+                .find(|m| m.msg_id == action.msg_id)
+                .map(|m| String::from(&m.user))
+                .unwrap_or_default();
+            let report = ChatReport {
+                _id: random_game_id(),
+                reporter: String::from(&self.user.username),
+                reported,
+                room: action.id,
+                msg_id: action.msg_id,
+                reason: action.reason.chars().take(200).collect(),
+                context,
+                created_at: bson::DateTime::now(),
+                resolved: false,
+            };
+            self.db.chat.add_report(&report).await;
+        }
+    }
+
+    /// Moderator mutes player in all rooms.
+    pub async fn mute_player(&self, req: MuteRequest) {
+        if !self.user.is_moderator() {
+            return;
+        }
+        let until = {
+            if req.minutes > 0 {
+                let ms = bson::DateTime::now().timestamp_millis()
+                    + req.minutes * 60 * 1000;
+                Some(bson::DateTime::from_millis(ms))
+            } else {
+                None
+            }
+        };
+        if self.db.players.set_mute(&req.username, until).await {
+            self.ws.chat.mute(&req.username, until);
+            let msg = mod_mute(&req.username, until);
+            self.msg_sender.send_msg(msg, SendTo::Me);
+        }
+    }
+
+    /// Load mute and account age for this player.
+    pub async fn load_player(&self) {
+        if !self.user.reg {
+            return;
+        }
+        let username = &self.user.username;
+        if let Some(player) = self.db.players.get_player(username).await {
+            self.ws.chat.add_account(username, player.created_at);
+            if let Some(until) = player.muted_until {
+                if until > bson::DateTime::now() {
+                    self.ws.chat.mute(username, Some(until));
+                }
+            }
+        }
+    }
+
+    /// Moderator gets last messages hidden by chat filter.
+    pub async fn get_filtered(&self) {
+        if !self.user.is_moderator() {
+            return;
+        }
+        let messages = self.db.chat.get_filtered().await;
+        self.msg_sender.send_msg(mod_filtered(messages), SendTo::Me);
+    }
+
+    /// Save message hidden by chat filter.
+    async fn add_filtered(&self, filtered: &FilteredMsg) {
+        self.db.chat.add_filtered(filtered).await;
+    }
+
+    // INBOX PART
+
+    /// Send private message to other registered player.
+    pub async fn send_direct_msg(&self, req: DirectMsgReq) {
+        let username = &self.user.username;
+        if &req.to == username {
+            return;
+        }
+        let check = match self.ws.chat.check_msg(self.user, &req.message) {
+            Some(FilterResult::RateLimited) => {
+                let msg = inbox_error(&req.to, "rate_limited");
+                self.msg_sender.send_msg(msg, SendTo::Me);
+                return;
+            }
+            Some(check) => check,
+            None => return,
+        };
+        let players = &self.db.players;
+        let recipient = match players.get_player(&req.to).await {
+            Some(recipient) if recipient.reg => recipient,
+            _ => {
+                let msg = inbox_error(&req.to, "not_found");
+                self.msg_sender.send_msg(msg, SendTo::Me);
+                return;
+            }
+        };
+        let blocked = {
+            if let Some(sender) = players.get_player(username).await {
+                sender.blocked.contains(&req.to)
+            } else {
+                false
+            }
+        };
+        if blocked || recipient.blocked.contains(username) {
+            let msg = inbox_error(&req.to, "blocked");
+            self.msg_sender.send_msg(msg, SendTo::Me);
+            return;
+        }
+        let m = DirectMessage {
+            _id: random_game_id(),
+            conversation: Conversation::id(username, &req.to),
+            user: String::from(username),
+            to: String::from(&req.to),
+            message: req.message,
+            time: bson::DateTime::now(),
+        };
+        let res = inbox_message(&m);
+        if let FilterResult::Hidden(reason) = check {
+            self.msg_sender.send_msg(res, SendTo::Me);
+            let filtered = FilteredMsg {
+                _id: m._id,
+                user: m.user,
+                room: format!("inbox:{}", &m.to),
+                message: m.message,
+                reason: String::from(reason),
+                created_at: m.time,
+            };
+            self.add_filtered(&filtered).await;
+            return;
+        }
+        if self.db.inbox.add_message(&m).await {
+            self.msg_sender.send_msg(res.clone(), SendTo::Me);
+            self.msg_sender.send_msg(res, SendTo::User(m.to));
+        }
+    }
+
+    /// Get all conversations for this player.
+    pub async fn get_inbox(&self) {
+        if !self.user.reg {
+            return;
+        }
+        let inbox = &self.db.inbox;
+        let conversations = inbox.conversations(&self.user.username).await;
+        self.msg_sender
+            .send_msg(inbox_full(conversations), SendTo::Me);
+    }
+
+    /// Get messages with other player and mark them as read.
+    pub async fn get_conversation(&self, req: &InboxReq) {
+        if !self.user.reg {
+            return;
+        }
+        let username = &self.user.username;
+        let id = Conversation::id(username, &req.username);
+        let messages = self.db.inbox.messages(&id, req.page).await;
+        self.db.inbox.read_conversation(&id, username).await;
+        let msg = inbox_conversation(&req.username, messages);
+        self.msg_sender.send_msg(msg, SendTo::Me);
+        self.get_unread().await;
+    }
+
+    /// Send number of unread messages.
+    pub async fn get_unread(&self) {
+        if !self.user.reg {
+            return;
+        }
+        let count = self.db.inbox.unread_count(&self.user.username).await;
+        self.msg_sender.send_msg(inbox_unread(count), SendTo::Me);
+    }
+
+    /// Block or unblock player.
+    pub async fn block_player(&self, req: &InboxReq) {
+        if !self.user.reg || req.username == self.user.username {
+            return;
+        }
+        let players = &self.db.players;
+        let username = &self.user.username;
+        if players
+            .set_blocked(username, &req.username, req.block)
+            .await
+        {
+            let msg = inbox_block(&req.username, req.block);
+            self.msg_sender.send_msg(msg, SendTo::Me);
+        }
+    }
+
+    // FRIENDS PART
+
+    /// Follow or unfollow other registered player.
+    pub async fn follow_player(&self, req: &FollowReq) {
+        let username = &self.user.username;
+        if !self.user.reg || &req.username == username {
+            return;
+        }
+        let players = &self.db.players;
+        if req.follow {
+            match players.get_player(&req.username).await {
+                Some(player) if player.reg => (),
+                _ => return,
+            }
+        }
+        if players
+            .set_following(username, &req.username, req.follow)
+            .await
+        {
+            let msg = follow(&req.username, req.follow);
+            self.msg_sender.send_msg(msg, SendTo::Me);
+        }
+    }
+
+    /// Get status for all followed players.
+    pub async fn get_friends(&self) {
+        if !self.user.reg {
+            return;
+        }
+        let db = &self.db.players;
+        if let Some(player) = db.get_player(&self.user.username).await {
+            let friends = self.ws.friends_status(&player.following);
+            self.msg_sender.send_msg(friends_full(friends), SendTo::Me);
+        }
+    }
+
+    /// Online followers of this player.
+    async fn online_followers(&self, username: &str) -> Vec<String> {
+        let followers = self.db.players.followers(username).await;
+        followers
+            .into_iter()
+            .filter(|f| self.ws.players.is_online(f))
+            .collect()
+    }
+
+    /// Tell followers that this player is online.
+    pub async fn notify_online(&self) {
+        if !self.user.reg {
+            return;
+        }
+        let followers = self.online_followers(&self.user.username).await;
+        if !followers.is_empty() {
+            let msg = friend_online(&self.user.username);
+            self.msg_sender.send_msg(msg, SendTo::Users(followers));
+        }
+    }
+
+    /// Tell followers of both players that game has started.
+    async fn notify_playing(&self, game: &ShuuroGame) {
+        let path = game_path(&game._id, game.current_stage);
+        for player in &game.players {
+            let followers = self.online_followers(player).await;
+            if !followers.is_empty() {
+                let msg = friend_playing(player, &game._id, &path);
+                self.msg_sender.send_msg(msg, SendTo::Users(followers));
+            }
+        }
+    }
+
+    // NOTIFICATIONS PART
+
+    /// Save notification and send it if player is connected.
+    pub async fn notify(
+        &self,
+        username: &str,
+        kind: NotificationKind,
+        data: Value,
+    ) {
+        notify(&self.db.notifications, self.tx, username, kind, data).await;
+    }
+
+    /// Send number of unread notifications.
+    pub async fn get_notifications_unread(&self) {
+        if !self.user.reg {
+            return;
+        }
+        let db = &self.db.notifications;
+        let count = db.unread_notifications(&self.user.username).await;
+        let msg = notifications_unread(count);
+        self.msg_sender.send_msg(msg, SendTo::Me);
+    }
+
+    /// Moderator sends warning to player.
+    pub async fn warn_player(&self, req: WarnRequest) {
+        if !self.user.is_moderator() || req.message.trim().is_empty() {
+            return;
+        }
+        let data = serde_json::json!({
+            "from": &self.user.username,
+            "message": req.message.trim()
+        });
+        self.notify(&req.username, NotificationKind::ModWarning, data)
+            .await;
+    }
+
+    /// Offline opponent gets one unread notification per game about moves.
+    async fn notify_move(&self, game_id: &String, players: &[String; 2]) {
+        for player in players {
+            if player != &self.user.username
+                && !self.ws.players.is_online(player)
+            {
+                let data = serde_json::json!({
+                    "game_id": game_id,
+                    "from": &self.user.username
+                });
+                self.notify(player, NotificationKind::MoveMade, data).await;
+            }
+        }
+    }
+
+    /// Moderator gets all unresolved reports.
+    pub async fn get_reports(&self) {
+        if !self.user.is_moderator() {
+            return;
+        }
+        let reports = self.db.chat.get_reports().await;
+        self.msg_sender.send_msg(mod_reports(reports), SendTo::Me);
+    }
+
+    /// Moderator resolves report and gets updated list.
+    pub async fn resolve_report(&self, req: ReportResolve) {
+        if !self.user.is_moderator() {
+            return;
+        }
+        if self.db.chat.resolve_report(&req.report_id).await {
+            self.get_reports().await;
         }
     }
 
@@ -150,25 +557,120 @@ impl<'a> MessageHandler<'a> {
         }
     }
 
-    pub fn add_game_req(&self, game_req: GameRequest) {
-        if self.ws.players.check_in_game(&game_req.username) {
-            if let Some(msg) = self.ws.game_reqs.add(game_req) {
-                self.msg_sender.send_msg(msg, SendTo::All);
+    pub async fn add_game_req(&self, game_req: GameRequest) {
+        if !self.ws.players.check_in_game(&game_req.username) {
+            return;
+        }
+        let opponent = game_req.opponent.clone();
+        let data = serde_json::json!({
+            "from": &game_req.username,
+            "variant": &game_req.variant,
+            "time": game_req.time,
+            "incr": game_req.incr,
+            "credits": game_req.credits
+        });
+        let to = game_req.audience();
+        if let Some(msg) = self.ws.game_reqs.add(game_req) {
+            self.msg_sender.send_msg(msg, to);
+            if let Some(opponent) = opponent {
+                self.notify(&opponent, NotificationKind::Challenge, data)
+                    .await;
             }
         }
     }
 
+    // PUZZLES PART
+
+    pub async fn get_puzzle(&self) {
+        let msg = match &self.db.mongo {
+            Some(mongo) => next_puzzle(mongo, self.user).await,
+            None => unsupported(),
+        };
+        self.msg_sender.send_msg(msg, SendTo::Me);
+    }
+
+    pub async fn puzzle_move(&self, req: &PuzzleMove) {
+        let msg = match &self.db.mongo {
+            Some(mongo) => check_move(mongo, self.user, req).await,
+            None => unsupported(),
+        };
+        self.msg_sender.send_msg(msg, SendTo::Me);
+    }
+
+    // ANALYSIS PART
+
+    pub fn analysis_new(&self, req: &AnalysisReq) {
+        let msg = self.ws.analysis.new_board(&self.user.username, req);
+        self.send_analysis(msg);
+    }
+
+    pub fn analysis_set(&self, req: &AnalysisReq) {
+        let msg = self.ws.analysis.set_position(&self.user.username, req);
+        self.send_analysis(msg);
+    }
+
+    pub fn analysis_move(&self, req: &AnalysisReq) {
+        let msg = self.ws.analysis.play(&self.user.username, req);
+        self.send_analysis(msg);
+    }
+
+    pub fn analysis_deploy(&self) {
+        let msg = self.ws.analysis.deploy(&self.user.username);
+        self.send_analysis(msg);
+    }
+
+    pub fn analysis_undo(&self) {
+        let msg = self.ws.analysis.undo(&self.user.username);
+        self.send_analysis(msg);
+    }
+
+    pub fn analysis_redo(&self) {
+        let msg = self.ws.analysis.redo(&self.user.username);
+        self.send_analysis(msg);
+    }
+
+    pub fn analysis_close(&self) {
+        self.ws.analysis.close(&self.user.username);
+    }
+
+    pub fn analysis_connect(&self) {
+        self.ws.analysis.connect(&self.user.username);
+    }
+
+    pub fn analysis_disconnect(&self) {
+        self.ws.analysis.disconnect(&self.user.username);
+    }
+
+    /// Legal moves for one square, for live game or any position.
+    pub fn get_hints(&self, req: &HintsReq) {
+        let moves = square_hints(&self.ws.shuuro_games, req);
+        self.msg_sender
+            .send_msg(legal_hints(req, moves), SendTo::Me);
+    }
+
+    fn send_analysis(&self, msg: Option<Value>) {
+        if let Some(msg) = msg {
+            self.msg_sender.send_msg(msg, SendTo::Me);
+        }
+    }
+
+    /// Top players for home page.
+    pub fn get_leaderboards(&self) {
+        let msg = leaderboard_full(self.ws.leaderboards.home());
+        self.msg_sender.send_msg(msg, SendTo::Me);
+    }
+
     pub fn get_all_game_reqs(&self) {
-        let all = self.ws.game_reqs.get_all();
+        let all = self.ws.game_reqs.get_all(&self.user.username);
         let msg = home_lobby_full(all);
         self.msg_sender.send_msg(msg, SendTo::Me);
     }
 
     pub fn remove_game_req(&self, username: &String) {
-        if let Some(msg) =
+        if let Some((msg, to)) =
             self.ws.game_reqs.remove("home_lobby_remove", username)
         {
-            self.msg_sender.send_msg(msg, SendTo::All);
+            self.msg_sender.send_msg(msg, to);
         }
     }
 
@@ -180,15 +682,29 @@ impl<'a> MessageHandler<'a> {
 
     async fn accept_game_req(&self, game: GameRequest) {
         let request = game.clone();
-        let shuuro_game = self.create_game(game).await;
+        let mut shuuro_game = self.create_game(game).await;
         let players = shuuro_game.players.clone();
+        if let Err(e) = self.db.games.add_game(&mut shuuro_game).await {
+            error!(
+                white = %players[0],
+                black = %players[1],
+                error = %e,
+                "can't add game"
+            );
+            let msg = home_lobby_error("game can't be created");
+            self.msg_sender.send_msg(msg, SendTo::Players(players));
+            return;
+        }
         let id = String::from(&shuuro_game._id);
         let id2 = String::from(&id);
         self.ws.players.new_spectators(&shuuro_game._id);
         let shuuro_game = self.ws.shuuro_games.add_game(shuuro_game);
-        let msg = add_game_to_db(&self.db.mongo.games, &shuuro_game).await;
         {
-            if shuuro_game.sub_variant.is_some() {
+            if shuuro_game.sub_variant.is_some()
+                || shuuro_game.start_sfen.is_some()
+            {
+                // Starting position is set by live game.
+                self.db.games.update_game(&shuuro_game).await;
                 let hand = {
                     format!(
                         "{}{}",
@@ -199,90 +715,31 @@ impl<'a> MessageHandler<'a> {
                 self.msg_sender.send_tv_msg(msg, &self.ws.players);
             }
         }
+        info!(
+            game_id = %id,
+            white = %players[0],
+            black = %players[1],
+            "game started"
+        );
         self.ws.players.add_players(&players);
-        self.msg_sender.send_msg(msg, SendTo::Players(players));
+        for player in &players {
+            let msg = live_game_start(&shuuro_game, player);
+            self.msg_sender
+                .send_msg(msg, SendTo::User(String::from(player)));
+        }
         self.ws
             .shuuro_games
             .change_variant(&GameGet::from((&request, &id2)));
         self.shuuro_games_count(SendTo::All);
         self.ws.chat.add_chat(&id);
-        let _lost_on_time_task =
-            self.lost_on_time_task(&GameGet::from((&request, &id2)));
-        let _check_clock_task = self.check_clock_task(&id);
-    }
-
-    pub fn lost_on_time_task(&self, json: &GameGet) -> JoinHandle<()> {
-        let mut db_rv = self.db_tx.subscribe();
-        let ws2 = self.ws.clone();
-        let db = self.db.mongo.games.clone();
-        tokio::spawn({
-            let json = json.clone();
-            let msg_sender = self.msg_sender.clone();
-            async move {
-                while let Ok(msg) = db_rv.recv().await {
-                    if let MsgDatabase::LostOnTime(b) = &msg {
-                        ws2.shuuro_games.check_clocks(&json, b);
-                        let time_check = b.lock().unwrap();
-                        if !time_check.exist {
-                            break;
-                        } else if time_check.finished {
-                            let tv_spectators =
-                                ws2.players.get_spectators("tv");
-                            let match_spectators =
-                                ws2.players.get_spectators(&json.game_id);
-                            drop(time_check);
-                            if let Some(values) =
-                                ws2.shuuro_games.clock_status(&json, b)
-                            {
-                                ws2.players.remove_players(&values.2);
-                                msg_sender.send_msg(
-                                    values.0.clone(),
-                                    SendTo::Players(values.2),
-                                );
-                                if let Some(s) = match_spectators {
-                                    msg_sender.send_msg(
-                                        values.0,
-                                        SendTo::Spectators(s),
-                                    );
-                                }
-                                msg_sender.send_msg(
-                                    values.1,
-                                    SendTo::Spectators(tv_spectators.unwrap()),
-                                );
-                            }
-
-                            tokio::spawn(async move {
-                                ws2.shuuro_games
-                                    .remove_game(&json, &db.clone())
-                                    .await;
-                                let count = ws2.shuuro_games.game_count();
-                                let msg = fmt_count("active_games", count);
-                                msg_sender.send_msg(msg, SendTo::All);
-                                ws2.chat.remove_chat(&json.game_id);
-                            });
-                            break;
-                        }
-                    }
-                }
-            }
-        })
-    }
-
-    pub fn check_clock_task(&self, id: &String) -> JoinHandle<()> {
-        let id = String::from(id);
-        let db_tx = self.db_tx.clone();
-        tokio::spawn(async move {
-            let a = arc2(TimeCheck::new(&id));
-            loop {
-                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-                let t = a.lock().unwrap();
-                if t.finished || t.both_lost || !t.exist {
-                    //self2.lost_on_time(&id2, values);
-                    break;
-                }
-                if db_tx.send(MsgDatabase::LostOnTime(a.clone())).is_ok() {}
-            }
-        })
+        self.notify_playing(&shuuro_game).await;
+        self.update_deadline(&GameGet::from((&request, &id2)));
+    }
+
+    /// Reschedule flag fall after clock was clicked or game ended.
+    fn update_deadline(&self, json: &GameGet) {
+        let deadline = self.ws.shuuro_games.deadline(json);
+        self.ws.scheduler.schedule(json, deadline);
     }
 
     pub async fn check_game_req(&self, game: GameRequest) {
@@ -292,24 +749,31 @@ impl<'a> MessageHandler<'a> {
         if game.username() == self.user.username {
             self.remove_game_req(&game.username);
         } else {
+            let username = &self.user.username;
+            if !self.ws.game_reqs.can_accept(&game.username, username) {
+                return;
+            }
             self.remove_game_req(&game.username);
             self.remove_game_req(&self.user.username);
             self.accept_game_req(game).await;
         }
     }
 
-    pub fn _lost_on_time(&self, json: &GameGet, values: (Value, Value)) {
-        if let Some(players) = self.ws.shuuro_games.get_players(json) {
-            self.msg_sender
-                .send_msg(values.0.clone(), SendTo::Players(players));
-            if let Some(spectators) =
-                self.ws.players.get_spectators(&json.game_id)
-            {
-                self.msg_sender
-                    .send_msg(values.0, SendTo::Spectators(spectators));
+    /// Replace private player IDs in message with public game ID.
+    pub async fn public_game_ids(&self, value: &mut Value) -> bool {
+        let games = &self.ws.shuuro_games;
+        let mut changed = false;
+        for pointer in ["/game_id", "/data/game_id"] {
+            if let Some(Value::String(id)) = value.pointer_mut(pointer) {
+                if let Some(public) =
+                    games.public_id(id, self.db.games.as_ref()).await
+                {
+                    *id = public;
+                    changed = true;
+                }
             }
         }
-        self.msg_sender.send_tv_msg(values.1, &self.ws.players);
+        changed
     }
 
     pub fn get_hand(&self, json: &GameGet) {
@@ -334,10 +798,10 @@ impl<'a> MessageHandler<'a> {
         if let Some(game) = self
             .ws
             .shuuro_games
-            .get_game(json, &self.db.mongo.games, self)
+            .get_game(json, self.db.games.as_ref(), self)
             .await
         {
-            let res = live_game_start(&game);
+            let res = live_game_start(&game, username);
             if !&game.players.contains(username) {
                 self.ws.players.add_spectator(&game._id, username);
                 self.user.watch(&json.game_id);
@@ -358,6 +822,7 @@ impl<'a> MessageHandler<'a> {
         }
     }
 
+    #[instrument(skip_all, fields(game_id = %json.game_id))]
     pub fn shop_move(&self, json: GameGet) {
         #[allow(clippy::collapsible_match)]
         if let Some(confirmed) =
@@ -366,10 +831,12 @@ impl<'a> MessageHandler<'a> {
             if let LiveGameMove::BuyMove(confirmed) = confirmed {
                 self.confirm_shop(&json, &confirmed);
                 self.set_deploy(&json, confirmed);
+                self.update_deadline(&json);
             }
         }
     }
 
+    #[instrument(skip_all, fields(game_id = %json.game_id))]
     pub async fn place_move(&self, mut json: GameGet) {
         #[allow(clippy::collapsible_match)]
         if let Some(m) =
@@ -386,12 +853,15 @@ impl<'a> MessageHandler<'a> {
                 if fme {
                     self.ws
                         .shuuro_games
-                        .remove_game(&json, &self.db.mongo.games)
+                        .remove_game(&json, self.db, &self.ws.leaderboards)
                         .await;
                     self.shuuro_games_count(SendTo::All);
                     self.ws.players.remove_spectators(&json.game_id);
                     self.ws.players.remove_players(&players);
+                    self.update_deadline(&json);
                 } else {
+                    self.notify_move(&json.game_id, &players).await;
+                    self.update_deadline(&json);
                     json.game_move = sfen;
                     let _ = self
                         .db_tx
@@ -402,6 +872,7 @@ impl<'a> MessageHandler<'a> {
         }
     }
 
+    #[instrument(skip_all, fields(game_id = %json.game_id))]
     pub async fn fight_move(&self, mut json: GameGet) {
         #[allow(clippy::collapsible_match)]
         if let Some(m) =
@@ -424,14 +895,17 @@ impl<'a> MessageHandler<'a> {
                 if status > 0 {
                     self.ws
                         .shuuro_games
-                        .remove_game(&json, &self.db.mongo.games)
+                        .remove_game(&json, self.db, &self.ws.leaderboards)
                         .await;
                     self.ws.players.remove_players(&players);
                     self.shuuro_games_count(SendTo::All);
                     self.ws.players.remove_spectators(&json.game_id);
                     let res_end = live_game_end(&json.game_id);
                     self.msg_sender.send_tv_msg(res_end, &self.ws.players);
+                    self.update_deadline(&json);
                 } else {
+                    self.notify_move(&game_id, &players).await;
+                    self.update_deadline(&json);
                     json.game_move = sfen;
                     let _ = self
                         .db_tx
@@ -482,12 +956,12 @@ impl<'a> MessageHandler<'a> {
                 ) {
                     _s_count = s;
                 }
-                if let Some(r) = self
+                if let Some((r, to)) = self
                     .ws
                     .game_reqs
                     .remove("home_lobby_remove", &self.user.username)
                 {
-                    self.msg_sender.send_msg(r, SendTo::All);
+                    self.msg_sender.send_msg(r, to);
                 }
                 self.ws.players.remove_online_player(&self.user.username)
             }
@@ -508,12 +982,34 @@ impl<'a> MessageHandler<'a> {
 
     async fn create_game(&self, game: GameRequest) -> ShuuroGame {
         let colors = game.colors(&self.user.username);
-        let id = game_exist(&self.db.mongo.games).await;
-        ShuuroGame::from((&game, &colors, id.as_str()))
+        // ID is chosen when game is added to database.
+        let mut shuuro_game = ShuuroGame::from((&game, &colors, ""));
+        shuuro_game.tc.start_clock(&self.ws.clock);
+        if shuuro_game.start_sfen.is_some()
+            || shuuro_game.credits != [SHOP_CREDIT, SHOP_CREDIT]
+        {
+            return shuuro_game;
+        }
+        let key = rating_key(&shuuro_game);
+        let mut ratings = [0; 2];
+        for (i, username) in colors.iter().enumerate() {
+            match self.db.players.get_player(username).await {
+                Some(player) if player.reg => {
+                    let rating = player.ratings.get(&key).copied();
+                    ratings[i] =
+                        rating.unwrap_or_default().rating.round() as i32;
+                }
+                _ => return shuuro_game,
+            }
+        }
+        shuuro_game.rated = true;
+        shuuro_game.ratings = Some(ratings);
+        shuuro_game
     }
 
     // DRAW PART
 
+    #[instrument(skip_all, fields(game_id = %json.game_id))]
     pub async fn draw_req(&self, json: &GameGet, username: &String) {
         let draw = self.ws.shuuro_games.draw_req(json, username);
         if let Some(draw) = draw {
@@ -533,8 +1029,9 @@ impl<'a> MessageHandler<'a> {
                 }
                 self.ws
                     .shuuro_games
-                    .remove_game(json, &self.db.mongo.games)
+                    .remove_game(json, self.db, &self.ws.leaderboards)
                     .await;
+                self.update_deadline(json);
                 self.shuuro_games_count(SendTo::All);
             } else {
                 let res = live_game_draw2(d, &json.game_id, username);
@@ -549,6 +1046,7 @@ impl<'a> MessageHandler<'a> {
             }
         }
     }
+    #[instrument(skip_all, fields(game_id = %json.game_id))]
     pub async fn resign(&self, json: &GameGet, username: &String) {
         if let Some(players) = self.ws.shuuro_games.resign(json, username) {
             let res = live_game_resign(username, &json.game_id);
@@ -562,8 +1060,9 @@ impl<'a> MessageHandler<'a> {
             }
             self.ws
                 .shuuro_games
-                .remove_game(json, &self.db.mongo.games)
+                .remove_game(json, self.db, &self.ws.leaderboards)
                 .await;
+            self.update_deadline(json);
             self.shuuro_games_count(SendTo::All);
         }
     }
@@ -584,34 +1083,15 @@ impl<'a> MessageHandler<'a> {
     }
 
     pub async fn save_all(&self) {
-        if self.user.username == "iiiurosiii" {
+        if self.user.is_admin() {
             *self.adding.lock().unwrap() = false;
             self.ws
                 .shuuro_games
-                .save_on_exit(&self.db.mongo.games)
+                .save_on_exit(self.db.games.as_ref())
                 .await;
             std::process::exit(1);
         }
     }
-
-    pub async fn start_unfinished_clock(&self) {
-        if self.user.username == "iiiurosiii" {
-            let unfinished = self.ws.shuuro_games.get_unfinished();
-            if unfinished.is_empty() {
-                return;
-            }
-            let variants =
-                ["standard", "shuuro", "shuuroFairy", "standardFairy"];
-            for (i, v) in unfinished.iter().enumerate() {
-                for id in v {
-                    let json = GameGet::new(id, &String::from(variants[i]));
-                    let _lost_on_time = self.lost_on_time_task(&json);
-                    let _check_clock = self.check_clock_task(id);
-                }
-            }
-            self.ws.shuuro_games.delete_unfinished();
-        }
-    }
 }
 
 #[derive(Clone)]
@@ -630,7 +1110,9 @@ impl MsgSender {
 
     pub fn send_msg(&self, value: Value, to: SendTo) {
         let cm = ClientMessage::new(&self.user, value, to);
-        let _ = self.tx.send(cm);
+        if self.tx.send(cm).is_err() {
+            METRICS.broadcast_dropped();
+        }
     }
 
     pub fn send_tv_msg(&self, message: Value, players: &Players) {
@@ -1,10 +1,16 @@
+pub mod analysis;
+pub mod chat_filter;
 pub mod client_messages;
+pub mod clock;
 pub mod game_requests;
 pub mod games;
 pub mod handler;
+pub mod leaderboards;
 pub mod live_game;
 pub mod messages;
+pub mod rate_limit;
 pub mod rooms;
+pub mod scheduler;
 pub mod server_messages;
 pub mod state;
 pub mod time_control;
@@ -0,0 +1,216 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    hash::Hash,
+    net::{IpAddr, SocketAddr},
+    sync::{Mutex, MutexGuard},
+};
+
+use async_session::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts, State},
+    http::{request::Parts, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    config::{Limit, RateLimitConfig},
+    AppState,
+};
+
+use super::chat_filter::TokenBucket;
+
+/// Above this many keys, refilled buckets are forgotten.
+/// If that's not enough, least recently used half is removed.
+pub const MAX_KEYS: usize = 10_000;
+
+/// Limits for websocket messages, HTTP requests and new accounts.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    sessions: Buckets<(String, String)>,
+    ips: Buckets<IpAddr>,
+    http: Buckets<IpAddr>,
+    accounts: Buckets<IpAddr>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            config: config.clone(),
+            sessions: Buckets::default(),
+            ips: Buckets::default(),
+            http: Buckets::default(),
+            accounts: Buckets::default(),
+        }
+    }
+
+    /// Take token for websocket message from session and its IP.
+    /// Tokens are taken only if both buckets have one.
+    pub fn message(&self, session: &str, ip: Option<IpAddr>, t: &str) -> bool {
+        let (t, limit) = match self.config.messages.get_key_value(t) {
+            Some((t, limit)) => (String::from(t), limit),
+            None => (String::from("*"), &self.config.default),
+        };
+        let key = (String::from(session), t);
+        // Sessions are always locked before IPs.
+        let mut sessions = self.sessions.lock();
+        match ip {
+            Some(ip) => {
+                let mut ips = self.ips.lock();
+                if !has_token(&sessions, &key) || !has_token(&ips, &ip) {
+                    return false;
+                }
+                take(&mut sessions, key, limit)
+                    & take(&mut ips, ip, &self.config.ip)
+            }
+            None => take(&mut sessions, key, limit),
+        }
+    }
+
+    /// Take token for HTTP request.
+    pub fn http(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => self.http.take(ip, &self.config.http),
+            None => true,
+        }
+    }
+
+    /// Take token for new anonymous account.
+    pub fn new_account(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => self.accounts.take(ip, &self.config.accounts),
+            None => true,
+        }
+    }
+
+    /// Socket has too many strikes and it's closed.
+    pub fn is_closed(&self, strikes: &Strikes) -> bool {
+        strikes.count >= self.config.strikes
+    }
+
+    /// Add strike for rejected message. Returns true if socket is closed.
+    pub fn strike(&self, strikes: &mut Strikes, now: DateTime<Utc>) -> bool {
+        let reset = Duration::seconds(self.config.strikes_reset_secs as i64);
+        if strikes.last.is_some_and(|last| now - last >= reset) {
+            strikes.count = 0;
+        }
+        strikes.count += 1;
+        strikes.last = Some(now);
+        self.is_closed(strikes)
+    }
+
+    /// Client address, from last proxy if it's trusted.
+    pub fn client_ip(&self, parts: &Parts) -> Option<IpAddr> {
+        if self.config.trust_proxy {
+            if let Some(ip) = forwarded_for(&parts.headers) {
+                return Some(ip);
+            }
+        }
+        let info = parts.extensions.get::<ConnectInfo<SocketAddr>>()?;
+        Some(info.0.ip())
+    }
+}
+
+/// Rejected messages on one socket.
+#[derive(Default)]
+pub struct Strikes {
+    count: u32,
+    last: Option<DateTime<Utc>>,
+}
+
+impl Strikes {
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+/// Last address added to `X-Forwarded-For`.
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    let header = headers.get("x-forwarded-for")?.to_str().ok()?;
+    header.rsplit(',').next()?.trim().parse().ok()
+}
+
+/// Token buckets with same limit for many keys.
+struct Buckets<K> {
+    all: Mutex<HashMap<K, TokenBucket>>,
+}
+
+impl<K> Default for Buckets<K> {
+    fn default() -> Self {
+        Self {
+            all: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Eq + Hash> Buckets<K> {
+    fn lock(&self) -> MutexGuard<'_, HashMap<K, TokenBucket>> {
+        self.all.lock().unwrap()
+    }
+
+    fn take(&self, key: K, limit: &Limit) -> bool {
+        take(&mut self.lock(), key, limit)
+    }
+}
+
+/// Key has token left, new keys always have it.
+fn has_token<K: Eq + Hash>(all: &HashMap<K, TokenBucket>, key: &K) -> bool {
+    all.get(key).is_none_or(TokenBucket::has_token)
+}
+
+fn take<K: Eq + Hash>(
+    all: &mut HashMap<K, TokenBucket>,
+    key: K,
+    limit: &Limit,
+) -> bool {
+    if all.len() >= MAX_KEYS && !all.contains_key(&key) {
+        all.retain(|_, bucket| !bucket.is_full());
+        if all.len() >= MAX_KEYS {
+            let mut used: Vec<_> =
+                all.values().map(TokenBucket::last_used).collect();
+            used.sort_unstable();
+            let oldest = used[used.len() / 2];
+            all.retain(|_, bucket| bucket.last_used() > oldest);
+        }
+    }
+    all.entry(key)
+        .or_insert_with(|| {
+            TokenBucket::new(limit.burst, 1.0 / limit.refill_secs)
+        })
+        .take()
+}
+
+/// Client address, if it's known.
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let state = AppState::from_ref(state);
+        Ok(Self(state.ws.rate_limits.client_ip(parts)))
+    }
+}
+
+/// Reject HTTP requests above limit.
+pub async fn limit_http<B>(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !state.ws.rate_limits.http(ip) {
+        return StatusCode::TOO_MANY_REQUESTS.into_response();
+    }
+    next.run(req).await
+}
@@ -1,14 +1,19 @@
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 
+use bson::DateTime;
+use chrono::Local;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::arc2;
+use crate::config::ChatConfig;
 use crate::database::mongo::ShuuroGame;
 use crate::database::redis::UserSession;
 
-use super::server_messages::live_chat_message;
+use super::chat_filter::{ChatFilter, FilterResult};
+use super::clock::{system_clock, Clock, SharedClock};
+use super::server_messages::{live_chat_delete, live_chat_message};
 
 /// Struct containing active players and spectators
 pub struct Players {
@@ -41,6 +46,16 @@ impl Players {
         in_game.len()
     }
 
+    /// Check if player is connected.
+    pub fn is_online(&self, username: &str) -> bool {
+        self.online.lock().unwrap().contains(username)
+    }
+
+    /// Check if player is playing now.
+    pub fn is_playing(&self, username: &str) -> bool {
+        self.in_game.lock().unwrap().contains(username)
+    }
+
     pub fn check_in_game(&self, username: &str) -> bool {
         let in_game = self.in_game.lock().unwrap();
         in_game.get(username).is_none()
@@ -123,13 +138,15 @@ impl Default for Players {
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ChatMsg {
     pub id: String,
     pub user: String,
     pub time: String,
     pub message: String,
     pub variant: String,
+    #[serde(default)]
+    pub msg_id: u64,
 }
 
 impl ChatMsg {
@@ -141,70 +158,152 @@ impl ChatMsg {
             message,
             id,
             variant: String::from("shuuro"),
+            msg_id: 0,
         }
     }
 
     /// Formats date in format HH:MM
-    pub fn update(&mut self, user: &String) {
+    pub fn update(&mut self, user: &String, msg_id: u64, clock: &dyn Clock) {
         self.user = String::from(user);
-        self.time = chrono::offset::Local::now().to_rfc3339();
+        self.time = clock.now().with_timezone(&Local).to_rfc3339();
+        self.msg_id = msg_id;
     }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ChatRooms {
     messages: Arc<Mutex<HashMap<String, Vec<ChatMsg>>>>,
+    counter: Arc<Mutex<u64>>,
+    muted: Arc<Mutex<HashMap<String, DateTime>>>,
+    max_length: usize,
+    #[serde(skip)]
+    filter: ChatFilter,
+    #[serde(skip, default = "system_clock")]
+    clock: SharedClock,
 }
 
-impl Default for ChatRooms {
-    fn default() -> Self {
+/// Chat message after it went through filter.
+pub enum NewChatMsg {
+    Public(Value, ChatMsg),
+    Hidden(Value, ChatMsg, &'static str),
+    RateLimited,
+}
+
+impl ChatRooms {
+    pub fn new(config: &ChatConfig, clock: SharedClock) -> Self {
         let mut messages = HashMap::default();
         messages.insert(String::from("home"), vec![]);
         let messages = arc2(messages);
-        Self { messages }
+        Self {
+            messages,
+            counter: arc2(0),
+            muted: arc2(HashMap::default()),
+            max_length: config.max_length,
+            filter: ChatFilter::new(config),
+            clock,
+        }
     }
-}
 
-impl ChatRooms {
-    /// Can message be added to room.
-    fn can_add(&self, chat: &Vec<ChatMsg>, player: &UserSession) -> bool {
-        if !player.reg {
-            return false;
-        }
-        let count = chat.iter().fold(0, |mut acc, x| {
-            if x.user == player.username {
-                acc += 1;
-            }
-            acc
-        });
-        if count < 5 {
+    /// Check if message is shorter than limit.
+    fn message_length(&self, message: &str) -> bool {
+        if !message.is_empty() && message.len() < self.max_length {
             return true;
         }
         false
     }
 
-    /// Check if message length less than 50 chars.
-    fn message_length(&self, m: &ChatMsg) -> bool {
-        if !m.message.is_empty() && m.message.len() < 50 {
-            return true;
+    /// Check length, mute and filter for new message.
+    /// Returns `None` if message can't be sent at all.
+    pub fn check_msg(
+        &self,
+        player: &UserSession,
+        message: &str,
+    ) -> Option<FilterResult> {
+        if !player.reg
+            || !self.message_length(message)
+            || self.is_muted(&player.username).is_some()
+        {
+            return None;
         }
-        false
+        Some(self.filter.check(&player.username, message))
     }
 
-    /// Add new message.
+    /// Add new message. Filtered messages are visible only to sender.
     pub fn add_msg(
         &self,
         id: &String,
         mut m: ChatMsg,
         player: &UserSession,
-    ) -> Option<Value> {
+    ) -> Option<NewChatMsg> {
+        if let Some(chat) = self.messages.lock().unwrap().get_mut(id) {
+            let check = self.check_msg(player, &m.message)?;
+            if check == FilterResult::RateLimited {
+                return Some(NewChatMsg::RateLimited);
+            }
+            m.update(&player.username, self.next_id(), self.clock.as_ref());
+            let res = live_chat_message(&m);
+            if let FilterResult::Hidden(reason) = check {
+                return Some(NewChatMsg::Hidden(res, m, reason));
+            }
+            chat.push(m.clone());
+            return Some(NewChatMsg::Public(res, m));
+        }
+        None
+    }
+
+    /// Save when account was created.
+    pub fn add_account(&self, username: &str, created_at: DateTime) {
+        self.filter.add_account(username, created_at);
+    }
+
+    /// Unique ID for every message.
+    fn next_id(&self) -> u64 {
+        let mut counter = self.counter.lock().unwrap();
+        *counter += 1;
+        *counter
+    }
+
+    /// Delete one message from room.
+    pub fn delete_msg(&self, id: &String, msg_id: u64) -> Option<Value> {
         if let Some(chat) = self.messages.lock().unwrap().get_mut(id) {
-            if self.message_length(&m) && self.can_add(chat, player) {
-                m.update(&player.username);
-                let res = live_chat_message(&m);
-                chat.push(m.clone());
-                return Some(res);
+            if let Some(index) = chat.iter().position(|m| m.msg_id == msg_id) {
+                chat.remove(index);
+                return Some(live_chat_delete(id, msg_id));
+            }
+        }
+        None
+    }
+
+    /// Get reported message with 5 messages before and after it.
+    pub fn context(&self, id: &String, msg_id: u64) -> Option<Vec<ChatMsg>> {
+        if let Some(chat) = self.messages.lock().unwrap().get(id) {
+            if let Some(index) = chat.iter().position(|m| m.msg_id == msg_id) {
+                let start = index.saturating_sub(5);
+                let end = (index + 6).min(chat.len());
+                return Some(chat[start..end].to_vec());
+            }
+        }
+        None
+    }
+
+    /// Mute player in all rooms until selected time.
+    pub fn mute(&self, username: &str, until: Option<DateTime>) {
+        let mut muted = self.muted.lock().unwrap();
+        if let Some(until) = until {
+            muted.insert(String::from(username), until);
+        } else {
+            muted.remove(username);
+        }
+    }
+
+    /// Returns time when mute expires, if player is still muted.
+    pub fn is_muted(&self, username: &str) -> Option<DateTime> {
+        let mut muted = self.muted.lock().unwrap();
+        if let Some(until) = muted.get(username) {
+            if *until > DateTime::now() {
+                return Some(*until);
             }
+            muted.remove(username);
         }
         None
     }
@@ -223,6 +322,18 @@ impl ChatRooms {
         drop(chat);
     }
 
+    /// Restore chat from database.
+    pub fn set_chat(&self, id: &String, lines: Vec<ChatMsg>) {
+        let mut counter = self.counter.lock().unwrap();
+        if let Some(last) = lines.iter().map(|m| m.msg_id).max() {
+            *counter = last.max(*counter);
+        }
+        self.messages
+            .lock()
+            .unwrap()
+            .insert(String::from(id), lines);
+    }
+
     pub fn remove_chat(&self, id: &String) {
         let mut chat = self.messages.lock().unwrap();
         chat.remove(&String::from(id));
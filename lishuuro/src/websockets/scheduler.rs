@@ -0,0 +1,123 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use tokio::sync::Notify;
+use tracing::{debug, info_span, Instrument};
+
+use crate::{database::Database, metrics::METRICS};
+
+use super::{
+    server_messages::fmt_count, ClientMessage, GameGet, SendTo, WsState,
+};
+
+/// Flag-fall deadlines for all live games, ordered by time.
+#[derive(Default)]
+pub struct ClockScheduler {
+    deadlines: Mutex<Deadlines>,
+    changed: Notify,
+}
+
+#[derive(Default)]
+struct Deadlines {
+    queue: BTreeSet<(DateTime<Utc>, String)>,
+    games: HashMap<String, (DateTime<Utc>, GameGet)>,
+}
+
+impl ClockScheduler {
+    /// Set new deadline for game. Game without deadline is removed.
+    pub fn schedule(&self, json: &GameGet, deadline: Option<DateTime<Utc>>) {
+        let mut deadlines = self.deadlines.lock().unwrap();
+        let id = String::from(&json.game_id);
+        if let Some((old, _)) = deadlines.games.remove(&id) {
+            deadlines.queue.remove(&(old, String::from(&id)));
+        }
+        if let Some(deadline) = deadline {
+            deadlines.queue.insert((deadline, String::from(&id)));
+            deadlines.games.insert(id, (deadline, json.clone()));
+        }
+        drop(deadlines);
+        self.changed.notify_one();
+    }
+
+    /// Remove games with passed deadline. Also returns next deadline.
+    pub fn due(
+        &self,
+        now: DateTime<Utc>,
+    ) -> (Vec<GameGet>, Option<DateTime<Utc>>) {
+        let mut deadlines = self.deadlines.lock().unwrap();
+        let mut due = vec![];
+        while let Some((deadline, id)) = deadlines.queue.first().cloned() {
+            if deadline > now {
+                return (due, Some(deadline));
+            }
+            deadlines.queue.pop_first();
+            if let Some((_, json)) = deadlines.games.remove(&id) {
+                due.push(json);
+            }
+        }
+        (due, None)
+    }
+}
+
+/// Wait for next deadline and finish games where player lost on time.
+pub async fn run_scheduler(ws: Arc<WsState>, db: Arc<Database>) {
+    loop {
+        let now = ws.clock.now();
+        let (due, next) = ws.scheduler.due(now);
+        for json in due {
+            let ws = ws.clone();
+            let db = db.clone();
+            let span = info_span!("game", game_id = %json.game_id);
+            let task = async move { flag_fall(&ws, &db, &json).await };
+            tokio::spawn(task.instrument(span));
+        }
+        if let Some(next) = next {
+            let wait = (next - now).to_std().unwrap_or_default();
+            tokio::select! {
+                _ = ws.clock.sleep(wait) => (),
+                _ = ws.scheduler.changed.notified() => (),
+            }
+        } else {
+            ws.scheduler.changed.notified().await;
+        }
+    }
+}
+
+/// Check clocks after deadline. If nobody lost, clock was clicked meanwhile.
+async fn flag_fall(ws: &WsState, db: &Arc<Database>, json: &GameGet) {
+    let time_check = match ws.shuuro_games.check_clocks(json) {
+        Some(time_check) => time_check,
+        None => return,
+    };
+    if !time_check.finished {
+        ws.scheduler.schedule(json, ws.shuuro_games.deadline(json));
+        return;
+    }
+    debug!("lost on time");
+    let tv_spectators = ws.players.get_spectators("tv");
+    let match_spectators = ws.players.get_spectators(&json.game_id);
+    if let Some(values) = ws.shuuro_games.clock_status(json, &time_check) {
+        ws.players.remove_players(&values.2);
+        send(ws, values.0.clone(), SendTo::Players(values.2));
+        if let Some(s) = match_spectators {
+            send(ws, values.0, SendTo::Spectators(s));
+        }
+        send(ws, values.1, SendTo::Spectators(tv_spectators.unwrap()));
+    }
+    ws.shuuro_games
+        .remove_game(json, db, &ws.leaderboards)
+        .await;
+    let count = ws.shuuro_games.game_count();
+    send(ws, fmt_count("active_games", count), SendTo::All);
+    ws.chat.remove_chat(&json.game_id);
+}
+
+fn send(ws: &WsState, msg: Value, to: SendTo) {
+    if ws.tx.send(ClientMessage::system(msg, to)).is_err() {
+        METRICS.broadcast_dropped();
+    }
+}
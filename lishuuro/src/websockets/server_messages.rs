@@ -1,16 +1,165 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use chrono::Utc;
 use serde_json::{json, Value};
 
-use crate::database::mongo::ShuuroGame;
+use crate::database::mongo::{
+    ChatReport, Conversation, DirectMessage, FilteredMsg, Notification, Puzzle,
+    ShuuroGame,
+};
 
-use super::{rooms::ChatMsg, GameRequest, TvGame};
+use super::{
+    analysis::HintsReq, leaderboards::LeaderboardEntry, rooms::ChatMsg,
+    state::FriendStatus, GameRequest, TvGame,
+};
 
 pub fn live_chat_message(msg: &ChatMsg) -> Value {
     json!({ "t": "live_chat_message", "data": msg })
 }
 
+pub fn live_chat_delete(id: &str, msg_id: u64) -> Value {
+    json!({ "t": "live_chat_delete", "data": { "id": id, "msg_id": msg_id } })
+}
+
+pub fn live_chat_muted(until: &bson::DateTime) -> Value {
+    json!({ "t": "live_chat_muted", "data": { "until": until.timestamp_millis() } })
+}
+
+pub fn live_chat_error(id: &str, reason: &str) -> Value {
+    json!({ "t": "live_chat_error", "data": { "id": id, "reason": reason } })
+}
+
+pub fn mod_mute(username: &str, until: Option<bson::DateTime>) -> Value {
+    let until = until.map(|u| u.timestamp_millis());
+    json!({ "t": "mod_mute", "data": { "username": username, "until": until } })
+}
+
+pub fn mod_reports(reports: Vec<ChatReport>) -> Value {
+    json!({ "t": "mod_reports", "data": { "reports": reports } })
+}
+
+pub fn mod_filtered(messages: Vec<FilteredMsg>) -> Value {
+    json!({ "t": "mod_filtered", "data": { "messages": messages } })
+}
+
+pub fn inbox_message(m: &DirectMessage) -> Value {
+    json!({ "t": "inbox_message", "data": m })
+}
+
+pub fn inbox_full(conversations: Vec<Conversation>) -> Value {
+    json!({ "t": "inbox_full", "data": { "conversations": conversations } })
+}
+
+pub fn inbox_conversation(
+    username: &str,
+    messages: Vec<DirectMessage>,
+) -> Value {
+    json!({ "t": "inbox_conversation", "data": { "username": username, "messages": messages } })
+}
+
+pub fn inbox_unread(count: u32) -> Value {
+    json!({ "t": "inbox_unread", "data": { "count": count } })
+}
+
+pub fn inbox_block(username: &str, block: bool) -> Value {
+    json!({ "t": "inbox_block", "data": { "username": username, "block": block } })
+}
+
+pub fn inbox_error(username: &str, reason: &str) -> Value {
+    json!({ "t": "inbox_error", "data": { "username": username, "reason": reason } })
+}
+
+pub fn friend_online(username: &str) -> Value {
+    json!({ "t": "friend_online", "data": { "username": username } })
+}
+
+pub fn friend_playing(username: &str, game_id: &str, path: &str) -> Value {
+    json!({ "t": "friend_playing", "data": { "username": username, "game_id": game_id, "path": path } })
+}
+
+pub fn friends_full(friends: Vec<FriendStatus>) -> Value {
+    json!({ "t": "friends_full", "data": { "friends": friends } })
+}
+
+pub fn follow(username: &str, follow: bool) -> Value {
+    json!({ "t": "follow", "data": { "username": username, "follow": follow } })
+}
+
+pub fn notification(n: &Notification, read: bool) -> Value {
+    json!({ "t": "notification", "data": {
+        "id": &n._id,
+        "kind": n.kind,
+        "data": &n.data,
+        "read": read,
+        "created_at": n.created_at.timestamp_millis()
+    }})
+}
+
+pub fn notifications_unread(count: u64) -> Value {
+    json!({ "t": "notifications_unread", "data": { "count": count } })
+}
+
+/// Reply for feature that needs MongoDB when in-memory storage is used.
+pub fn unsupported() -> Value {
+    json!({ "exist": false, "reason": "unsupported" })
+}
+
+pub fn leaderboard_update(key: &str, players: &[LeaderboardEntry]) -> Value {
+    json!({ "t": "leaderboard_update", "data": { "key": key, "players": players } })
+}
+
+pub fn leaderboard_full(all: HashMap<String, Vec<LeaderboardEntry>>) -> Value {
+    json!({ "t": "leaderboard_full", "data": { "leaderboards": all } })
+}
+
+pub fn puzzle(p: &Puzzle) -> Value {
+    json!({ "t": "puzzle", "data": {
+        "id": &p._id,
+        "game_id": &p.game_id,
+        "variant": &p.variant,
+        "sfen": &p.sfen,
+        "rating": p.rating.rating.round(),
+        "plays": p.plays
+    }})
+}
+
+pub fn puzzle_move(
+    id: &str,
+    correct: bool,
+    reply: Option<&String>,
+    done: bool,
+    rating: Option<f64>,
+) -> Value {
+    json!({ "t": "puzzle_move", "data": {
+        "id": id,
+        "correct": correct,
+        "reply": reply,
+        "done": done,
+        "rating": rating
+    }})
+}
+
+pub fn analysis_state(
+    game: &ShuuroGame,
+    outcome: Option<String>,
+    legal_moves: &HashMap<String, Vec<String>>,
+    can_undo: bool,
+    can_redo: bool,
+) -> Value {
+    json!({ "t": "analysis_state", "data": {
+        "variant": &game.variant,
+        "stage": game.current_stage,
+        "sfen": &game.sfen,
+        "hands": &game.hands,
+        "side_to_move": game.side_to_move,
+        "status": game.status,
+        "result": &game.result,
+        "outcome": outcome,
+        "legal_moves": legal_moves,
+        "can_undo": can_undo,
+        "can_redo": can_redo
+    }})
+}
+
 pub fn fmt_chat(id: &String, chat: Vec<ChatMsg>) -> Value {
     serde_json::json!({"t": "live_chat_full","data":{ "id": &id, "lines": chat }})
 }
@@ -24,6 +173,11 @@ pub fn fmt_count(id: &str, cnt: usize) -> Value {
     serde_json::json!({"t": id, "data": { "id": id, "cnt": cnt } })
 }
 
+/// Too many messages of type `t`. Socket is closed after `closing`.
+pub fn rate_limited(t: &str, closing: bool) -> Value {
+    json!({ "t": "rate_limited", "data": { "type": t, "closing": closing } })
+}
+
 pub fn home_lobby_game(t: &str, game_request: &GameRequest) -> Value {
     serde_json::json!({"t": t, "data": game_request })
 }
@@ -32,8 +186,24 @@ pub fn home_lobby_full(all: Vec<GameRequest>) -> Value {
     json!({ "t": "home_lobby_full", "data" : { "lobbyGames": all }})
 }
 
-pub fn live_game_start(game: &ShuuroGame) -> Value {
-    serde_json::json!({"t": "live_game_start", "data": { "game_id": &game._id, "game_info": &game} })
+/// Accepted seek didn't become game.
+pub fn home_lobby_error(reason: &str) -> Value {
+    json!({ "t": "home_lobby_error", "data": { "reason": reason } })
+}
+
+/// Game for `username`. Only player gets own private ID.
+pub fn live_game_start(game: &ShuuroGame, username: &str) -> Value {
+    let mut info = serde_json::to_value(game).unwrap_or_default();
+    if let Some(info) = info.as_object_mut() {
+        info.remove("player_ids");
+    }
+    let player_id = game
+        .players
+        .iter()
+        .position(|p| p == username)
+        .map(|i| &game.player_ids[i])
+        .filter(|id| !id.is_empty());
+    json!({ "t": "live_game_start", "data": { "game_id": &game._id, "game_info": info, "player_id": player_id } })
 }
 
 pub fn live_game_hand(hand: &str) -> Value {
@@ -52,7 +222,7 @@ pub fn set_deploy(id: &str, hand: &str, game: &ShuuroGame) -> Value {
     serde_json::json!({"t": "redirect_deploy", "data": {
         "path": format!("/shuuro/{id}-1"),
         "hand": hand,
-        "last_clock": Utc::now(),
+        "last_clock": game.tc.clock.now(),
         "side_to_move": "w",
         "w": String::from( &game.players[0]),
         "b": String::from( &game.players[1]),
@@ -152,3 +322,12 @@ pub fn live_game_lot(game_id: &str, status: i32, result: &str) -> Value {
 pub fn live_game_end(game_id: &str) -> Value {
     serde_json::json!({"t": "live_game_end", "data": { "game_id": String::from(game_id)}})
 }
+
+pub fn legal_hints(req: &HintsReq, moves: Option<Vec<String>>) -> Value {
+    json!({ "t": "legal_hints", "data": {
+        "game_id": &req.game_id,
+        "square": &req.square,
+        "exist": moves.is_some(),
+        "moves": moves.unwrap_or_default()
+    }})
+}
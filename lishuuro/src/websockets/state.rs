@@ -1,50 +1,82 @@
 use std::collections::HashMap;
 
-use crate::database::{mongo::ShuuroGame, queries::unfinished};
+use crate::{config::Config, database::Database};
 
 use super::{
+    analysis::AnalysisBoards,
+    clock::SharedClock,
     games::ShuuroGames,
+    leaderboards::Leaderboards,
+    rate_limit::RateLimiter,
     rooms::{ChatRooms, Players},
-    ClientMessage, GameReqs,
+    scheduler::ClockScheduler,
+    ClientMessage, GameGet, GameReqs,
 };
-use mongodb::Collection;
+use serde::Serialize;
 use tokio::sync::broadcast;
 
+/// Current status for followed player.
+#[derive(Clone, Serialize)]
+pub struct FriendStatus {
+    pub username: String,
+    pub online: bool,
+    pub playing: bool,
+    pub path: Option<String>,
+}
+
 /// This struct contains all data.
 pub struct WsState {
     pub players: Players,
     pub chat: ChatRooms,
     pub game_reqs: GameReqs,
     pub shuuro_games: ShuuroGames,
+    pub leaderboards: Leaderboards,
+    pub analysis: AnalysisBoards,
     pub tx: broadcast::Sender<ClientMessage>,
+    pub broadcast_capacity: usize,
+    /// Clock shared by all live games.
+    pub clock: SharedClock,
+    /// Deadlines for all live games.
+    pub scheduler: ClockScheduler,
+    pub rate_limits: RateLimiter,
 }
 
-impl Default for WsState {
-    fn default() -> Self {
+impl WsState {
+    pub fn new(config: &Config, clock: SharedClock) -> Self {
         let players = Players::default();
-        let chat = ChatRooms::default();
+        let chat = ChatRooms::new(&config.chat, clock.clone());
         let game_reqs = GameReqs::default();
-        let (tx, _rx) = broadcast::channel(100);
+        let (tx, _rx) = broadcast::channel(config.broadcast_capacity);
+        let leaderboards = Leaderboards::new(tx.clone());
         Self {
             players,
             chat,
             game_reqs,
+            leaderboards,
+            analysis: AnalysisBoards::default(),
             tx,
             shuuro_games: ShuuroGames::default(),
+            broadcast_capacity: config.broadcast_capacity,
+            clock,
+            scheduler: ClockScheduler::default(),
+            rate_limits: RateLimiter::new(&config.rate_limit),
         }
     }
-}
-
-impl WsState {
-    /// Load all games that are not finished.
-    pub async fn load_unfinished(&self, db: &Collection<ShuuroGame>) {
-        let unfinished = unfinished(db).await;
+    /// Load all games that are not finished, with their chat.
+    pub async fn load_unfinished(&self, db: &Database) {
+        let unfinished = db.games.unfinished().await;
+        let ids = unfinished.keys().collect();
+        let mut chats = db.chat.unfinished_chats(ids).await;
         let mut games8 = HashMap::new();
         let mut games12 = HashMap::new();
+        let mut clocks = vec![];
         self.players.add_spectators(&unfinished);
-        for game in unfinished {
+        for mut game in unfinished {
+            game.1.tc.set_clock(&self.clock);
+            clocks.push(GameGet::new(&game.0, &game.1.variant));
             self.players.add_players(&game.1.players);
-            self.chat.add_chat(&game.0);
+            let lines = chats.remove(&game.0).unwrap_or_default();
+            self.chat.set_chat(&game.0, lines);
             if game.1.variant.contains("shuuro") {
                 games12.insert(game.0, game.1);
             } else {
@@ -53,5 +85,39 @@ impl WsState {
         }
         let unfinished = vec![games8, games12];
         self.shuuro_games.load_unfinished(unfinished);
+        for json in clocks {
+            let deadline = self.shuuro_games.deadline(&json);
+            self.scheduler.schedule(&json, deadline);
+        }
     }
+
+    /// Load leaderboards for all variants and speeds.
+    pub async fn load_leaderboards(&self, db: &Database) {
+        let since = Leaderboards::active_since();
+        for key in Leaderboards::keys() {
+            let players = db.players.get_leaderboard(&key, since).await;
+            self.leaderboards.load(&key, players);
+        }
+    }
+
+    /// Get status for all followed players.
+    pub fn friends_status(&self, following: &[String]) -> Vec<FriendStatus> {
+        following
+            .iter()
+            .map(|username| {
+                let game = self.shuuro_games.player_game(username);
+                FriendStatus {
+                    username: String::from(username),
+                    online: self.players.is_online(username),
+                    playing: self.players.is_playing(username),
+                    path: game.map(|g| game_path(&g.0, g.1)),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Frontend path for live game.
+pub fn game_path(id: &str, stage: u8) -> String {
+    format!("/shuuro/{id}-{stage}")
 }
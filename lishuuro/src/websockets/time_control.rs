@@ -1,9 +1,11 @@
-use chrono::{DateTime, Duration, FixedOffset, Utc};
+use chrono::{DateTime, Duration, FixedOffset};
 use serde::{Deserialize, Serialize};
 
 use crate::database::mongo::ShuuroGame;
 use crate::database::serde_helpers::{array_i32_duration, duration_i32_array};
 
+use super::clock::{system_clock, SharedClock};
+
 /// TimeControl for ShuuroGame.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TimeControl {
@@ -17,6 +19,8 @@ pub struct TimeControl {
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
     pub incr: i64,
+    #[serde(skip, default = "system_clock")]
+    pub clock: SharedClock,
 }
 
 impl Default for TimeControl {
@@ -34,6 +38,7 @@ impl From<&ShuuroGame> for TimeControl {
             clocks: s.clocks,
             stage: s.current_stage,
             incr: s.incr.num_seconds(),
+            clock: system_clock(),
         }
     }
 }
@@ -42,20 +47,38 @@ impl TimeControl {
     /// Create new time control.
     pub fn new(time: i64, incr: i64) -> Self {
         let duration = Duration::seconds(time * 60 + incr);
-        let last_click = Utc::now().into();
+        let clock = system_clock();
 
         Self {
             clocks: [duration, duration],
             stage: 0,
             incr,
-            last_click,
+            last_click: clock.now().into(),
+            clock,
         }
     }
 
+    /// Use shared clock for new game and start counting from it.
+    pub fn start_clock(&mut self, clock: &SharedClock) {
+        self.clock = clock.clone();
+        self.last_click = self.now();
+    }
+
+    /// Use shared clock for loaded game. Last click is kept,
+    /// so time while server was down still counts.
+    pub fn set_clock(&mut self, clock: &SharedClock) {
+        self.clock = clock.clone();
+    }
+
+    /// Current time from clock.
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        self.clock.now().into()
+    }
+
     /// Update to current stage.
     pub fn update_stage(&mut self, stage: u8) {
         self.stage = stage;
-        self.last_click = Utc::now().into();
+        self.last_click = self.now();
     }
 
     /// Click on clock. For shop both can click.
@@ -83,10 +106,15 @@ impl TimeControl {
         None
     }
 
+    /// Time when `current_duration` for selected color runs out.
+    /// Player has one more second after clock shows zero.
+    pub fn deadline(&self, color: usize) -> DateTime<FixedOffset> {
+        self.last_click + self.clocks[color] + Duration::seconds(1)
+    }
+
     /// Elapsed time since last click.
     fn elapsed(&self) -> Duration {
-        let now: DateTime<FixedOffset> = Utc::now().into();
-        now - self.last_click
+        self.now() - self.last_click
     }
 
     /// Update last click.
@@ -96,7 +124,7 @@ impl TimeControl {
         }
         let duration = current.checked_add(&self.incr()).unwrap();
         self.clocks[color] = duration;
-        self.last_click = Utc::now().into();
+        self.last_click = self.now();
     }
 
     /// Get incr in Duration.
@@ -112,7 +140,6 @@ pub struct TimeCheck {
     pub lost: usize,
     pub both_lost: bool,
     pub id: String,
-    pub exist: bool,
 }
 
 impl TimeCheck {
@@ -122,7 +149,6 @@ impl TimeCheck {
             lost: 0,
             both_lost: false,
             id: String::from(id),
-            exist: true,
         }
     }
     pub fn finished(&mut self) {
@@ -138,8 +164,4 @@ impl TimeCheck {
         self.lost = index;
         self.finished();
     }
-
-    pub fn dont_exist(&mut self) {
-        self.exist = false;
-    }
 }